@@ -1,17 +1,41 @@
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 use async_bincode::AsyncBincodeStream;
 use serde::{Deserialize, Serialize};
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::TcpStream;
 use tokio_tower::multiplex;
 use tokio_tower::multiplex::Server;
 use tower::Service;
 
+use crate::config::CONFIG;
 use crate::error::{AgentError, Result};
-use crate::service::{RequestPayload, ResponsePayload, ResponseResult};
+use crate::parser::PARSER_VERSION;
+use crate::service::{
+    ActivityHistory, CommandPolicy, DashboardCache, ImageCache, Metrics, RecentErrors, RequestPayload,
+    ResponsePayload, ResponseResult, Subscriptions,
+};
 use crate::SessionStorage;
 
+pub use control::ControlCommand;
+use drain::Drain;
+use frame::FrameLimited;
+pub use limit::{BusyPolicy, DispatchLimit};
+pub use reconnect::ReconnectPolicy;
+pub use status::{ConnectionEvent, ConnectionState, StateReporter};
+
+mod control;
+mod drain;
+mod frame;
+mod limit;
+mod reconnect;
+mod state;
+mod status;
+
 #[derive(Debug, Deserialize)]
 struct RequestFrame {
     payload: RequestPayload,
@@ -20,21 +44,51 @@ struct RequestFrame {
 #[derive(Debug, Serialize)]
 struct ResponseFrame {
     payload: ResponseResult,
+    /// Version of parsers producing the payload.
+    parser_version: u32,
 }
 
-impl Default for ResponseFrame {
-    fn default() -> Self {
+impl ResponseFrame {
+    fn new(payload: ResponseResult) -> Self {
         ResponseFrame {
-            payload: Ok(ResponsePayload::None),
+            payload,
+            parser_version: PARSER_VERSION,
         }
     }
 }
 
+impl Default for ResponseFrame {
+    fn default() -> Self {
+        ResponseFrame::new(Ok(ResponsePayload::None))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SharedData {
     pub node: String,
     pub client: reqwest::Client,
     pub session_store: SessionStorage,
+    pub activity_history: ActivityHistory,
+    pub command_policy: Arc<CommandPolicy>,
+    pub metrics: Arc<Metrics>,
+    pub subscriptions: Arc<Subscriptions>,
+    pub dashboard_cache: Arc<DashboardCache>,
+    pub recent_errors: Arc<RecentErrors>,
+    pub image_cache: Arc<ImageCache>,
+    pub dispatch_limit: DispatchLimit,
+}
+
+/// Dispatch requests. Requests over `server.max_concurrency` wait or are refused, while control
+/// commands of the server are not limited.
+async fn process_message(frame: RequestFrame, data: SharedData) -> ResponseFrame {
+    let _permit = match frame.payload {
+        RequestPayload::Control(_) => None,
+        _ => match data.dispatch_limit.acquire().await {
+            Ok(permit) => permit,
+            Err(e) => return ResponseFrame::new(Err(e.into())),
+        },
+    };
+    ResponseFrame::new(frame.payload.dispatch(data).await)
 }
 
 #[derive(Debug, Default)]
@@ -62,6 +116,7 @@ where
     T: core::fmt::Debug,
 {
     pub v: T,
+    /// Correlation id assigned by the server, and put back in the response as is.
     pub tag: u32,
 }
 
@@ -93,10 +148,7 @@ impl Service<Tagged<RequestFrame>> for KiteService {
             let tag = req.tag;
             println!("Received frame: {:?}, tag = {}", &req.v, tag);
 
-            let request_frame = req.v;
-            let response_frame = ResponseFrame {
-                payload: request_frame.payload.dispatch(data).await,
-            };
+            let response_frame = process_message(req.v, data).await;
             let mut response = Tagged::<ResponseFrame>::from(response_frame);
 
             response.tag = tag;
@@ -107,22 +159,220 @@ impl Service<Tagged<RequestFrame>> for KiteService {
     }
 }
 
-pub async fn run(server_address: String, shared_data: SharedData) -> Result<()> {
-    println!("Connecting to server: {}", server_address);
+/// Probe the idle connection every `interval` by TCP keepalive, so that NAT on the way keeps the flow.
+/// If probes are not answered within `timeout`, the connection is dropped, and the agent reconnects.
+///
+/// Keepalive stands in for an application-level ping: the agent only answers frames of the server on
+/// this connection, and has no frame of its own to send. The first probe goes out after `interval` of
+/// idle, so a dead peer is found in about `interval + timeout`. The number of probes is only set on
+/// Linux, and other platforms keep their default count, so `timeout` is not honored there.
+fn set_heartbeat(socket: &TcpStream, interval: Duration, timeout: Duration) -> std::io::Result<()> {
+    let keepalive = TcpKeepalive::new().with_time(interval).with_interval(interval);
+    #[cfg(target_os = "linux")]
+    let keepalive = {
+        let retries = (timeout.as_secs() / interval.as_secs().max(1)).max(1) as u32;
+        keepalive.with_retries(retries)
+    };
+    #[cfg(not(target_os = "linux"))]
+    let _ = timeout;
+
+    SockRef::from(socket).set_tcp_keepalive(&keepalive)
+}
+
+/// Serve requests from the server, until disconnected or `shutdown` resolves. On shutdown, requests
+/// in process are still answered before the connection is closed. State transitions are reported
+/// by `reporter`.
+pub async fn run(
+    server_address: String,
+    shared_data: SharedData,
+    shutdown: impl Future<Output = ()> + 'static,
+    reporter: &StateReporter,
+) -> Result<()> {
+    reporter.report(ConnectionState::Connecting);
+    let result = serve(server_address, shared_data, shutdown, reporter).await;
+
+    let reason = result.as_ref().err().map(|e| e.to_string());
+    reporter.report(ConnectionState::Disconnected { reason });
+    result
+}
+
+async fn serve(
+    server_address: String,
+    shared_data: SharedData,
+    shutdown: impl Future<Output = ()> + 'static,
+    reporter: &StateReporter,
+) -> Result<()> {
     // Create a socket and connect to server.
-    let socket = tokio::net::TcpStream::connect(server_address)
+    let socket = TcpStream::connect(server_address)
         .await
-        .map_err(|_| AgentError::ConnectionFailure)?;
+        .map_err(|e| AgentError::ConnectionFailure(e.to_string()))?;
+    if CONFIG.server.heartbeat_interval > 0 {
+        let interval = Duration::from_secs(CONFIG.server.heartbeat_interval);
+        let timeout = Duration::from_secs(CONFIG.server.heartbeat_timeout);
+        set_heartbeat(&socket, interval, timeout)?;
+    }
 
-    println!("Connected.");
+    reporter.report(ConnectionState::Connected);
 
+    let stream = FrameLimited::new(socket, CONFIG.server.max_frame);
     Server::new(
-        AsyncBincodeStream::from(socket).for_async(),
+        Drain::new(AsyncBincodeStream::from(stream).for_async(), shutdown),
         KiteService { shared_data },
     )
     .await
     .map_err(|e| AgentError::Service(e.to_string()))?;
 
-    println!("Disconnected.");
     Ok(())
 }
+
+#[cfg(test)]
+pub(crate) fn new_shared_data() -> SharedData {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let activity_history = ActivityHistory::new(db.open_tree(crate::service::HISTORY_TREE).unwrap());
+    let session_store = SessionStorage::open(db).unwrap();
+
+    SharedData {
+        node: String::from("test"),
+        client: reqwest::Client::new(),
+        session_store,
+        activity_history,
+        command_policy: Arc::new(CommandPolicy::default()),
+        metrics: Arc::new(Metrics::default()),
+        subscriptions: Arc::new(Subscriptions::default()),
+        dashboard_cache: Arc::new(DashboardCache::default()),
+        recent_errors: Arc::new(RecentErrors::new(10)),
+        image_cache: Arc::new(ImageCache::new(16)),
+        dispatch_limit: DispatchLimit::new(0, BusyPolicy::Queue),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use socket2::SockRef;
+    use tokio::net::{TcpListener, TcpStream};
+    use tower::Service;
+
+    use super::{
+        new_shared_data, process_message, set_heartbeat, ControlCommand, KiteService, RequestFrame,
+        ResponseFrame, Tagged,
+    };
+    use crate::config::CommandConfig;
+    use crate::parser::{Parse, ScScoreSummary, PARSER_VERSION};
+    use crate::service::{watch_config, CommandPolicy, RequestPayload, ResponsePayload, ScDashboard};
+
+    #[test]
+    fn test_parser_version() {
+        let frame = ResponseFrame::new(Ok(ResponsePayload::Pong(String::from("上应小风筝"))));
+        assert_eq!(frame.parser_version, PARSER_VERSION);
+
+        // The version is the last field of the frame.
+        let body = bincode::serialize(&frame).unwrap();
+        assert!(body.ends_with(&PARSER_VERSION.to_le_bytes()));
+    }
+
+    #[tokio::test]
+    async fn test_response_tag() {
+        let mut data = new_shared_data();
+        let config = CommandConfig {
+            allow: None,
+            deny: vec![String::from("Ping")],
+            watch_interval: 0,
+        };
+        data.command_policy = Arc::new(CommandPolicy::new(&config));
+        let mut service = KiteService { shared_data: data };
+
+        // The tag of the request is put back in the response, which is an error here.
+        let frame = RequestFrame {
+            payload: RequestPayload::Ping(String::from("ping")),
+        };
+        let response = service.call(Tagged { v: frame, tag: 7 }).await.unwrap();
+
+        assert_eq!(response.tag, 7);
+        assert_eq!(response.v.payload.unwrap_err().code, 57);
+    }
+
+    #[tokio::test]
+    async fn test_clear_cache() {
+        let data = new_shared_data();
+        let html_page = std::fs::read_to_string("html/第二课堂得分页面.html").unwrap();
+        let ttl = Duration::from_secs(600);
+        data.dashboard_cache
+            .get_or_compose("1910000000", "password", ttl, || async {
+                Ok(ScDashboard {
+                    summary: ScScoreSummary::from_html(&html_page)?,
+                    remaining: 0.0,
+                    recent: Vec::new(),
+                    updated_at: chrono::Local::now(),
+                })
+            })
+            .await
+            .unwrap();
+        assert!(!data.dashboard_cache.is_empty());
+
+        let frame = RequestFrame {
+            payload: RequestPayload::Control(ControlCommand::ClearCache),
+        };
+        let response = process_message(frame, data.clone()).await;
+
+        assert!(matches!(response.payload, Ok(ResponsePayload::None)));
+        assert!(data.dashboard_cache.is_empty());
+        assert!(data.image_cache.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socket = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+
+        set_heartbeat(&socket, Duration::from_secs(30), Duration::from_secs(90)).unwrap();
+
+        let socket = SockRef::from(&socket);
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(30));
+        assert_eq!(socket.keepalive_interval().unwrap(), Duration::from_secs(30));
+        #[cfg(target_os = "linux")]
+        assert_eq!(socket.keepalive_retries().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_reload_command_policy() {
+        let file_name = format!("kite-agent-test-policy-{}.toml", uuid::Uuid::new_v4());
+        let path = std::env::temp_dir().join(file_name);
+        let path = path.to_str().unwrap().to_string();
+        let config = "[agent]\nname = \"test\"\ndb = \"kite-cache\"\n\
+            [server]\naddr = \"localhost:8000\"\nconn = 1\n";
+        std::fs::write(&path, config).unwrap();
+
+        let data = new_shared_data();
+        let watcher = tokio::spawn(watch_config(
+            data.command_policy.clone(),
+            path.clone(),
+            Duration::from_millis(10),
+        ));
+        let ping = || RequestPayload::Ping(String::from("ping"));
+        assert!(ping().dispatch(data.clone()).await.is_ok());
+
+        // Let the watcher take the file as it is before modifying.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        std::fs::write(&path, format!("{}[command]\ndeny = [\"Ping\"]\n", config)).unwrap();
+
+        let denied = async {
+            loop {
+                match ping().dispatch(data.clone()).await {
+                    Err(response) => return response,
+                    Ok(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        };
+        let response = tokio::time::timeout(Duration::from_secs(5), denied)
+            .await
+            .unwrap();
+        assert_eq!(response.code, 57);
+
+        watcher.abort();
+        let _ = std::fs::remove_file(&path);
+    }
+}
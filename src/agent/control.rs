@@ -0,0 +1,62 @@
+//! Control commands pushed by the server, which are not requests of clients.
+
+use serde::Deserialize;
+
+use crate::config::reload_config;
+use crate::error::{AgentError, Result};
+use crate::service::{DoRequest, ResponsePayload, ResponseResult};
+
+use super::SharedData;
+
+#[derive(Debug, Deserialize)]
+pub enum ControlCommand {
+    /// Login again with each session in the pool.
+    RefreshSessions,
+    /// Drop dashboards and images cached.
+    ClearCache,
+    /// Read the config file again, and apply its command policy. Other settings take effect on restart.
+    ReloadCommandPolicy,
+}
+
+impl ControlCommand {
+    pub async fn handle(self, data: SharedData) -> Result<()> {
+        match self {
+            ControlCommand::RefreshSessions => refresh_sessions(data).await,
+            ControlCommand::ClearCache => {
+                data.dashboard_cache.clear();
+                data.image_cache.clear();
+                Ok(())
+            }
+            ControlCommand::ReloadCommandPolicy => {
+                let config = reload_config().map_err(|e| AgentError::BadConfig(e.to_string()))?;
+                data.command_policy.reload(&config.command);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ControlCommand {
+    /// Answer with an empty payload once handled. A failed command is answered with the error, so that
+    /// the server knows it is not applied.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        if let Err(e) = self.handle(data).await {
+            println!("Failed to handle control command: {:?}", e);
+            return Err(e.into());
+        }
+        Ok(ResponsePayload::None)
+    }
+}
+
+/// Login with each session, and keep going if some fails.
+async fn refresh_sessions(mut data: SharedData) -> Result<()> {
+    for mut session in data.session_store.all()? {
+        let client = data.session_store.client_of(&session.account, &data.client);
+        match session.login(client).await {
+            Ok(_) => data.session_store.insert(&session)?,
+            Err(e) => println!("Failed to refresh session of {}: {:?}", session.account, e),
+        }
+    }
+    Ok(())
+}
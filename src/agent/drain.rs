@@ -0,0 +1,147 @@
+//! Graceful shutdown of the connection to kite-server.
+//!
+//! `multiplex::Server` stops reading once the request stream ends, and returns only after all the
+//! pending requests are answered and the responses are flushed. `Drain` ends the request stream as
+//! soon as shutdown is signaled, so that no response queued at that time is lost.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::{Sink, Stream};
+
+pub struct Drain<T> {
+    inner: T,
+    /// Resolves when the agent is shutting down.
+    shutdown: Pin<Box<dyn Future<Output = ()>>>,
+    /// Whether the request stream has been ended.
+    closed: bool,
+}
+
+impl<T> Drain<T> {
+    pub fn new(inner: T, shutdown: impl Future<Output = ()> + 'static) -> Self {
+        Self {
+            inner,
+            shutdown: Box::pin(shutdown),
+            closed: false,
+        }
+    }
+}
+
+impl<T: Stream + Unpin> Stream for Drain<T> {
+    type Item = T::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.closed {
+            return Poll::Ready(None);
+        }
+        // Check shutdown first, requests arrived later are not accepted.
+        if self.shutdown.as_mut().poll(cx).is_ready() {
+            self.closed = true;
+            return Poll::Ready(None);
+        }
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+impl<T: Sink<I> + Unpin, I> Sink<I> for Drain<T> {
+    type Error = T::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: I) -> Result<(), Self::Error> {
+        Pin::new(&mut self.inner).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use async_bincode::AsyncBincodeStream;
+    use futures::{SinkExt, StreamExt};
+    use tokio::sync::{mpsc, oneshot};
+    use tokio_tower::multiplex::Server;
+    use tower::Service;
+
+    use super::Drain;
+    use crate::agent::Tagged;
+
+    /// Answer each request after a while, and report requests received.
+    struct SlowEcho {
+        received: mpsc::UnboundedSender<u32>,
+    }
+
+    impl Service<Tagged<u32>> for SlowEcho {
+        type Response = Tagged<u32>;
+        type Error = anyhow::Error;
+        type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Tagged<u32>) -> Self::Future {
+            self.received.send(req.v).unwrap();
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok(req)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drain_on_shutdown() {
+        let (host, agent) = tokio::io::duplex(1024);
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let (received_tx, mut received_rx) = mpsc::unbounded_channel();
+
+        let transport: AsyncBincodeStream<_, Tagged<u32>, Tagged<u32>, _> =
+            AsyncBincodeStream::from(agent).for_async();
+        let server = Server::new(
+            Drain::new(transport, async move {
+                let _ = shutdown_rx.await;
+            }),
+            SlowEcho {
+                received: received_tx,
+            },
+        );
+
+        let mut host: AsyncBincodeStream<_, Tagged<u32>, Tagged<u32>, _> =
+            AsyncBincodeStream::from(host).for_async();
+        let client = async move {
+            for i in 0..3 {
+                host.send(Tagged { v: i, tag: i }).await.unwrap();
+            }
+            // Shutdown while all the requests are still in process.
+            for _ in 0..3 {
+                received_rx.recv().await.unwrap();
+            }
+            shutdown_tx.send(()).unwrap();
+
+            let mut responses = Vec::new();
+            while let Some(response) = host.next().await {
+                responses.push(response.unwrap().v);
+            }
+            responses
+        };
+        let (result, mut responses) = tokio::join!(server, client);
+        responses.sort_unstable();
+
+        assert!(result.is_ok());
+        assert_eq!(responses, vec![0, 1, 2]);
+    }
+}
@@ -0,0 +1,198 @@
+//! Frame guard over the connection to kite-server.
+//!
+//! Messages are sent as `[u32 length (big endian)][bincode body]`. `AsyncBincodeStream` already
+//! buffers a fragmented frame until the whole body arrives, but it trusts the length prefix and
+//! reserves as much memory as the peer declares. `FrameLimited` watches the length prefixes on the
+//! read side, and fails the connection once a frame larger than the limit is announced.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+#[derive(Debug)]
+pub struct FrameLimited<S> {
+    inner: S,
+    /// Max body size of a single frame.
+    limit: usize,
+    /// Length prefix bytes of the next frame.
+    header: [u8; 4],
+    header_len: usize,
+    /// Bytes of the current frame body not read yet.
+    body_left: usize,
+}
+
+impl<S> FrameLimited<S> {
+    pub fn new(inner: S, limit: usize) -> Self {
+        Self {
+            inner,
+            limit,
+            header: [0u8; 4],
+            header_len: 0,
+            body_left: 0,
+        }
+    }
+
+    /// Walk through the bytes just read, and check each length prefix we meet.
+    fn inspect(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            if self.body_left > 0 {
+                let n = self.body_left.min(data.len());
+                self.body_left -= n;
+                data = &data[n..];
+                continue;
+            }
+            self.header[self.header_len] = data[0];
+            self.header_len += 1;
+            data = &data[1..];
+
+            if self.header_len == self.header.len() {
+                let size = u32::from_be_bytes(self.header) as usize;
+                if size > self.limit {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Frame size {} exceeds server.max_frame {}.", size, self.limit),
+                    ));
+                }
+                self.header_len = 0;
+                self.body_left = size;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for FrameLimited<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+
+        match Pin::new(&mut self.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => Poll::Ready(self.inspect(&buf.filled()[filled..])),
+            other => other,
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for FrameLimited<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use async_bincode::AsyncBincodeStream;
+    use bincode::Options;
+    use futures::StreamExt;
+    use serde::Serialize;
+    use tokio::io::AsyncWriteExt;
+
+    use super::FrameLimited;
+    use crate::agent::{RequestFrame, ResponseFrame, Tagged};
+    use crate::service::RequestPayload;
+
+    /// Mirror of the leading `RequestPayload` variants, used to encode a frame as kite-server does.
+    #[derive(Serialize)]
+    enum Payload {
+        #[allow(dead_code)]
+        None,
+        Ping(String),
+    }
+
+    #[derive(Serialize)]
+    struct Frame {
+        v: Payload,
+        tag: u32,
+    }
+
+    fn encode_frame() -> Vec<u8> {
+        let frame = Frame {
+            v: Payload::Ping(String::from("上应小风筝")),
+            tag: 7,
+        };
+        let body = bincode::options().serialize(&frame).unwrap();
+
+        let mut message = (body.len() as u32).to_be_bytes().to_vec();
+        message.extend_from_slice(&body);
+        message
+    }
+
+    type TestStream = AsyncBincodeStream<
+        FrameLimited<tokio::io::DuplexStream>,
+        Tagged<RequestFrame>,
+        Tagged<ResponseFrame>,
+        async_bincode::AsyncDestination,
+    >;
+
+    #[tokio::test]
+    async fn test_fragmented_frame() {
+        let (mut host, agent) = tokio::io::duplex(64);
+        let mut stream: TestStream =
+            AsyncBincodeStream::from(FrameLimited::new(agent, 1024)).for_async();
+
+        let message = encode_frame();
+        let (first, second) = message.split_at(3);
+        let writer = async move {
+            host.write_all(first).await.unwrap();
+            tokio::task::yield_now().await;
+            host.write_all(second).await.unwrap();
+            host
+        };
+        let (_host, frame) = tokio::join!(writer, stream.next());
+        let frame = frame.unwrap().unwrap();
+
+        assert_eq!(frame.tag, 7);
+        assert!(matches!(frame.v.payload, RequestPayload::Ping(s) if s == "上应小风筝"));
+    }
+
+    #[tokio::test]
+    async fn test_frame_at_limit() {
+        let message = encode_frame();
+        let size = message.len() - 4;
+
+        let (mut host, agent) = tokio::io::duplex(64);
+        let mut stream: TestStream =
+            AsyncBincodeStream::from(FrameLimited::new(agent, size)).for_async();
+        let writer = async move {
+            host.write_all(&message).await.unwrap();
+            host
+        };
+        let (_host, frame) = tokio::join!(writer, stream.next());
+        assert_eq!(frame.unwrap().unwrap().tag, 7);
+
+        // One byte over the limit.
+        let (mut host, agent) = tokio::io::duplex(64);
+        let mut stream: TestStream =
+            AsyncBincodeStream::from(FrameLimited::new(agent, size - 1)).for_async();
+        host.write_all(&encode_frame()).await.unwrap();
+
+        let error = stream.next().await.unwrap().unwrap_err();
+        assert!(error.to_string().contains("exceeds server.max_frame"));
+    }
+
+    #[tokio::test]
+    async fn test_oversize_frame() {
+        let (mut host, agent) = tokio::io::duplex(64);
+        let mut stream: TestStream = AsyncBincodeStream::from(FrameLimited::new(agent, 8)).for_async();
+
+        host.write_all(&encode_frame()).await.unwrap();
+        assert!(stream.next().await.unwrap().is_err());
+    }
+}
@@ -0,0 +1,89 @@
+//! Bound requests dispatched at once, over all the connections to kite-server, so that a burst of
+//! requests does not get the agent rate-limited by the upstream.
+
+use std::sync::Arc;
+
+use serde::Deserialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::service::ActionError;
+
+/// What to do with a request when the limit is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum BusyPolicy {
+    /// Wait until a request in process is done.
+    #[default]
+    Queue,
+    /// Answer with `ActionError::Busy` at once.
+    Reject,
+}
+
+#[derive(Debug, Clone)]
+pub struct DispatchLimit {
+    /// Permits of requests in process, or `None` if not limited.
+    permits: Option<Arc<Semaphore>>,
+    policy: BusyPolicy,
+}
+
+impl DispatchLimit {
+    /// Allow `max` requests in process at once, or any number if zero.
+    pub fn new(max: usize, policy: BusyPolicy) -> Self {
+        Self {
+            permits: Some(max).filter(|&x| x > 0).map(|x| Arc::new(Semaphore::new(x))),
+            policy,
+        }
+    }
+
+    /// Take a permit, which is held while the request is processed.
+    pub async fn acquire(&self) -> Result<Option<OwnedSemaphorePermit>, ActionError> {
+        let permits = match &self.permits {
+            Some(permits) => permits.clone(),
+            None => return Ok(None),
+        };
+        let permit = match self.policy {
+            BusyPolicy::Queue => permits.acquire_owned().await.ok(),
+            BusyPolicy::Reject => permits.try_acquire_owned().ok(),
+        };
+        permit.map(Some).ok_or(ActionError::Busy)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{BusyPolicy, DispatchLimit};
+    use crate::service::ActionError;
+
+    #[tokio::test]
+    async fn test_reject() {
+        let limit = DispatchLimit::new(1, BusyPolicy::Reject);
+
+        let permit = limit.acquire().await.unwrap();
+        assert!(permit.is_some());
+        assert!(matches!(limit.acquire().await, Err(ActionError::Busy)));
+
+        drop(permit);
+        assert!(limit.acquire().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_queue() {
+        let limit = DispatchLimit::new(1, BusyPolicy::Queue);
+
+        let permit = limit.acquire().await.unwrap();
+        let waiting = tokio::time::timeout(Duration::from_millis(20), limit.acquire()).await;
+        assert!(waiting.is_err());
+
+        let limit_cloned = limit.clone();
+        let queued = tokio::spawn(async move { limit_cloned.acquire().await.map(|x| x.is_some()) });
+        drop(permit);
+        assert!(matches!(queued.await.unwrap(), Ok(true)));
+
+        // Not limited.
+        let limit = DispatchLimit::new(0, BusyPolicy::Reject);
+        let permits: Vec<_> = futures::future::join_all((0..8).map(|_| limit.acquire())).await;
+        assert!(permits.iter().all(|x| matches!(x, Ok(None))));
+    }
+}
@@ -0,0 +1,69 @@
+//! Backoff between attempts to reconnect to the server.
+
+use std::time::Duration;
+
+use crate::net::auth::retry_delay;
+
+/// Delay before reconnecting, which doubles on each failure in a row up to `max_delay`, with jitter.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// Base delay, in milliseconds.
+    base_delay: u64,
+    /// Max delay, in milliseconds.
+    max_delay: u64,
+    /// Failures in a row.
+    attempt: u32,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self::new(1000, 60000)
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn new(base_delay: u64, max_delay: u64) -> Self {
+        Self {
+            base_delay,
+            max_delay,
+            attempt: 0,
+        }
+    }
+
+    /// Delay before the next attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = retry_delay(
+            &mut rand::thread_rng(),
+            self.base_delay,
+            self.max_delay,
+            self.attempt,
+        );
+        self.attempt = self.attempt.saturating_add(1);
+        delay
+    }
+
+    /// Start over from the base delay, once connected.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::ReconnectPolicy;
+
+    #[test]
+    fn test_reconnect_policy() {
+        let mut policy = ReconnectPolicy::new(1000, 8000);
+        for ceiling in [1000, 2000, 4000, 8000, 8000, 8000] {
+            assert!(policy.next_delay() < Duration::from_millis(ceiling));
+        }
+        assert_eq!(policy.attempt, 6);
+
+        policy.reset();
+        assert_eq!(policy.attempt, 0);
+        assert!(policy.next_delay() < Duration::from_millis(1000));
+    }
+}
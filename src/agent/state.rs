@@ -0,0 +1,167 @@
+//! Agent state handoff, used for hot reload.
+//!
+//! The old process exports sessions, the activity apply history, and the image and dashboard caches
+//! to a blob, and the new process imports it before serving. Cached dashboards carry passwords, as
+//! sessions do. The blob is `[flag][body]`, where the flag tells whether the body is encrypted with
+//! AES-256-GCM, whose nonce is prepended to the ciphertext and its tag. A body that fails to
+//! authenticate is rejected.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AgentError, Result};
+use crate::net::{decrypt, encrypt, Session};
+use crate::service::{ExportedDashboard, ExportedImage};
+
+use super::SharedData;
+
+const FLAG_PLAIN: u8 = 0;
+const FLAG_ENCRYPTED: u8 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AgentState {
+    sessions: Vec<Session>,
+    /// Raw (key, value) entries of the activity history tree.
+    activity_history: Vec<(Vec<u8>, Vec<u8>)>,
+    images: Vec<ExportedImage>,
+    dashboards: Vec<ExportedDashboard>,
+}
+
+impl SharedData {
+    /// Export sessions and caches, encrypted if a key is given.
+    pub fn export_state(&self, key: Option<&[u8; 32]>) -> Result<Vec<u8>> {
+        let state = AgentState {
+            sessions: self.session_store.all()?,
+            activity_history: self.activity_history.dump()?,
+            images: self.image_cache.dump(),
+            dashboards: self.dashboard_cache.dump(),
+        };
+        let body = bincode::serialize(&state)?;
+
+        let mut blob = Vec::new();
+        if let Some(key) = key {
+            blob.push(FLAG_ENCRYPTED);
+            blob.extend(encrypt(key, &body)?);
+        } else {
+            blob.push(FLAG_PLAIN);
+            blob.extend_from_slice(&body);
+        }
+        Ok(blob)
+    }
+
+    /// Import state exported by another agent process. Existing entries with the same key are
+    /// overwritten.
+    pub fn import_state(&mut self, blob: &[u8], key: Option<&[u8; 32]>) -> Result<()> {
+        let body = match (blob.split_first(), key) {
+            (Some((&FLAG_PLAIN, body)), _) => body.to_vec(),
+            (Some((&FLAG_ENCRYPTED, body)), Some(key)) => {
+                decrypt(key, body).map_err(|_| AgentError::BadState(String::from("无法解密")))?
+            }
+            (Some((&FLAG_ENCRYPTED, _)), None) => {
+                return Err(AgentError::BadState(String::from("需要密钥解密")).into());
+            }
+            _ => return Err(AgentError::BadState(String::from("格式错误")).into()),
+        };
+        let state: AgentState = bincode::deserialize(&body)?;
+
+        for session in &state.sessions {
+            self.session_store.insert(session)?;
+        }
+        self.activity_history.restore(state.activity_history)?;
+        self.image_cache.restore(state.images);
+        self.dashboard_cache.restore(state.dashboards);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use chrono::Local;
+
+    use crate::agent::new_shared_data;
+    use crate::net::fake::{FakeResponse, FakeUpstream};
+    use crate::net::Session;
+    use crate::parser::{ActivityDetail, Parse, ScScoreSummary};
+    use crate::service::ScDashboard;
+
+    const IMAGE_URL: &str = "http://sc.sit.edu.cn/upload/image.gif";
+
+    async fn check_round_trip(key: Option<&[u8; 32]>) {
+        let upstream = FakeUpstream::start(|_| {
+            FakeResponse::ok("GIF8")
+                .header("Content-Type", "image/gif")
+                .header("Cache-Control", "max-age=86400")
+        })
+        .await;
+        let mut client = upstream.user_client();
+
+        let mut old = new_shared_data();
+        let mut session = Session::new("1910000000", "password");
+        let mut cookies = std::collections::HashMap::new();
+        cookies.insert(String::from("JSESSIONID"), String::from("0123456789"));
+        session.cookies.insert(String::from("sc.sit.edu.cn"), cookies);
+        old.session_store.insert(&session).unwrap();
+
+        let html_page = std::fs::read_to_string("html/第二课堂详情页面.html").unwrap();
+        let activity = ActivityDetail::from_html(&html_page).unwrap();
+        old.activity_history.record(&activity).unwrap();
+
+        old.image_cache.fetch(&mut client, IMAGE_URL).await.unwrap();
+
+        let html_page = std::fs::read_to_string("html/第二课堂得分页面.html").unwrap();
+        let dashboard = ScDashboard {
+            summary: ScScoreSummary::from_html(&html_page).unwrap(),
+            remaining: 0.0,
+            recent: Vec::new(),
+            updated_at: Local::now(),
+        };
+        let ttl = Duration::from_secs(600);
+        let cache = &old.dashboard_cache;
+        cache
+            .get_or_compose("1910000000", "password", ttl, || async { Ok(dashboard.clone()) })
+            .await
+            .unwrap();
+
+        let blob = old.export_state(key).unwrap();
+        let mut new = new_shared_data();
+        new.import_state(&blob, key).unwrap();
+
+        assert_eq!(new.session_store.query("1910000000").unwrap(), Some(session));
+        let history = old.activity_history.query(activity.id).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(new.activity_history.query(activity.id).unwrap(), history);
+
+        // Served from the caches imported, without asking upstream or composing again.
+        let image = new.image_cache.fetch(&mut client, IMAGE_URL).await.unwrap();
+        assert_eq!(image, b"GIF8");
+        assert_eq!(upstream.requests().len(), 1);
+        let compose = || async { panic!("Composed again") };
+        let imported = new
+            .dashboard_cache
+            .get_or_compose("1910000000", "password", ttl, compose)
+            .await
+            .unwrap();
+        assert_eq!(imported.summary, dashboard.summary);
+        assert_eq!(imported.updated_at, dashboard.updated_at);
+    }
+
+    #[tokio::test]
+    async fn test_state_round_trip() {
+        check_round_trip(None).await;
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_state_round_trip() {
+        let key = [7u8; 32];
+        check_round_trip(Some(&key)).await;
+
+        let mut blob = new_shared_data().export_state(Some(&key)).unwrap();
+        assert!(new_shared_data().import_state(&blob, None).is_err());
+
+        // Modified on the way.
+        let last = blob.len() - 1;
+        blob[last] ^= 1;
+        assert!(new_shared_data().import_state(&blob, Some(&key)).is_err());
+    }
+}
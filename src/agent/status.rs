@@ -0,0 +1,100 @@
+//! Connection state of each worker, reported to subscribers so that operators can watch whether the
+//! agent is connected.
+
+use std::fmt;
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    /// The connection is closed, or not established. The reason is `None` if closed without error.
+    Disconnected {
+        reason: Option<String>,
+    },
+    /// Waiting before connecting again.
+    Reconnecting {
+        delay: Duration,
+    },
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionState::Connecting => write!(f, "Connecting to server..."),
+            ConnectionState::Connected => write!(f, "Connected."),
+            ConnectionState::Disconnected { reason: None } => write!(f, "Disconnected."),
+            ConnectionState::Disconnected { reason: Some(reason) } => {
+                write!(f, "Disconnected: {}", reason)
+            }
+            ConnectionState::Reconnecting { delay } => {
+                write!(f, "Trying to reconnect in {:?}...", delay)
+            }
+        }
+    }
+}
+
+/// A state transition of the worker numbered `worker`.
+#[derive(Debug, Clone)]
+pub struct ConnectionEvent {
+    pub worker: usize,
+    pub state: ConnectionState,
+}
+
+/// Report state transitions of a worker, which are dropped if nobody subscribes.
+#[derive(Debug, Clone)]
+pub struct StateReporter {
+    worker: usize,
+    sender: broadcast::Sender<ConnectionEvent>,
+}
+
+impl StateReporter {
+    pub fn new(worker: usize, sender: broadcast::Sender<ConnectionEvent>) -> Self {
+        Self { worker, sender }
+    }
+
+    pub fn report(&self, state: ConnectionState) {
+        let _ = self.sender.send(ConnectionEvent {
+            worker: self.worker,
+            state,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::net::TcpListener;
+    use tokio::sync::broadcast;
+
+    use super::{ConnectionState, StateReporter};
+    use crate::agent::{new_shared_data, run};
+
+    #[tokio::test]
+    async fn test_connection_failure() {
+        // Take a free port, and close it.
+        let address = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (sender, mut receiver) = broadcast::channel(4);
+        let reporter = StateReporter::new(3, sender);
+
+        let result = run(address.to_string(), new_shared_data(), async {}, &reporter).await;
+        assert!(result.is_err());
+
+        let event = receiver.recv().await.unwrap();
+        assert_eq!(event.worker, 3);
+        assert_eq!(event.state, ConnectionState::Connecting);
+
+        let event = receiver.recv().await.unwrap();
+        match event.state {
+            ConnectionState::Disconnected { reason: Some(reason) } => {
+                assert!(reason.starts_with("无法连接到 kite-server"))
+            }
+            state => panic!("Unexpected state: {:?}", state),
+        }
+    }
+}
@@ -1,8 +1,13 @@
+use std::collections::HashMap;
 use std::{error::Error, fs};
 
 use serde::Deserialize;
 
-const DEFAULT_CONFIG_PATH: &str = "kite.toml";
+use crate::agent::BusyPolicy;
+use crate::net::HttpVersion;
+use crate::parser::DedupStrategy;
+
+pub(crate) const DEFAULT_CONFIG_PATH: &str = "kite.toml";
 
 lazy_static! {
     /// Global configuration
@@ -18,6 +23,15 @@ pub struct Config {
     pub agent: AgentConfig,
     /// Server related.
     pub server: ServerConfig,
+    /// Request commands enabled or disabled.
+    #[serde(default)]
+    pub command: CommandConfig,
+    /// Second classroom related.
+    #[serde(default)]
+    pub sc: ScConfig,
+    /// SSO login related.
+    #[serde(default)]
+    pub login: LoginConfig,
 }
 
 #[derive(Deserialize)]
@@ -26,6 +40,56 @@ pub struct ServerConfig {
     pub addr: String,
     ///  Max connections to server.
     pub conn: u8,
+    /// Max size of a single frame received from server, in bytes.
+    #[serde(default = "default_max_frame")]
+    pub max_frame: usize,
+    /// Base delay before reconnecting, in milliseconds. It doubles on each failure in a row.
+    #[serde(default = "default_reconnect_base_delay")]
+    pub reconnect_base_delay: u64,
+    /// Max delay before reconnecting, in milliseconds.
+    #[serde(default = "default_reconnect_max_delay")]
+    pub reconnect_max_delay: u64,
+    /// Seconds of idle before probing the connection by TCP keepalive, and between probes. Zero to
+    /// disable.
+    #[serde(default = "default_heartbeat_interval")]
+    pub heartbeat_interval: u64,
+    /// Seconds without answer to probes before the connection is considered dead, counted from the
+    /// first probe. Only honored on Linux, where the count of probes can be set.
+    #[serde(default = "default_heartbeat_timeout")]
+    pub heartbeat_timeout: u64,
+    /// Seconds to wait for requests in process on shutdown, before dropping them.
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    /// Max requests in process at once over all connections. Zero for no limit.
+    #[serde(default)]
+    pub max_concurrency: usize,
+    /// Whether to queue requests over `max_concurrency`, or answer them as busy.
+    #[serde(default)]
+    pub busy_policy: BusyPolicy,
+}
+
+fn default_max_frame() -> usize {
+    4 * 1024 * 1024
+}
+
+fn default_reconnect_base_delay() -> u64 {
+    1000
+}
+
+fn default_reconnect_max_delay() -> u64 {
+    60000
+}
+
+fn default_heartbeat_interval() -> u64 {
+    30
+}
+
+fn default_heartbeat_timeout() -> u64 {
+    90
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
 }
 
 #[derive(Deserialize)]
@@ -36,15 +100,233 @@ pub struct AgentConfig {
     pub db: String,
     /// Proxy string for most connections.
     pub proxy: Option<String>,
+    /// Proxy designated for some accounts, indexed by account, so that accounts egress from different
+    /// addresses. Other accounts use `proxy`.
+    #[serde(default)]
+    pub account_proxies: HashMap<String, String>,
+    /// Compress sessions stored, which saves space for large pools.
+    #[serde(default)]
+    pub compress_sessions: bool,
+    /// HTTP version to talk to upstream, "auto", "1.1" or "2".
+    #[serde(default)]
+    pub http_version: HttpVersion,
+    /// Count of recent errors kept for debugging.
+    #[serde(default = "default_recent_errors")]
+    pub recent_errors: usize,
+    /// Times to send a GET again on connection errors or 5xx statuses. POSTs are never sent again.
+    #[serde(default = "default_send_retries")]
+    pub send_retries: u32,
+    /// Base delay before sending again, in milliseconds. It doubles on each retry, and the actual delay
+    /// is randomized in [0, current delay).
+    #[serde(default = "default_send_retry_base_delay")]
+    pub send_retry_base_delay: u64,
+    /// Max delay before sending again, in milliseconds.
+    #[serde(default = "default_send_retry_max_delay")]
+    pub send_retry_max_delay: u64,
+    /// Seconds a request to upstream may take, from connecting until the body is read.
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout: u64,
+    /// Seconds a stored session lives after its last login or successful use. Expired sessions have
+    /// their cookies cleared, and log in again on use. Zero to keep sessions forever.
+    #[serde(default)]
+    pub session_ttl: u64,
+}
+
+fn default_recent_errors() -> usize {
+    50
+}
+
+fn default_send_retries() -> u32 {
+    2
+}
+
+fn default_send_retry_base_delay() -> u64 {
+    200
+}
+
+fn default_send_retry_max_delay() -> u64 {
+    2000
+}
+
+fn default_request_timeout() -> u64 {
+    30
+}
+
+#[derive(Deserialize)]
+pub struct CommandConfig {
+    /// Commands allowed, named by request payload. All commands are allowed if not set.
+    pub allow: Option<Vec<String>>,
+    /// Commands disabled, e.g. "ScJoin" on a read-only agent.
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// Seconds between checks of the config file, which is reloaded once modified. Zero to disable.
+    #[serde(default = "default_watch_interval")]
+    pub watch_interval: u64,
+}
+
+fn default_watch_interval() -> u64 {
+    5
+}
+
+impl Default for CommandConfig {
+    fn default() -> Self {
+        Self {
+            allow: None,
+            deny: Vec::new(),
+            watch_interval: default_watch_interval(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ScConfig {
+    /// Fetch the activity list again if a blank page is returned.
+    #[serde(default = "default_retry_blank_list")]
+    pub retry_blank_list: bool,
+    /// Delay before fetching again, in milliseconds.
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay: u64,
+    /// How to handle an activity listed under several categories.
+    #[serde(default)]
+    pub dedup: DedupStrategy,
+    /// Parse responses declared as JSON by Content-Type, which some endpoints return in AJAX.
+    #[serde(default = "default_detect_json")]
+    pub detect_json: bool,
+    /// Credits required, to compute the remaining on the dashboard.
+    #[serde(default = "default_required_credits")]
+    pub required_credits: f32,
+    /// Credits required in each category, indexed by category name like "主题报告".
+    #[serde(default)]
+    pub category_required_credits: HashMap<String, f32>,
+    /// Seconds to serve a dashboard from cache before scraping again.
+    #[serde(default = "default_dashboard_ttl")]
+    pub dashboard_ttl: u64,
+    /// Cache activity images as `Cache-Control`, `Expires` and `ETag` of the server say.
+    #[serde(default = "default_image_cache")]
+    pub image_cache: bool,
+    /// Max count of images cached, and the least recently used one is evicted first.
+    #[serde(default = "default_image_cache_size")]
+    pub image_cache_size: usize,
+    /// Times to retry when the busy page is returned.
+    #[serde(default = "default_busy_retries")]
+    pub busy_retries: u32,
+    /// Delay before the first retry on the busy page, in milliseconds. It doubles on each retry.
+    #[serde(default = "default_busy_retry_delay")]
+    pub busy_retry_delay: u64,
+    /// Images of an activity downloaded at once.
+    #[serde(default = "default_image_concurrency")]
+    pub image_concurrency: usize,
+    /// Check parsed values, and attach warnings to the response for implausible ones, or print them to
+    /// stderr if the response has no warnings.
+    #[serde(default)]
+    pub validate: bool,
+    /// Fail the whole list on a malformed row, instead of skipping it with a warning.
+    #[serde(default)]
+    pub strict_parsing: bool,
+}
+
+fn default_retry_blank_list() -> bool {
+    true
+}
+
+fn default_retry_delay() -> u64 {
+    500
+}
+
+fn default_detect_json() -> bool {
+    true
+}
+
+fn default_required_credits() -> f32 {
+    10.0
+}
+
+fn default_dashboard_ttl() -> u64 {
+    600
+}
+
+fn default_image_cache() -> bool {
+    true
+}
+
+fn default_busy_retries() -> u32 {
+    3
+}
+
+fn default_image_cache_size() -> usize {
+    512
+}
+
+fn default_busy_retry_delay() -> u64 {
+    1000
+}
+
+fn default_image_concurrency() -> usize {
+    4
+}
+
+impl Default for ScConfig {
+    fn default() -> Self {
+        Self {
+            retry_blank_list: default_retry_blank_list(),
+            retry_delay: default_retry_delay(),
+            dedup: DedupStrategy::default(),
+            detect_json: default_detect_json(),
+            required_credits: default_required_credits(),
+            category_required_credits: HashMap::new(),
+            dashboard_ttl: default_dashboard_ttl(),
+            image_cache: default_image_cache(),
+            image_cache_size: default_image_cache_size(),
+            busy_retries: default_busy_retries(),
+            busy_retry_delay: default_busy_retry_delay(),
+            image_concurrency: default_image_concurrency(),
+            validate: false,
+            strict_parsing: false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LoginConfig {
+    /// Base delay before retrying login, in milliseconds. It doubles on each retry, and the actual
+    /// delay is randomized in [0, current delay).
+    #[serde(default = "default_retry_base_delay")]
+    pub retry_base_delay: u64,
+    /// Max delay before retrying login, in milliseconds.
+    #[serde(default = "default_retry_max_delay")]
+    pub retry_max_delay: u64,
+}
+
+fn default_retry_base_delay() -> u64 {
+    500
+}
+
+fn default_retry_max_delay() -> u64 {
+    8000
+}
+
+impl Default for LoginConfig {
+    fn default() -> Self {
+        Self {
+            retry_base_delay: default_retry_base_delay(),
+            retry_max_delay: default_retry_max_delay(),
+        }
+    }
 }
 
 /// Load the global configuration from DEFAULT_CONFIG_PATH on the startup.
-fn load_config(path: &str) -> Result<Config, Box<dyn Error>> {
+pub(crate) fn load_config(path: &str) -> Result<Config, Box<dyn Error>> {
     let text = fs::read_to_string(path)?;
     let toml = toml::from_str(&text)?;
 
     Ok(toml)
 }
 
+/// Read the config file again. `CONFIG` is loaded once, so only settings applied at runtime, such
+/// as command policy, take effect without restarting.
+pub fn reload_config() -> Result<Config, Box<dyn Error>> {
+    load_config(DEFAULT_CONFIG_PATH)
+}
+
 pub(crate) const USERAGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/87.0.4280.88 ' \
 'Safari/537.36 Edg/87.0.664.66";
@@ -3,10 +3,22 @@ pub type Result<T> = std::result::Result<T, anyhow::Error>;
 #[derive(Debug, thiserror::Error)]
 #[error("代理错误: {}", 0)]
 pub enum AgentError {
-    #[error("无法连接到 kite-server")]
-    ConnectionFailure,
+    #[error("无法连接到 kite-server: {0}")]
+    ConnectionFailure(String),
     #[error("服务错误: {0}")]
     Service(String),
+    #[error("无法导入状态: {0}")]
+    BadState(String),
+    #[error("无法解压会话数据")]
+    BadSession,
+    /// Sessions fail to encrypt or decrypt, as the key is wrong or the value is modified.
+    #[error("无法加解密会话数据，请检查密钥")]
+    SessionCrypto,
+    /// Sessions stored encrypted, but no key is given by the environment variable.
+    #[error("会话数据已加密，请设置 {0}")]
+    SessionKeyRequired(&'static str),
+    #[error("无法加载配置: {0}")]
+    BadConfig(String),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -2,14 +2,22 @@
 
 #[macro_use]
 extern crate lazy_static;
-#[macro_use]
-extern crate num_derive;
 
-use tokio::time::Duration;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{broadcast, watch};
 
-use agent::{run, SharedData};
-use config::CONFIG;
-use net::SessionStorage;
+use agent::{
+    run, ConnectionEvent, ConnectionState, DispatchLimit, ReconnectPolicy, SharedData, StateReporter,
+};
+use config::{CONFIG, DEFAULT_CONFIG_PATH};
+use error::AgentError;
+use net::{AccountProxies, SessionStorage};
+use service::{
+    watch_config, ActivityHistory, CommandPolicy, DashboardCache, ImageCache, Metrics, RecentErrors,
+    Subscriptions, HISTORY_TREE,
+};
 
 mod agent;
 mod config;
@@ -18,20 +26,24 @@ mod net;
 mod parser;
 pub mod service;
 
-fn worker_thread(storage: SessionStorage, client: reqwest::Client) {
+fn worker_thread(shared_data: SharedData, shutdown: watch::Receiver<bool>, reporter: StateReporter) {
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .expect("Fail to create runtime.");
+    let mut policy = ReconnectPolicy::new(
+        CONFIG.server.reconnect_base_delay,
+        CONFIG.server.reconnect_max_delay,
+    );
 
-    loop {
-        let storage = storage.clone();
-        let client = client.clone();
+    while !*shutdown.borrow() {
+        let shared_data = shared_data.clone();
+        let mut signal = shutdown.clone();
+        let reporter_cloned = reporter.clone();
 
         // Run on current thread.
-        runtime.block_on(async move {
+        let result = runtime.block_on(async move {
             let remote_server = &CONFIG.server.addr;
-            let node_name = &CONFIG.agent.name;
 
             let local = tokio::task::LocalSet::new();
 
@@ -39,30 +51,93 @@ fn worker_thread(storage: SessionStorage, client: reqwest::Client) {
             local
                 .run_until(async move {
                     tokio::task::spawn_local(async move {
-                        run(
-                            remote_server.clone(),
-                            SharedData {
-                                node: node_name.clone(),
-                                session_store: storage,
-                                client,
-                            },
-                        )
-                        .await
-                        .unwrap_or_else(|e| eprintln!("{}", e));
+                        let mut deadline = signal.clone();
+                        let shutdown = async move {
+                            let _ = signal.wait_for(|stop| *stop).await;
+                        };
+                        // Requests still in process after the timeout are dropped.
+                        let timeout = Duration::from_secs(CONFIG.server.shutdown_timeout);
+                        let deadline = async move {
+                            let _ = deadline.wait_for(|stop| *stop).await;
+                            tokio::time::sleep(timeout).await;
+                        };
+                        let server = run(remote_server.clone(), shared_data, shutdown, &reporter_cloned);
+                        tokio::select! {
+                            result = server => result,
+                            _ = deadline => {
+                                eprintln!("Requests in process are dropped after {:?}.", timeout);
+                                Ok(())
+                            }
+                        }
                     })
-                    .await;
+                    .await
                 })
-                .await;
+                .await
             /* KiteService has been aborted now.*/
         });
+        let result = result.unwrap_or_else(|e| Err(e.into()));
+        // Connected before the connection dropped, so back off from the base delay again.
+        if !matches!(
+            result.as_ref().map_err(|e| e.downcast_ref::<AgentError>()),
+            Err(Some(AgentError::ConnectionFailure(_)))
+        ) {
+            policy.reset();
+        }
+
+        if *shutdown.borrow() {
+            break;
+        }
+        let delay = policy.next_delay();
+        reporter.report(ConnectionState::Reconnecting { delay });
+
+        let mut signal = shutdown.clone();
+        runtime.block_on(async move {
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = signal.wait_for(|stop| *stop) => {}
+            }
+        });
+    }
+}
 
-        println!("Trying to reconnect...");
-        std::thread::sleep(Duration::from_secs(10));
+/// Print state transitions of workers.
+async fn log_connection_state(mut receiver: broadcast::Receiver<ConnectionEvent>) {
+    loop {
+        match receiver.recv().await {
+            Ok(event) => match &event.state {
+                ConnectionState::Disconnected { reason: Some(_) } => {
+                    eprintln!("[worker {}] {}", event.worker, event.state)
+                }
+                _ => println!("[worker {}] {}", event.worker, event.state),
+            },
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
     }
 }
 
+/// Resolve on Ctrl-C, or SIGTERM sent by the service manager.
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
 fn main() {
-    let mut builder = reqwest::ClientBuilder::new().redirect(reqwest::redirect::Policy::none());
+    let new_builder = || {
+        let builder = reqwest::ClientBuilder::new().redirect(reqwest::redirect::Policy::none());
+        CONFIG.agent.http_version.apply(builder)
+    };
+    let mut builder = new_builder();
 
     if let Some(proxy) = &CONFIG.agent.proxy {
         let err_msg = "Invalid proxy settings.";
@@ -74,20 +149,72 @@ fn main() {
         println!("Load proxy: {}", proxy);
     }
     let http_client = builder.build().expect("Could not init http client.");
-    let storage = SessionStorage::new().expect("Fail to load SessionStorage.");
+    let proxies = AccountProxies::new(&CONFIG.agent.account_proxies, || {
+        new_builder().danger_accept_invalid_certs(true)
+    })
+    .expect("Invalid account proxy settings.");
+    let db = sled::Config::new()
+        .mode(sled::Mode::HighThroughput)
+        .path(&CONFIG.agent.db)
+        .open()
+        .expect("Fail to open database.");
+    let storage = SessionStorage::from_config(db.clone())
+        .expect("Fail to load SessionStorage.")
+        .with_proxies(proxies);
+    let history_tree = db.open_tree(HISTORY_TREE).expect("Fail to load activity history.");
+    let history = ActivityHistory::new(history_tree);
+    let shared_data = SharedData {
+        node: CONFIG.agent.name.clone(),
+        client: http_client,
+        session_store: storage,
+        activity_history: history,
+        command_policy: Arc::new(CommandPolicy::new(&CONFIG.command)),
+        metrics: Arc::new(Metrics::default()),
+        subscriptions: Arc::new(Subscriptions::default()),
+        dashboard_cache: Arc::new(DashboardCache::default()),
+        recent_errors: Arc::new(RecentErrors::new(CONFIG.agent.recent_errors)),
+        image_cache: Arc::new(ImageCache::new(CONFIG.sc.image_cache_size)),
+        dispatch_limit: DispatchLimit::new(CONFIG.server.max_concurrency, CONFIG.server.busy_policy),
+    };
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let (state_tx, state_rx) = broadcast::channel(64);
     let mut worker_threads = Vec::new();
 
-    for _ in 0..CONFIG.server.conn {
-        let client = http_client.clone();
-        let storage = storage.clone();
+    for index in 0..CONFIG.server.conn as usize {
+        let shared_data = shared_data.clone();
+        let shutdown = shutdown_rx.clone();
+        let reporter = StateReporter::new(index, state_tx.clone());
 
         let worker = std::thread::spawn(move || {
-            worker_thread(storage, client);
+            worker_thread(shared_data, shutdown, reporter);
         });
         worker_threads.push(worker);
     }
 
-    loop {
-        std::thread::sleep(Duration::from_millis(1000));
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Fail to create runtime.");
+    runtime.spawn(log_connection_state(state_rx));
+    if CONFIG.command.watch_interval > 0 {
+        runtime.spawn(watch_config(
+            shared_data.command_policy.clone(),
+            String::from(DEFAULT_CONFIG_PATH),
+            Duration::from_secs(CONFIG.command.watch_interval),
+        ));
+    }
+    runtime
+        .block_on(shutdown_signal())
+        .expect("Fail to listen for shutdown signal.");
+
+    // Let workers answer requests in process before exit.
+    println!("Shutting down...");
+    if shutdown_tx.send(true).is_err() {
+        eprintln!("All workers have exited.");
+    }
+    for worker in worker_threads {
+        if worker.join().is_err() {
+            eprintln!("Worker thread panicked.");
+        }
     }
 }
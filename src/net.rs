@@ -1,9 +1,12 @@
-pub use client::{parse_domain, UserClient};
+pub use client::{parse_domain, AccountProxies, HttpVersion, RetryPolicy, UserClient};
 pub use session::AccountCookies;
-pub use session::{Session, SessionStorage};
+pub use session::{Session, SessionGuard, SessionStorage};
+pub(crate) use session::{decrypt, encrypt};
 
 pub mod auth;
 mod availability;
 pub(crate) mod client;
+#[cfg(test)]
+pub(crate) mod fake;
 mod session;
 mod user_agent;
@@ -1,7 +1,12 @@
+use std::time::Duration;
+
+use rand::Rng;
 use reqwest::{Request, StatusCode};
 
+use crate::config::CONFIG;
 use crate::error::Result;
 use crate::make_parameter;
+use crate::parser::{LoginForm, Parse};
 use crate::service::ActionError;
 
 use super::client::is_request_redirecting;
@@ -14,6 +19,11 @@ const LOGIN_URL: &str = "https://authserver.sit.edu.cn/authserver/login";
 const NEED_CAPTCHA_URL: &str = "https://authserver.sit.edu.cn/authserver/needCaptcha.html";
 #[allow(dead_code)]
 const CAPTCHA_URL: &str = "https://authserver.sit.edu.cn/authserver/captcha.html";
+/// Logout page, which ends the session on authserver.
+const LOGOUT_URL: &str = "https://authserver.sit.edu.cn/authserver/logout";
+
+/// Max redirects or forms to follow in the login flow.
+const MAX_LOGIN_HOPS: usize = 5;
 
 /// Search in text by regex, and return the first group.
 #[macro_export]
@@ -82,6 +92,128 @@ fn identify_captcha(image_content: Vec<u8>) -> Result<String> {
     Ok(clean_verify_code(&text))
 }
 
+/// Upper bound of the delay before retry `attempt` (from 0), doubling on each retry.
+fn backoff_ceiling(base: u64, max: u64, attempt: u32) -> u64 {
+    base.saturating_mul(1u64 << attempt.min(32)).min(max)
+}
+
+/// Delay before retry `attempt`, by the "full jitter" algorithm: a random value in [0, ceiling), so
+/// that agents failing at the same time do not retry at the same time.
+pub(crate) fn retry_delay<R: Rng>(rng: &mut R, base: u64, max: u64, attempt: u32) -> Duration {
+    let ceiling = backoff_ceiling(base, max, attempt);
+    if ceiling == 0 {
+        return Duration::from_millis(0);
+    }
+    Duration::from_millis(rng.gen_range(0..ceiling))
+}
+
+/// A request in the login flow.
+#[derive(Debug, Clone, PartialEq)]
+enum LoginCall {
+    Get(String),
+    Post(String, Vec<(String, String)>),
+}
+
+/// A response in the login flow.
+#[derive(Debug, Clone)]
+struct LoginPage {
+    url: String,
+    status: StatusCode,
+    location: Option<String>,
+    body: String,
+}
+
+enum LoginOutcome {
+    Success,
+    WrongPassword,
+    /// Captcha wrong, or other error. Try again.
+    Retry,
+}
+
+async fn send_login_call(client: &mut UserClient, call: LoginCall) -> Result<LoginPage> {
+    let request = match call {
+        LoginCall::Get(url) => client.raw_client.get(&url).build()?,
+        LoginCall::Post(url, fields) => client.raw_client.post(&url).form(&fields).build()?,
+    };
+    let response = client.send(request).await?;
+    let url = response.url().to_string();
+    let status = response.status();
+    let location = response
+        .headers()
+        .get("Location")
+        .and_then(|x| x.to_str().ok())
+        .map(ToString::to_string);
+
+    Ok(LoginPage {
+        url,
+        status,
+        location,
+        body: response.text().await?,
+    })
+}
+
+fn resolve_url(base: &str, href: &str) -> Result<String> {
+    Ok(reqwest::Url::parse(base)?.join(href)?.to_string())
+}
+
+/// Open the login page, following redirects, since authserver may send us to another unified auth.
+async fn open_login_page(client: &mut UserClient, url: &str) -> Result<LoginPage> {
+    let mut url = url.to_string();
+    for _ in 0..MAX_LOGIN_HOPS {
+        let page = send_login_call(client, LoginCall::Get(url.clone())).await?;
+        match &page.location {
+            Some(location) if is_request_redirecting(page.status) => {
+                url = resolve_url(&page.url, location)?
+            }
+            _ => return Ok(page),
+        }
+    }
+    Err(ActionError::Unknown.into())
+}
+
+/// Fill and submit the login form on the page, and then submit the forms on the pages returned to
+/// continue, until redirected.
+async fn submit_login(
+    client: &mut UserClient,
+    page: LoginPage,
+    account: &str,
+    password: &str,
+    captcha: &str,
+) -> Result<LoginOutcome> {
+    let form = LoginForm::from_html(&page.body)?;
+    let password_field = form.password_field.ok_or(ActionError::Unknown)?;
+    let password = match &form.salt {
+        Some(salt) => generate_password_string(password, salt),
+        None => password.to_string(),
+    };
+    let mut fields = form.fields;
+    fields.push((
+        form.username_field.unwrap_or_else(|| String::from("username")),
+        account.to_string(),
+    ));
+    fields.push((password_field, password));
+    fields.push((String::from("captchaResponse"), captcha.to_string()));
+
+    let url = resolve_url(&page.url, &form.action)?;
+    let mut response = send_login_call(client, LoginCall::Post(url, fields)).await?;
+    for _ in 0..MAX_LOGIN_HOPS {
+        if is_request_redirecting(response.status) {
+            return Ok(LoginOutcome::Success);
+        }
+        if response.body.contains("您提供的用户名或者密码有误") {
+            return Ok(LoginOutcome::WrongPassword);
+        }
+        // A page with a form to continue the flow, which the browser submits by script.
+        let form = match LoginForm::from_html(&response.body) {
+            Ok(form) if form.password_field.is_none() => form,
+            _ => return Ok(LoginOutcome::Retry),
+        };
+        let url = resolve_url(&response.url, &form.action)?;
+        response = send_login_call(client, LoginCall::Post(url, form.fields)).await?;
+    }
+    Ok(LoginOutcome::Retry)
+}
+
 /// Login on campus official auth-server with student id and password.
 /// Return string of cookies on `.sit.edu.cn`.
 pub async fn portal_login(
@@ -98,10 +230,7 @@ pub async fn portal_login(
         client.session.cookies.clear();
 
         // Request login page to get encrypt key and so on.
-        let index_request = Request::new(reqwest::Method::GET, LOGIN_URL.parse()?);
-        let index_response = client.send(index_request).await?;
-        let index_html = index_response.text().await?;
-        let aes_key = regex_find!(&index_html, r#"var pwdDefaultEncryptSalt = "(.*?)";"#).unwrap();
+        let page = open_login_page(&mut client, LOGIN_URL).await?;
 
         let need_captcha = check_need_captcha(&mut client, user_name).await?;
         let mut captcha = String::new();
@@ -115,49 +244,38 @@ pub async fn portal_login(
                 }
             }
         }
-        let login_request = client
-            .raw_client
-            .post(LOGIN_URL)
-            .form(&[
-                ("username", user_name),
-                (
-                    "password",
-                    &generate_password_string(&password.to_string(), &aes_key),
-                ),
-                ("dllt", "userNamePasswordLogin"),
-                ("execution", "e1s1"),
-                ("_eventId", "submit"),
-                ("rmShown", "1"),
-                ("captchaResponse", &captcha),
-                (
-                    "lt",
-                    &regex_find!(&index_html, r#"<input type="hidden" name="lt" value="(.*?)"/>"#)
-                        .unwrap(),
-                ),
-            ])
-            .build()?;
-
-        let response = client.send(login_request).await?;
-        // Login successfully.
-        if is_request_redirecting(response.status()) {
-            return Ok(client.session);
-        }
-        // Password error
-        if response.status() == StatusCode::OK {
-            let response_text = response.text().await?;
-            if response_text.contains("您提供的用户名或者密码有误") {
-                // If successfully authenticated or password wrong, break.
-                return Err(ActionError::LoginFailed.into());
-            } else {
-                // Else, captcha wrong, or other error, make a captcha challenge again.
-            }
+        let outcome = submit_login(&mut client, page, user_name, password, &captcha).await?;
+        match outcome {
+            LoginOutcome::Success => return Ok(client.session),
+            LoginOutcome::WrongPassword => return Err(ActionError::LoginFailed.into()),
+            LoginOutcome::Retry => {}
         }
 
         try_count -= 1;
+        if try_count > 0 {
+            let attempt = (7 - try_count) as u32;
+            let config = &CONFIG.login;
+            let delay = retry_delay(
+                &mut rand::thread_rng(),
+                config.retry_base_delay,
+                config.retry_max_delay,
+                attempt,
+            );
+            tokio::time::sleep(delay).await;
+        }
     }
     Err(ActionError::Unknown.into())
 }
 
+/// Logout on authserver, so that the session is closed on the server side as well.
+pub async fn portal_logout(raw_client: &reqwest::Client, session: Session) -> Result<()> {
+    let mut client = UserClient::new(session, raw_client);
+
+    let request = client.raw_client.get(LOGOUT_URL).build()?;
+    client.send(request).await?;
+    Ok(())
+}
+
 /// When submit password to `authserver.sit.edu.cn`, it's required to do AES and base64 algorithm with
 /// origin password. We use a key from HTML (generated and changed by `JSESSIONID`) to help with.
 pub fn generate_password_string(clear_password: &str, key: &str) -> String {
@@ -176,3 +294,81 @@ pub fn generate_password_string(clear_password: &str, key: &str) -> String {
     let encrypted_password = cipher.encrypt_vec(&content);
     base64::encode(encrypted_password)
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::{backoff_ceiling, open_login_page, retry_delay, submit_login, LoginOutcome};
+    use crate::net::fake::{FakeRequest, FakeResponse, FakeUpstream};
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(backoff_ceiling(500, 8000, 0), 500);
+        assert_eq!(backoff_ceiling(500, 8000, 3), 4000);
+        assert_eq!(backoff_ceiling(500, 8000, 10), 8000);
+
+        let mut rng = StdRng::seed_from_u64(7);
+        for attempt in 0..6 {
+            let ceiling = Duration::from_millis(backoff_ceiling(500, 8000, attempt));
+            // Delays of 100 agents retrying at the same time.
+            let delays: Vec<Duration> = (0..100)
+                .map(|_| retry_delay(&mut rng, 500, 8000, attempt))
+                .collect();
+
+            assert!(delays.iter().all(|x| *x < ceiling));
+            assert!(delays.iter().any(|x| *x < ceiling / 4));
+            assert!(delays.iter().any(|x| *x > ceiling * 3 / 4));
+            assert!(delays.iter().collect::<HashSet<_>>().len() > 50);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_login_chain() {
+        // Over plain http, which the fake upstream serves as a proxy.
+        let login_url = "http://authserver.sit.edu.cn/authserver/login";
+        let new_login =
+            "http://ids.sit.edu.cn/authserver/login?service=https%3A%2F%2Fauthserver.sit.edu.cn\
+                         %2Fauthserver%2Fupgrade%2Fcallback";
+        let callback = "http://authserver.sit.edu.cn/authserver/upgrade/callback";
+        let fixture = |path| std::fs::read_to_string(path).unwrap();
+
+        let handler = move |request: &FakeRequest| match (request.method.as_str(), &*request.url) {
+            ("GET", url) if url == login_url => FakeResponse::redirect(new_login),
+            ("GET", url) if url == new_login => {
+                FakeResponse::ok(fixture("html/统一认证登录页面_新版.html"))
+            }
+            ("POST", url) if url == new_login => {
+                let page = fixture("html/统一认证登录跳转页面.html");
+                FakeResponse::ok(page.replace("https://", "http://"))
+            }
+            ("POST", url) if url == callback => {
+                FakeResponse::redirect("http://authserver.sit.edu.cn/authserver/index.do")
+            }
+            _ => FakeResponse::status("404 Not Found"),
+        };
+        let upstream = FakeUpstream::start(handler).await;
+        let mut client = upstream.user_client();
+
+        let login_page = open_login_page(&mut client, login_url).await.unwrap();
+        let outcome = submit_login(&mut client, login_page, "1910000000", "password", "")
+            .await
+            .unwrap();
+        assert!(matches!(outcome, LoginOutcome::Success));
+
+        let requests = upstream.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[1].url, new_login);
+        assert_eq!(requests[2].method, "POST");
+        let fields: Vec<&str> = requests[2].body.split('&').collect();
+        assert!(fields.contains(&"execution=e2s1-8f1c2a7d"));
+        assert!(fields.contains(&"username=1910000000"));
+        assert!(!fields.contains(&"password=password"));
+        assert_eq!(requests[3].url, callback);
+        assert_eq!(requests[3].body, "ticket=ST-98765-unifiedauth&relayState=sc");
+    }
+}
@@ -1,9 +1,17 @@
-use reqwest::header::HeaderValue;
-use reqwest::{Client, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::{HeaderValue, CONTENT_TYPE};
+use reqwest::{Client, ClientBuilder, Method, Response, StatusCode};
+use serde::Deserialize;
 
 use crate::error::Result;
+use crate::parser::decode_body;
+use crate::service::ActionError;
 
-use super::Session;
+use super::auth::retry_delay;
+use super::{AccountCookies, Session};
 
 /// Get domain by url. The url must be started with `http://` or `https://` and a splash needed to
 /// after the domain. The function used to get domain and pick cookies from cookie store by name, or
@@ -13,6 +21,34 @@ pub fn parse_domain(url: &str) -> Option<String> {
     regex.captures(url).map(|x| x[1].to_string())
 }
 
+/// Join the base url and path, then append query pairs. Slashes between base and path are normalized,
+/// query parameters already in the base are kept, and values are url-encoded.
+pub fn build_url(base: &str, path: &str, query: &[(&str, &str)]) -> Result<String> {
+    let mut url = reqwest::Url::parse(base)?;
+
+    if !path.is_empty() {
+        let full_path = format!("{}/{}", url.path().trim_end_matches('/'), path.trim_start_matches('/'));
+        url.set_path(&full_path);
+    }
+    if !query.is_empty() {
+        url.query_pairs_mut().extend_pairs(query);
+    }
+    Ok(url.to_string())
+}
+
+/// Read the body as text. Unlike `Response::text`, the charset is not taken from the header only,
+/// since some older pages of the school are in GBK whatever the header says.
+pub async fn read_text(response: Response) -> Result<String> {
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .map(String::from);
+    let body = response.bytes().await?;
+
+    Ok(decode_body(&body, content_type.as_deref()))
+}
+
 pub fn parse_protocol(url: &str) -> String {
     if url.starts_with("https") {
         return String::from("https");
@@ -20,6 +56,124 @@ pub fn parse_protocol(url: &str) -> String {
     String::from("http")
 }
 
+/// HTTP version to talk to upstream. Some campus proxies misbehave with HTTP/2.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Default)]
+pub enum HttpVersion {
+    /// Negotiated by ALPN on TLS, and HTTP/1.1 on plain connections.
+    #[default]
+    #[serde(rename = "auto")]
+    Auto,
+    #[serde(rename = "1.1")]
+    Http1,
+    /// HTTP/2 with prior knowledge, even on plain connections.
+    #[serde(rename = "2")]
+    Http2,
+}
+
+impl HttpVersion {
+    /// Pin the HTTP version of the client to build.
+    pub fn apply(self, builder: ClientBuilder) -> ClientBuilder {
+        match self {
+            HttpVersion::Auto => builder,
+            HttpVersion::Http1 => builder.http1_only(),
+            HttpVersion::Http2 => builder.http2_prior_knowledge(),
+        }
+    }
+}
+
+/// Clients egressing through the proxy designated for each account.
+#[derive(Debug, Clone, Default)]
+pub struct AccountProxies {
+    clients: Arc<HashMap<String, Client>>,
+}
+
+impl AccountProxies {
+    /// Build a client by `builder` for each proxy, which is shared by accounts on the same proxy.
+    pub fn new<F>(proxies: &HashMap<String, String>, builder: F) -> Result<Self>
+    where
+        F: Fn() -> ClientBuilder,
+    {
+        let mut by_proxy: HashMap<&str, Client> = HashMap::new();
+        let mut clients = HashMap::new();
+
+        for (account, proxy) in proxies {
+            let client = match by_proxy.get(proxy.as_str()) {
+                Some(client) => client.clone(),
+                None => {
+                    let client = builder()
+                        .proxy(reqwest::Proxy::http(proxy)?)
+                        .proxy(reqwest::Proxy::https(proxy)?)
+                        .build()?;
+                    by_proxy.insert(proxy, client.clone());
+                    client
+                }
+            };
+            clients.insert(account.clone(), client);
+        }
+        Ok(Self {
+            clients: Arc::new(clients),
+        })
+    }
+
+    /// Client of the account, or `None` to use the default one.
+    pub fn get(&self, account: &str) -> Option<&Client> {
+        self.clients.get(account)
+    }
+}
+
+/// When to send a request again on a transient failure, that is, a connection error or a 5xx status.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RetryPolicy {
+    /// Max times to retry, zero to disable.
+    pub times: u32,
+    /// Base delay before retrying, in milliseconds. It doubles on each retry, and the actual delay is
+    /// randomized in [0, current delay).
+    pub base_delay: u64,
+    /// Max delay before retrying, in milliseconds.
+    pub max_delay: u64,
+    /// Retry POST and other non-idempotent requests too, which may take effect twice upstream.
+    pub retry_post: bool,
+}
+
+impl RetryPolicy {
+    /// Retry idempotent requests up to `times`.
+    pub fn new(times: u32, base_delay: u64, max_delay: u64) -> Self {
+        Self {
+            times,
+            base_delay,
+            max_delay,
+            retry_post: false,
+        }
+    }
+
+    fn allows(&self, method: &Method) -> bool {
+        let idempotent = matches!(
+            *method,
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE | Method::TRACE
+        );
+        self.times > 0 && (idempotent || self.retry_post)
+    }
+}
+
+/// Whether the failure is likely to go away by sending again.
+fn is_transient(result: &reqwest::Result<Response>) -> bool {
+    match result {
+        Ok(response) => response.status().is_server_error(),
+        Err(e) => e.is_connect() || e.is_timeout(),
+    }
+}
+
+/// Time limit of a request by default, from connecting until the body is read.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Report an expired request as `ActionError::Timeout`, and keep other errors as they are.
+fn map_timeout(result: reqwest::Result<Response>) -> Result<Response> {
+    match result {
+        Err(e) if e.is_timeout() => Err(ActionError::Timeout.into()),
+        result => Ok(result?),
+    }
+}
+
 pub enum Action {
     Redirect(String),
     Done,
@@ -34,6 +188,10 @@ pub struct UserClient {
 
     request_hook: Option<RequestHook>,
     response_hook: Option<ResponseHook>,
+    retry_policy: RetryPolicy,
+    timeout: Duration,
+    /// Cookies of the session when forked, to tell those set on the fork since.
+    forked_cookies: AccountCookies,
 }
 
 impl UserClient {
@@ -43,6 +201,38 @@ impl UserClient {
             raw_client: raw_client.clone(),
             request_hook: None,
             response_hook: None,
+            retry_policy: RetryPolicy::default(),
+            timeout: DEFAULT_TIMEOUT,
+            forked_cookies: AccountCookies::new(),
+        }
+    }
+
+    /// A client on a copy of the session, with the same hooks, for requests running concurrently.
+    /// Cookies set on the copy are not synced back, until the copy is merged by `merge`.
+    pub fn fork(&self) -> UserClient {
+        Self {
+            session: self.session.clone(),
+            raw_client: self.raw_client.clone(),
+            request_hook: self.request_hook,
+            response_hook: self.response_hook,
+            retry_policy: self.retry_policy,
+            timeout: self.timeout,
+            forked_cookies: self.session.cookies.clone(),
+        }
+    }
+
+    /// Take back cookies set on a fork since it was forked, so that a session renewed by the fork is
+    /// kept. Cookies the fork left as they were do not overwrite those set by other forks.
+    pub fn merge(&mut self, fork: UserClient) {
+        for (domain, cookies) in fork.session.cookies {
+            let forked = fork.forked_cookies.get(&domain);
+            let changed: Vec<(String, String)> = cookies
+                .into_iter()
+                .filter(|(name, value)| forked.and_then(|x| x.get(name)) != Some(value))
+                .collect();
+            if !changed.is_empty() {
+                self.session.cookies.entry(domain).or_default().extend(changed);
+            }
         }
     }
 
@@ -54,31 +244,79 @@ impl UserClient {
         self.response_hook = hook;
     }
 
-    pub async fn send(&mut self, request: reqwest::Request) -> Result<Response> {
-        let mut complete_url;
-        let mut request = request;
+    /// Retry transient failures by the policy. Requests are not retried by default.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Limit each request to `timeout`, from connecting until the body is read, unless the request
+    /// sets one itself. Reading the body after that fails with a timeout error too.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
 
+    /// Execute the request, and again on transient failures as the retry policy allows. The last
+    /// result is returned once retries run out.
+    async fn execute(&self, request: reqwest::Request) -> Result<Response> {
+        let policy = self.retry_policy;
+        if !policy.allows(request.method()) {
+            return map_timeout(self.raw_client.execute(request).await);
+        }
+        let mut attempt = 0;
         loop {
-            /* Parse domain and load cookies from session */
-            complete_url = request.url().to_string();
+            // A request with a streaming body can not be sent again.
+            let retry = match request.try_clone() {
+                Some(retry) if attempt < policy.times => retry,
+                _ => return map_timeout(self.raw_client.execute(request).await),
+            };
+            let result = self.raw_client.execute(retry).await;
+            if !is_transient(&result) {
+                return map_timeout(result);
+            }
+            let (base, max) = (policy.base_delay, policy.max_delay);
+            let delay = retry_delay(&mut rand::thread_rng(), base, max, attempt);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
 
-            let domain = parse_domain(&complete_url).expect("Could not parse domain.");
-            let cookies = self.session.get_cookie_string(&domain);
+    /// Send the request with cookies of the session, without calling the response hook, so that the
+    /// caller sees a redirect as is.
+    pub async fn send_once(&mut self, request: reqwest::Request) -> Result<Response> {
+        let mut request = request;
 
-            if !cookies.is_empty() {
-                request
-                    .headers_mut()
-                    .append("cookie", HeaderValue::from_str(&cookies)?);
-            }
+        /* Parse domain and load cookies from session */
+        let complete_url = request.url().to_string();
 
-            /* Call request hook */
-            if let Some(hook) = self.request_hook {
-                hook(&mut request);
-            }
-            /* Execute request */
-            let mut response = self.raw_client.execute(request).await?;
-            /* Store new cookies to session */
-            self.session.sync_cookies(&domain, response.cookies());
+        let domain = parse_domain(&complete_url).expect("Could not parse domain.");
+        let cookies = self.session.get_cookie_string(&domain);
+
+        if !cookies.is_empty() {
+            request
+                .headers_mut()
+                .append("cookie", HeaderValue::from_str(&cookies)?);
+        }
+
+        if request.timeout().is_none() {
+            *request.timeout_mut() = Some(self.timeout);
+        }
+        /* Call request hook */
+        if let Some(hook) = self.request_hook {
+            hook(&mut request);
+        }
+        /* Execute request */
+        let response = self.execute(request).await?;
+        /* Store new cookies to session */
+        self.session.sync_cookies(&domain, response.cookies());
+
+        Ok(response)
+    }
+
+    pub async fn send(&mut self, request: reqwest::Request) -> Result<Response> {
+        let mut request = request;
+
+        loop {
+            let mut response = self.send_once(request).await?;
             /* Call response hook */
             match self
                 .response_hook
@@ -86,8 +324,7 @@ impl UserClient {
                 .unwrap_or(Action::Done)
             {
                 Action::Redirect(next_hop) => {
-                    complete_url = next_hop;
-                    request = self.raw_client.get(&complete_url).build()?;
+                    request = self.raw_client.get(&next_hop).build()?;
                 }
                 Action::Done => {
                     return Ok(response);
@@ -128,3 +365,261 @@ pub fn default_response_hook(response: &mut Response) -> Action {
     }
     Action::Done
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::{build_url, HttpVersion, RetryPolicy, UserClient};
+    use crate::net::Session;
+    use crate::service::{ActionError, ErrorResponse};
+
+    #[test]
+    fn test_build_url() {
+        let path = "/public/activity/activityDetail.action";
+        let expected = "http://sc.sit.edu.cn/public/activity/activityDetail.action?activityId=1061909";
+
+        assert_eq!(
+            build_url("http://sc.sit.edu.cn", path, &[("activityId", "1061909")]).unwrap(),
+            expected
+        );
+        assert_eq!(
+            build_url("http://sc.sit.edu.cn/", path, &[("activityId", "1061909")]).unwrap(),
+            expected
+        );
+        assert_eq!(
+            build_url("http://sc.sit.edu.cn/public/", "activity/activityDetail.action", &[]).unwrap(),
+            "http://sc.sit.edu.cn/public/activity/activityDetail.action"
+        );
+    }
+
+    #[test]
+    fn test_build_url_with_query() {
+        let url = build_url(
+            "http://sc.sit.edu.cn/public/activity/activityList.action?pageSize=20",
+            "",
+            &[("pageNo", "1"), ("activityName", "讲座 报告")],
+        )
+        .unwrap();
+
+        assert_eq!(
+            url,
+            "http://sc.sit.edu.cn/public/activity/activityList.action?pageSize=20&pageNo=1\
+             &activityName=%E8%AE%B2%E5%BA%A7+%E6%8A%A5%E5%91%8A"
+        );
+    }
+
+    /// Answer each connection with a plain HTTP/1.1 response.
+    async fn serve_http1(listener: TcpListener) {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 4096];
+                let _ = stream.read(&mut buf).await;
+                let response = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_version() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(serve_http1(listener));
+
+        for version in [HttpVersion::Auto, HttpVersion::Http1] {
+            let client = version.apply(reqwest::ClientBuilder::new()).build().unwrap();
+            let response = client.get(&url).send().await.unwrap();
+
+            assert_eq!(response.version(), reqwest::Version::HTTP_11);
+            assert_eq!(response.text().await.unwrap(), "ok");
+        }
+    }
+
+    #[test]
+    fn test_parse_http_version() {
+        #[derive(serde::Deserialize)]
+        struct Agent {
+            #[serde(default)]
+            http_version: HttpVersion,
+        }
+        let parse = |s: &str| toml::from_str::<Agent>(s).unwrap().http_version;
+
+        assert_eq!(parse(""), HttpVersion::Auto);
+        assert_eq!(parse("http_version = \"1.1\""), HttpVersion::Http1);
+        assert_eq!(parse("http_version = \"2\""), HttpVersion::Http2);
+    }
+
+    /// Answer 503 to the first `failures` requests, and 200 afterwards.
+    async fn serve_flaky(listener: TcpListener, failures: usize, served: Arc<AtomicUsize>) {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let served = served.clone();
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 4096];
+                let _ = stream.read(&mut buf).await;
+                let response = if served.fetch_add(1, Ordering::SeqCst) < failures {
+                    "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                } else {
+                    "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok"
+                };
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_on_server_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let served = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(serve_flaky(listener, 2, served.clone()));
+
+        let raw_client = reqwest::Client::new();
+        let mut client = UserClient::new(Session::new("1910000000", "password"), &raw_client);
+        client.set_retry_policy(RetryPolicy::new(2, 10, 100));
+
+        let response = client.send(raw_client.get(&url).build().unwrap()).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "ok");
+        assert_eq!(served.load(Ordering::SeqCst), 3);
+
+        // POSTs are sent once unless opted in.
+        served.store(0, Ordering::SeqCst);
+        let request = raw_client.post(&url).body("id=1").build().unwrap();
+        let response = client.send(request).await.unwrap();
+        assert_eq!(response.status(), 503);
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+
+        let mut policy = RetryPolicy::new(2, 10, 100);
+        policy.retry_post = true;
+        client.set_retry_policy(policy);
+        let request = raw_client.post(&url).body("id=1").build().unwrap();
+        let response = client.send(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(served.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_runs_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let served = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(serve_flaky(listener, 5, served.clone()));
+
+        let raw_client = reqwest::Client::new();
+        let mut client = UserClient::new(Session::new("1910000000", "password"), &raw_client);
+        client.set_retry_policy(RetryPolicy::new(2, 10, 100));
+
+        let response = client.send(raw_client.get(&url).build().unwrap()).await.unwrap();
+        assert_eq!(response.status(), 503);
+        assert_eq!(served.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_on_dropped_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let served = Arc::new(AtomicUsize::new(0));
+        let served_cloned = served.clone();
+        tokio::spawn(async move {
+            loop {
+                // Close the connection after reading the request, without answering.
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0u8; 4096];
+                let _ = stream.read(&mut buf).await;
+                served_cloned.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        let raw_client = reqwest::Client::new();
+        let mut client = UserClient::new(Session::new("1910000000", "password"), &raw_client);
+        client.set_retry_policy(RetryPolicy::new(2, 10, 100));
+
+        // The request may have reached the upstream, which is not sent again.
+        assert!(client.send(raw_client.get(&url).build().unwrap()).await.is_err());
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+    }
+
+    /// Stall before the headers on "/slow", and in the middle of the body on other paths.
+    async fn serve_stalled(listener: TcpListener) {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 4096];
+                let n = stream.read(&mut buf).await.unwrap_or_default();
+                let request = String::from_utf8_lossy(&buf[..n]);
+                if !request.starts_with("GET /slow ") {
+                    let response = "HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\npartial";
+                    let _ = stream.write_all(response.as_bytes()).await;
+                }
+                tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve_stalled(listener));
+
+        let raw_client = reqwest::Client::new();
+        let mut client = UserClient::new(Session::new("1910000000", "password"), &raw_client);
+        client.set_timeout(std::time::Duration::from_millis(200));
+
+        let request = raw_client.get(format!("{}/slow", base)).build().unwrap();
+        let e = client.send(request).await.unwrap_err();
+        assert!(matches!(e.downcast_ref(), Some(ActionError::Timeout)));
+
+        // The body is read after the client returns, and expires all the same.
+        let request = raw_client.get(format!("{}/body", base)).build().unwrap();
+        let response = client.send(request).await.unwrap();
+        let e = anyhow::Error::from(response.text().await.unwrap_err());
+        assert_eq!(ErrorResponse::from(e).code, 64);
+    }
+
+    #[test]
+    fn test_merge_forks() {
+        let cookie = |name: &str, value: &str| (name.to_string(), value.to_string());
+        let mut session = Session::new("1910000000", "password");
+        let sc = vec![cookie("JSESSIONID", "old"), cookie("route", "1")];
+        session
+            .cookies
+            .insert(String::from("sc.sit.edu.cn"), sc.into_iter().collect());
+        let mut client = UserClient::new(session, &reqwest::Client::new());
+
+        // One fork renews the session, and another sets a cookie on another domain.
+        let (mut renewed, mut other) = (client.fork(), client.fork());
+        let (name, value) = cookie("JSESSIONID", "new");
+        renewed
+            .session
+            .cookies
+            .get_mut("sc.sit.edu.cn")
+            .unwrap()
+            .insert(name, value);
+        let authserver = vec![cookie("CASTGC", "TGT-1")];
+        other.session.cookies.insert(
+            String::from("authserver.sit.edu.cn"),
+            authserver.into_iter().collect(),
+        );
+        client.merge(renewed);
+        client.merge(other);
+
+        let session = &client.session;
+        assert_eq!(
+            session.query_cookie("sc.sit.edu.cn", "JSESSIONID").unwrap(),
+            "new"
+        );
+        assert_eq!(session.query_cookie("sc.sit.edu.cn", "route").unwrap(), "1");
+        assert_eq!(
+            session.query_cookie("authserver.sit.edu.cn", "CASTGC").unwrap(),
+            "TGT-1"
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! A fake upstream for tests, answering plain HTTP requests by a handler and keeping the requests
+//! received. Clients made by `FakeUpstream::client` send requests to any http host through it, as a
+//! proxy, so that code requesting the campus sites by their urls can be tested without them.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use super::{Session, UserClient};
+
+/// A request received. The url is absolute if the request is sent through the proxy.
+#[derive(Debug, Clone)]
+pub struct FakeRequest {
+    pub method: String,
+    pub url: String,
+    /// Header lines, as sent.
+    pub headers: Vec<String>,
+    pub body: String,
+}
+
+impl FakeRequest {
+    /// Path and query of the url.
+    pub fn path(&self) -> &str {
+        match self.url.find("://") {
+            Some(i) => {
+                let rest = &self.url[i + 3..];
+                rest.find('/').map_or("/", |j| &rest[j..])
+            }
+            None => &self.url,
+        }
+    }
+
+    /// Value of the header, matched by name case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            Some(value.trim()).filter(|_| key.trim().eq_ignore_ascii_case(name))
+        })
+    }
+}
+
+/// A response to send.
+#[derive(Debug, Clone)]
+pub struct FakeResponse {
+    status: &'static str,
+    headers: Vec<String>,
+    body: Vec<u8>,
+    delay: Duration,
+}
+
+impl FakeResponse {
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self::status("200 OK").body(body)
+    }
+
+    /// A response of the status line, like "503 Service Unavailable", without body.
+    pub fn status(status: &'static str) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+            delay: Duration::default(),
+        }
+    }
+
+    pub fn redirect(location: &str) -> Self {
+        Self::status("302 Found").header("Location", location)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push(format!("{}: {}", name, value));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Wait before answering.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+        for header in &self.headers {
+            head += &format!("{}\r\n", header);
+        }
+        head += &format!("Content-Length: {}\r\nConnection: close\r\n\r\n", self.body.len());
+
+        let mut bytes = head.into_bytes();
+        bytes.extend(&self.body);
+        bytes
+    }
+}
+
+type Handler = Box<dyn FnMut(&FakeRequest) -> FakeResponse + Send>;
+
+#[derive(Default)]
+struct Counters {
+    in_flight: AtomicUsize,
+    most_in_flight: AtomicUsize,
+}
+
+pub struct FakeUpstream {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<FakeRequest>>>,
+    counters: Arc<Counters>,
+}
+
+impl FakeUpstream {
+    /// Listen on a free port, and answer each request by `handler`.
+    pub async fn start<F>(handler: F) -> Self
+    where
+        F: FnMut(&FakeRequest) -> FakeResponse + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = Self {
+            addr: listener.local_addr().unwrap(),
+            requests: Arc::default(),
+            counters: Arc::default(),
+        };
+        let handler: Arc<Mutex<Handler>> = Arc::new(Mutex::new(Box::new(handler)));
+        let (requests, counters) = (upstream.requests.clone(), upstream.counters.clone());
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (handler, requests, counters) =
+                    (handler.clone(), requests.clone(), counters.clone());
+                tokio::spawn(async move {
+                    let _ = answer(stream, handler, requests, counters).await;
+                });
+            }
+        });
+        upstream
+    }
+
+    /// Url of the path on the upstream itself.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// A client sending http requests to any host through the upstream, not following redirects as
+    /// the agent does.
+    pub fn client(&self) -> reqwest::Client {
+        reqwest::Client::builder()
+            .proxy(reqwest::Proxy::http(self.url("")).unwrap())
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+    }
+
+    /// A user client on a new session, with `client()`.
+    pub fn user_client(&self) -> UserClient {
+        UserClient::new(Session::new("1910000000", "password"), &self.client())
+    }
+
+    /// Requests received so far.
+    pub fn requests(&self) -> Vec<FakeRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Paths of requests received so far.
+    pub fn paths(&self) -> Vec<String> {
+        self.requests().iter().map(|x| x.path().to_string()).collect()
+    }
+
+    /// The most requests being answered at once so far.
+    pub fn most_in_flight(&self) -> usize {
+        self.counters.most_in_flight.load(Ordering::SeqCst)
+    }
+}
+
+async fn answer(
+    mut stream: TcpStream,
+    handler: Arc<Mutex<Handler>>,
+    requests: Arc<Mutex<Vec<FakeRequest>>>,
+    counters: Arc<Counters>,
+) -> std::io::Result<()> {
+    let request = read_request(&mut stream).await?;
+    let in_flight = counters.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+    counters.most_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+
+    requests.lock().unwrap().push(request.clone());
+    let response = (handler.lock().unwrap())(&request);
+    tokio::time::sleep(response.delay).await;
+
+    counters.in_flight.fetch_sub(1, Ordering::SeqCst);
+    stream.write_all(&response.to_bytes()).await
+}
+
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<FakeRequest> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let head_end = loop {
+        if let Some(i) = buf.windows(4).position(|x| x == b"\r\n\r\n") {
+            break i;
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let head = String::from_utf8_lossy(&buf[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let mut request = FakeRequest {
+        method: request_line.next().unwrap_or_default().to_string(),
+        url: request_line.next().unwrap_or_default().to_string(),
+        headers: lines.map(ToString::to_string).collect(),
+        body: String::new(),
+    };
+
+    let length: usize = request
+        .header("Content-Length")
+        .and_then(|x| x.parse().ok())
+        .unwrap_or_default();
+    let mut body = buf[head_end + 4..].to_vec();
+    while body.len() < length {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    request.body = String::from_utf8_lossy(&body).to_string();
+    Ok(request)
+}
@@ -1,50 +1,263 @@
 use std::collections::HashMap;
+use std::convert::TryInto;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use chrono::{NaiveDateTime, Utc};
 use reqwest::cookie::Cookie;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
 use serde::{Deserialize, Serialize};
 
 use crate::config::CONFIG;
-use crate::error::Result;
+use crate::error::{AgentError, Result};
+use crate::service::ActionError;
+
+use super::{AccountProxies, RetryPolicy, UserClient};
 
 /// Session structure key format in relation.
 const SESSION_KEY_FORMAT: &str = "s:";
+/// Prefix of a compressed session value. A bincode session starts with the length of the account,
+/// which never looks like it.
+const COMPRESSED_MAGIC: &[u8] = b"\xffZSN";
+/// Prefix of an encrypted session value, or session file.
+const ENCRYPTED_MAGIC: &[u8] = b"\xffESN";
+/// Environment variable of the key to encrypt sessions at rest, 32 bytes encoded in base64.
+const SESSION_KEY_ENV: &str = "KITE_SESSION_KEY";
+
+/// Key of AES-256.
+pub type SecretKey = [u8; 32];
+
+/// Failures in a row before a session is benched.
+const BENCH_FAILURES: u32 = 3;
+/// Time a benched session is not chosen.
+const BENCH_TIME: Duration = Duration::from_secs(300);
+/// Time to wait for a session leased by another request.
+const LEASE_WAIT: Duration = Duration::from_secs(30);
+/// Times to choose a random session again, if the one chosen is leased meanwhile.
+const CHECKOUT_ATTEMPTS: usize = 3;
+
+/// Exclusive use of a session, released on drop.
+type Lease = tokio::sync::OwnedMutexGuard<()>;
 
 pub enum SessionError {}
 
+/// Where sessions are kept, as values indexed by account. Values are encoded, and compressed or
+/// encrypted by `SessionStorage`, so a backend keeps bytes only. A backend other than the local
+/// database, like one on Redis, lets agents share a session pool.
+pub trait SessionBackend: std::fmt::Debug + Send + Sync {
+    fn get(&self, account: &str) -> Result<Option<Vec<u8>>>;
+
+    fn insert(&self, account: &str, value: Vec<u8>) -> Result<()>;
+
+    fn remove(&self, account: &str) -> Result<()>;
+
+    /// Values of all sessions, in order of account.
+    fn values(&self) -> Result<Vec<Vec<u8>>>;
+
+    fn clear(&self) -> Result<()>;
+
+    fn len(&self) -> Result<usize>;
+}
+
+/// Sessions in the local sled database, which is the default.
+#[derive(Debug)]
+pub struct SledSessions {
+    db: sled::Db,
+}
+
+impl SledSessions {
+    pub fn new(db: sled::Db) -> Self {
+        Self { db }
+    }
+}
+
+impl SessionBackend for SledSessions {
+    fn get(&self, account: &str) -> Result<Option<Vec<u8>>> {
+        let value = self.db.get(String::from(SESSION_KEY_FORMAT) + account)?;
+        Ok(value.map(|x| x.to_vec()))
+    }
+
+    fn insert(&self, account: &str, value: Vec<u8>) -> Result<()> {
+        self.db.insert(String::from(SESSION_KEY_FORMAT) + account, value)?;
+        Ok(())
+    }
+
+    fn remove(&self, account: &str) -> Result<()> {
+        self.db.remove(String::from(SESSION_KEY_FORMAT) + account)?;
+        Ok(())
+    }
+
+    fn values(&self) -> Result<Vec<Vec<u8>>> {
+        self.db
+            .scan_prefix(SESSION_KEY_FORMAT)
+            .map(|item| Ok(item?.1.to_vec()))
+            .collect()
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.db.clear()?;
+        Ok(())
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(self.db.scan_prefix(SESSION_KEY_FORMAT).count())
+    }
+}
+
+/// Recent outcomes of requests on a session, kept in memory.
+#[derive(Debug, Default)]
+struct Health {
+    /// Failures in a row.
+    failures: u32,
+    /// Not chosen until then.
+    benched_until: Option<Instant>,
+}
+
+impl Health {
+    /// Weight to be chosen, halved on each failure in a row, or zero if benched.
+    fn weight(&self, now: Instant) -> f64 {
+        match self.benched_until {
+            Some(until) if until > now => 0.0,
+            _ => 0.5f64.powi(self.failures as i32),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SessionStorage {
-    /// Sled handle
-    db: sled::Db,
+    backend: Arc<dyn SessionBackend>,
     rng: rand::rngs::SmallRng,
+    /// Compress sessions on insert.
+    compress: bool,
+    /// Clients of accounts with a designated proxy.
+    proxies: AccountProxies,
+    /// Retry policy of clients on sessions of the storage.
+    retry_policy: RetryPolicy,
+    /// Time limit of requests by clients on sessions of the storage, or the client default.
+    timeout: Option<Duration>,
+    /// Key to encrypt sessions on insert, and to decrypt encrypted ones.
+    key: Option<SecretKey>,
+    /// Sessions not updated for longer are taken as expired, and their cookies are cleared.
+    ttl: Option<Duration>,
+    /// Health of sessions, indexed by account, shared by clones of the storage.
+    health: Arc<Mutex<HashMap<String, Health>>>,
+    /// Locks of sessions in use, indexed by account, so that a session is used by one request at a
+    /// time. Upstream sessions are stateful, and cookies updated by two requests interleave.
+    leases: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
 }
 
 impl SessionStorage {
-    /// Create a session database client.
-    pub fn new() -> Result<Self> {
+    /// Create a session storage on the database, set by the config.
+    pub fn from_config(db: sled::Db) -> Result<Self> {
+        let agent = &CONFIG.agent;
+        let (base, max) = (agent.send_retry_base_delay, agent.send_retry_max_delay);
+        let mut storage = Self::open(db)?;
+        if let Some(key) = session_key_from_env()? {
+            storage = storage.with_key(key);
+        }
+        if agent.session_ttl > 0 {
+            storage = storage.with_ttl(Duration::from_secs(agent.session_ttl));
+        }
+        Ok(storage
+            .with_compression(agent.compress_sessions)
+            .with_retry_policy(RetryPolicy::new(agent.send_retries, base, max))
+            .with_timeout(Duration::from_secs(agent.request_timeout)))
+    }
+
+    /// Create a session storage on an opened database.
+    pub fn open(db: sled::Db) -> Result<Self> {
+        Self::with_backend(Arc::new(SledSessions::new(db)))
+    }
+
+    /// Create a session storage keeping sessions in the backend.
+    pub fn with_backend(backend: Arc<dyn SessionBackend>) -> Result<Self> {
         use rand::SeedableRng;
 
-        let db = sled::Config::new()
-            .mode(sled::Mode::HighThroughput)
-            .path(&CONFIG.agent.db)
-            .open()?;
         // Note: get rand seed is a high cost operation, so we share it in session storage.
-        let os_rng = rand::rngs::OsRng::default();
+        let os_rng = rand::rngs::OsRng;
         let rng = rand::rngs::SmallRng::from_rng(os_rng)?;
 
-        Ok(Self { db, rng })
+        Ok(Self {
+            backend,
+            rng,
+            compress: false,
+            proxies: AccountProxies::default(),
+            retry_policy: RetryPolicy::default(),
+            timeout: None,
+            key: None,
+            ttl: None,
+            health: Arc::default(),
+            leases: Arc::default(),
+        })
+    }
+
+    /// Compress sessions inserted later. Sessions stored either way can be read.
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
     }
 
-    /// Query session by user.
+    /// Encrypt sessions inserted later with the key. Sessions stored in plain can still be read.
+    pub fn with_key(mut self, key: SecretKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Take sessions not logged in or used successfully within `ttl` as expired. Their cookies are
+    /// cleared when queried or chosen, so that the account logs in again on use.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Send requests of the accounts through their designated proxies, and others by the client given.
+    pub fn with_proxies(mut self, proxies: AccountProxies) -> Self {
+        self.proxies = proxies;
+        self
+    }
+
+    /// Retry transient failures of requests sent on sessions of the storage by the policy.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Limit requests sent on sessions of the storage to `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Client to send requests of the account with, which is `default` unless a proxy is designated.
+    pub fn client_of<'a>(&'a self, account: &str, default: &'a reqwest::Client) -> &'a reqwest::Client {
+        self.proxies.get(account).unwrap_or(default)
+    }
+
+    /// Query session by user. An expired session is returned without cookies.
     pub fn query(&self, account: &str) -> Result<Option<Session>> {
-        // Query session struct from db.
-        let value_option = self.db.get(String::from(SESSION_KEY_FORMAT) + account)?;
+        match self.backend.get(account)? {
+            Some(value) => self.expire(self.decode(&value)?).map(Some),
+            None => Ok(None),
+        }
+    }
 
-        if let Some(session_value) = value_option {
-            let session: Session = bincode::deserialize::<Session>(session_value.as_ref())?;
-            return Ok(Some(session));
+    fn is_expired(&self, session: &Session, now: NaiveDateTime) -> bool {
+        match self.ttl.map(chrono::Duration::from_std) {
+            Some(Ok(ttl)) => now - session.last_update > ttl,
+            _ => false,
         }
-        Ok(None)
+    }
+
+    /// Clear cookies of the session if expired, and store it back. The account and password are
+    /// kept, so the next request logs in again.
+    fn expire(&self, mut session: Session) -> Result<Session> {
+        if !session.cookies.is_empty() && self.is_expired(&session, Utc::now().naive_utc()) {
+            session.cookies.clear();
+            self.backend.insert(&session.account, self.encode(&session)?)?;
+        }
+        Ok(session)
     }
 
     /// Query session by user or create new one.
@@ -62,50 +275,140 @@ impl SessionStorage {
 
     /// Insert or update session data.
     pub fn insert(&mut self, session: &Session) -> Result<()> {
-        let db_key = String::from(SESSION_KEY_FORMAT) + &session.account;
-        let value = bincode::serialize(session)?;
-
-        self.db.insert(&db_key, value)?;
-        Ok(())
+        let value = self.encode(session)?;
+        self.backend.insert(&session.account, value)
     }
 
     /// List session
     pub fn list(&self, index: u16, size: u16) -> Result<Vec<Session>> {
         let sessions = self
-            .db
-            .iter()
+            .backend
+            .values()?
+            .into_iter()
             .skip((index * size) as usize)
             .take(size as usize)
-            .filter_map(|item| {
-                if let Ok((_, value)) = item {
-                    bincode::deserialize::<Session>(&value).ok()
-                } else {
-                    None
-                }
-            })
+            .filter_map(|value| self.decode(&value).ok())
             .collect::<Vec<Session>>();
         Ok(sessions)
     }
-    /// Choose a session data randomly.
+    /// List all sessions.
+    pub fn all(&self) -> Result<Vec<Session>> {
+        self.backend
+            .values()?
+            .iter()
+            .map(|value| self.decode(value))
+            .collect()
+    }
+
+    /// Choose a session data randomly, among ones not in use. Sessions failed recently are less
+    /// likely to be chosen, and benched ones are not unless all are.
     pub fn choose_randomly(&mut self) -> Result<Option<Session>> {
-        use rand::prelude::IteratorRandom;
+        let sessions = self.free_sessions()?;
+        self.choose_healthy(sessions).map(|s| self.expire(s)).transpose()
+    }
+
+    /// Choose a session whose account belongs to the college, or any session if there is none.
+    pub fn choose_by_college(&mut self, college: &str) -> Result<Option<Session>> {
+        let matched = self
+            .free_sessions()?
+            .into_iter()
+            .filter(|s| s.college.as_deref() == Some(college))
+            .collect();
+        match self.choose_healthy(matched) {
+            Some(session) => self.expire(session).map(Some),
+            None => self.choose_randomly(),
+        }
+    }
 
-        if let Some(Ok((_, session))) = self.db.iter().choose(&mut self.rng) {
-            let content = session.to_vec();
-            let session = bincode::deserialize::<Session>(&content)?;
+    /// Sessions not leased.
+    fn free_sessions(&self) -> Result<Vec<Session>> {
+        let leases = self.leases.lock().unwrap();
+        let is_free = |s: &Session| leases.get(&s.account).is_none_or(|x| x.try_lock().is_ok());
 
-            return Ok(Some(session));
+        Ok(self.all()?.into_iter().filter(is_free).collect())
+    }
+
+    fn lock_of(&self, account: &str) -> Arc<tokio::sync::Mutex<()>> {
+        let mut leases = self.leases.lock().unwrap();
+        leases.entry(account.to_string()).or_default().clone()
+    }
+
+    /// Lease the session if not in use.
+    fn try_lease(&self, account: &str) -> Option<Lease> {
+        self.lock_of(account).try_lock_owned().ok()
+    }
+
+    /// Lease the session, waiting for the request using it for `LEASE_WAIT` at most.
+    async fn lease(&self, account: &str) -> Result<Lease> {
+        let lock = self.lock_of(account).lock_owned();
+        tokio::time::timeout(LEASE_WAIT, lock)
+            .await
+            .map_err(|_| ActionError::NoSessionAvailable.into())
+    }
+
+    /// Choose a session weighted by health.
+    fn choose_healthy(&mut self, sessions: Vec<Session>) -> Option<Session> {
+        use rand::seq::SliceRandom;
+
+        let now = Instant::now();
+        let health = self.health.lock().unwrap();
+        let weight = |s: &Session| health.get(&s.account).map_or(1.0, |h| h.weight(now));
+
+        match sessions.choose_weighted(&mut self.rng, weight) {
+            Ok(session) => Some(session.clone()),
+            // All benched.
+            Err(_) => sessions.choose(&mut self.rng).cloned(),
         }
-        Ok(None)
     }
 
-    pub fn clear(&mut self) -> Result<()> {
-        self.db.clear()?;
+    /// Reset the health of the session after a successful request.
+    pub fn record_success(&self, account: &str) {
+        self.health.lock().unwrap().remove(account);
+    }
+
+    /// Lower the health of the session after a failed request, and bench it on failures in a row.
+    pub fn record_failure(&self, account: &str) {
+        let mut health = self.health.lock().unwrap();
+        let entry = health.entry(account.to_string()).or_default();
+
+        entry.failures += 1;
+        if entry.failures >= BENCH_FAILURES {
+            entry.benched_until = Some(Instant::now() + BENCH_TIME);
+        }
+    }
+
+    fn encode(&self, session: &Session) -> Result<Vec<u8>> {
+        seal(&encode_session(session, self.compress)?, self.key.as_ref())
+    }
+
+    fn decode(&self, value: &[u8]) -> Result<Session> {
+        decode_session(&unseal(value, self.key.as_ref())?)
+    }
+
+    /// Write all sessions to a file, encrypted if the storage has a key, so that another agent can
+    /// load them and skip logging in again.
+    pub fn save_to<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let sessions = bincode::serialize(&self.all()?)?;
+        std::fs::write(path, seal(&sessions, self.key.as_ref())?)?;
         Ok(())
     }
 
-    pub fn len(&self) -> usize {
-        self.db.len()
+    /// Insert sessions written by `save_to`, overwriting ones of the same account. Return the count.
+    pub fn load_from<P: AsRef<Path>>(&mut self, path: P) -> Result<usize> {
+        let content = std::fs::read(path)?;
+        let sessions: Vec<Session> = bincode::deserialize(&unseal(&content, self.key.as_ref())?)?;
+        for session in &sessions {
+            self.insert(session)?;
+        }
+        Ok(sessions.len())
+    }
+
+    pub fn clear(&mut self) -> Result<()> {
+        self.backend.clear()
+    }
+
+    pub fn len(&self) -> Result<usize> {
+        self.backend.len()
     }
 }
 
@@ -123,6 +426,122 @@ impl SessionStorage {
 //     }
 // }
 
+/// A client built on the session of an account. The session is written back to the storage when the
+/// guard is dropped, so that a refreshed session is not lost when a handler returns early on error.
+pub struct SessionGuard {
+    client: UserClient,
+    storage: SessionStorage,
+    /// Whether the session is loaded from the storage, or created for a new account.
+    stored: bool,
+    /// Whether the session has been saved explicitly.
+    saved: bool,
+    /// Released after the session is stored back on drop.
+    _lease: Lease,
+}
+
+impl SessionGuard {
+    /// Load the session of the account, or create a new one if the account is not stored or the
+    /// password changed. Wait if another request is using the session.
+    pub async fn new(
+        storage: &SessionStorage,
+        raw_client: &reqwest::Client,
+        account: &str,
+        password: &str,
+    ) -> Result<Self> {
+        let lease = storage.lease(account).await?;
+        let stored = storage.query(account)?.filter(|s| s.password == password);
+        let is_stored = stored.is_some();
+        let session = stored.unwrap_or_else(|| Session::new(account, password));
+
+        Ok(Self::with_session(storage, raw_client, session, is_stored, lease))
+    }
+
+    /// Choose a stored session randomly, for pages any student can visit. `None` if all sessions
+    /// are in use.
+    pub fn random(storage: &mut SessionStorage, raw_client: &reqwest::Client) -> Result<Option<Self>> {
+        Self::random_of_college(storage, raw_client, None)
+    }
+
+    /// Choose a stored session, preferring one of the college if given.
+    pub fn random_of_college(
+        storage: &mut SessionStorage,
+        raw_client: &reqwest::Client,
+        college: Option<&str>,
+    ) -> Result<Option<Self>> {
+        for _ in 0..CHECKOUT_ATTEMPTS {
+            let session = match college {
+                Some(college) => storage.choose_by_college(college)?,
+                None => storage.choose_randomly()?,
+            };
+            let session = match session {
+                Some(session) => session,
+                None => return Ok(None),
+            };
+            // Leased by another request after chosen.
+            if let Some(lease) = storage.try_lease(&session.account) {
+                return Ok(Some(Self::with_session(storage, raw_client, session, true, lease)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn with_session(
+        storage: &SessionStorage,
+        raw_client: &reqwest::Client,
+        session: Session,
+        stored: bool,
+        lease: Lease,
+    ) -> Self {
+        let raw_client = storage.client_of(&session.account, raw_client);
+        let mut client = UserClient::new(session, raw_client);
+        client.set_retry_policy(storage.retry_policy);
+        if let Some(timeout) = storage.timeout {
+            client.set_timeout(timeout);
+        }
+        Self {
+            client,
+            storage: storage.clone(),
+            stored,
+            saved: false,
+            _lease: lease,
+        }
+    }
+
+    /// Save the session now. A new session is only stored this way, so that credentials never
+    /// succeeded are not kept. The session is marked as updated, which keeps it from expiring.
+    pub fn save(&mut self) -> Result<()> {
+        self.client.session.last_update = Utc::now().naive_utc();
+        self.storage.insert(&self.client.session)?;
+        self.storage.record_success(&self.client.session.account);
+        self.saved = true;
+        Ok(())
+    }
+}
+
+impl Deref for SessionGuard {
+    type Target = UserClient;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
+impl DerefMut for SessionGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.client
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        if self.stored && !self.saved {
+            // Nothing to do with the error in drop, the session will be refreshed next time.
+            let _ = self.storage.insert(&self.client.session);
+            self.storage.record_failure(&self.client.session.account);
+        }
+    }
+}
+
 pub type AccountCookies = HashMap<String, HashMap<String, String>>;
 
 /// Campus account login session
@@ -137,6 +556,116 @@ pub struct Session {
     pub cookies: AccountCookies,
     /// Last use time.
     pub last_update: NaiveDateTime,
+    /// College of the student, parsed from the profile.
+    pub college: Option<String>,
+}
+
+/// Session stored before `college` is added.
+#[derive(Deserialize)]
+struct LegacySession {
+    account: String,
+    password: String,
+    cookies: AccountCookies,
+    last_update: NaiveDateTime,
+}
+
+/// Read the session key from the environment, if set.
+fn session_key_from_env() -> Result<Option<SecretKey>> {
+    let encoded = match std::env::var(SESSION_KEY_ENV) {
+        Ok(encoded) => encoded,
+        Err(_) => return Ok(None),
+    };
+    let bad_key = || AgentError::BadConfig(format!("{} 应为 32 字节密钥的 base64", SESSION_KEY_ENV));
+
+    let bytes = base64::decode(encoded.trim()).map_err(|_| bad_key())?;
+    let key: SecretKey = bytes.as_slice().try_into().map_err(|_| bad_key())?;
+    Ok(Some(key))
+}
+
+fn aead_key(key: &SecretKey) -> Result<LessSafeKey> {
+    let key = UnboundKey::new(&AES_256_GCM, key).map_err(|_| AgentError::SessionCrypto)?;
+    Ok(LessSafeKey::new(key))
+}
+
+/// Encrypt with AES-256-GCM by a random nonce, which is prepended to the ciphertext and its tag.
+pub(crate) fn encrypt(key: &SecretKey, plain: &[u8]) -> Result<Vec<u8>> {
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let mut sealed = plain.to_vec();
+    aead_key(key)?
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut sealed)
+        .map_err(|_| AgentError::SessionCrypto)?;
+
+    let mut encrypted = nonce.to_vec();
+    encrypted.extend(sealed);
+    Ok(encrypted)
+}
+
+/// Decrypt what `encrypt` returns. A value modified, or encrypted by another key, fails to
+/// authenticate and is rejected.
+pub(crate) fn decrypt(key: &SecretKey, encrypted: &[u8]) -> Result<Vec<u8>> {
+    if encrypted.len() < NONCE_LEN + AES_256_GCM.tag_len() {
+        return Err(AgentError::SessionCrypto.into());
+    }
+    let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| AgentError::SessionCrypto)?;
+
+    let mut buffer = ciphertext.to_vec();
+    let plain = aead_key(key)?
+        .open_in_place(nonce, Aad::empty(), &mut buffer)
+        .map_err(|_| AgentError::SessionCrypto)?;
+    Ok(plain.to_vec())
+}
+
+/// Encrypt the value if a key is given, and mark it.
+fn seal(value: &[u8], key: Option<&SecretKey>) -> Result<Vec<u8>> {
+    match key {
+        Some(key) => {
+            let mut sealed = ENCRYPTED_MAGIC.to_vec();
+            sealed.extend(encrypt(key, value)?);
+            Ok(sealed)
+        }
+        None => Ok(value.to_vec()),
+    }
+}
+
+/// Decrypt a value by `seal`, or return it as is if not encrypted.
+fn unseal(value: &[u8], key: Option<&SecretKey>) -> Result<Vec<u8>> {
+    match (value.strip_prefix(ENCRYPTED_MAGIC), key) {
+        (Some(encrypted), Some(key)) => decrypt(key, encrypted),
+        (Some(_), None) => Err(AgentError::SessionKeyRequired(SESSION_KEY_ENV).into()),
+        (None, _) => Ok(value.to_vec()),
+    }
+}
+
+/// Encode a session to store, compressed with deflate if `compress`.
+fn encode_session(session: &Session, compress: bool) -> Result<Vec<u8>> {
+    let value = bincode::serialize(session)?;
+    if !compress {
+        return Ok(value);
+    }
+    let mut compressed = COMPRESSED_MAGIC.to_vec();
+    compressed.extend(miniz_oxide::deflate::compress_to_vec(&value, 6));
+    Ok(compressed)
+}
+
+/// Decode a stored session, which may be compressed, or written by an older version.
+fn decode_session(value: &[u8]) -> Result<Session> {
+    if let Some(compressed) = value.strip_prefix(COMPRESSED_MAGIC) {
+        let value =
+            miniz_oxide::inflate::decompress_to_vec(compressed).map_err(|_| AgentError::BadSession)?;
+        return decode_session(&value);
+    }
+    if let Ok(session) = bincode::deserialize::<Session>(value) {
+        return Ok(session);
+    }
+    let legacy: LegacySession = bincode::deserialize(value)?;
+    Ok(Session {
+        account: legacy.account,
+        password: legacy.password,
+        cookies: legacy.cookies,
+        last_update: legacy.last_update,
+        college: None,
+    })
 }
 
 impl Session {
@@ -146,6 +675,7 @@ impl Session {
             password: password.to_string(),
             cookies: HashMap::default(),
             last_update: Utc::now().naive_utc(),
+            college: None,
         }
     }
 
@@ -162,7 +692,7 @@ impl Session {
         self.cookies = crate::service::portal_login(client, &self.account, &self.password)
             .await?
             .cookies;
-        self.last_update = Utc::now().naive_local();
+        self.last_update = Utc::now().naive_utc();
 
         Ok(())
     }
@@ -217,3 +747,362 @@ impl PartialEq<Session> for Session {
         self.account == other.account && self.password == other.password && self.cookies == other.cookies
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::{decode_session, Session, SessionBackend, SessionGuard, SessionStorage};
+    use crate::error::{AgentError, Result};
+    use crate::net::AccountProxies;
+
+    /// Sessions in memory, like a pool shared by agents.
+    #[derive(Debug, Default)]
+    struct MemorySessions {
+        values: Mutex<BTreeMap<String, Vec<u8>>>,
+    }
+
+    impl SessionBackend for MemorySessions {
+        fn get(&self, account: &str) -> Result<Option<Vec<u8>>> {
+            Ok(self.values.lock().unwrap().get(account).cloned())
+        }
+
+        fn insert(&self, account: &str, value: Vec<u8>) -> Result<()> {
+            self.values.lock().unwrap().insert(account.to_string(), value);
+            Ok(())
+        }
+
+        fn remove(&self, account: &str) -> Result<()> {
+            self.values.lock().unwrap().remove(account);
+            Ok(())
+        }
+
+        fn values(&self) -> Result<Vec<Vec<u8>>> {
+            Ok(self.values.lock().unwrap().values().cloned().collect())
+        }
+
+        fn clear(&self) -> Result<()> {
+            self.values.lock().unwrap().clear();
+            Ok(())
+        }
+
+        fn len(&self) -> Result<usize> {
+            Ok(self.values.lock().unwrap().len())
+        }
+    }
+
+    fn open_storage() -> SessionStorage {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        SessionStorage::open(db).unwrap()
+    }
+
+    /// A handler refreshing the session, and then failing before saving it.
+    async fn failing_handler(storage: &SessionStorage, account: &str, password: &str) -> Result<()> {
+        let mut client = SessionGuard::new(storage, &reqwest::Client::new(), account, password).await?;
+
+        let mut cookies = std::collections::HashMap::new();
+        cookies.insert(String::from("JSESSIONID"), String::from("refreshed"));
+        client.session.cookies.insert(String::from("sc.sit.edu.cn"), cookies);
+
+        let _score: f32 = "未评分".parse()?;
+        client.save()
+    }
+
+    #[tokio::test]
+    async fn test_session_guard_on_error() {
+        let mut storage = open_storage();
+        storage.insert(&Session::new("1910000000", "password")).unwrap();
+
+        assert!(failing_handler(&storage, "1910000000", "password").await.is_err());
+        let session = storage.query("1910000000").unwrap().unwrap();
+        assert_eq!(
+            session.query_cookie("sc.sit.edu.cn", "JSESSIONID").map(String::as_str),
+            Some("refreshed")
+        );
+
+        // A new account with a failed request is not stored.
+        assert!(failing_handler(&storage, "1910000001", "password").await.is_err());
+        assert!(storage.query("1910000001").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_choose_by_college() {
+        let mut storage = open_storage();
+
+        let mut science = Session::new("1910000000", "password");
+        science.college = Some(String::from("理学院"));
+        let mut chemistry = Session::new("1910000001", "password");
+        chemistry.college = Some(String::from("化学与环境工程学院"));
+        storage.insert(&science).unwrap();
+        storage.insert(&chemistry).unwrap();
+
+        for _ in 0..10 {
+            let session = storage.choose_by_college("理学院").unwrap().unwrap();
+            assert_eq!(session.account, "1910000000");
+        }
+        // Any session is fine if no one matches.
+        assert!(storage.choose_by_college("艺术与设计学院").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_encrypted_session() {
+        let key = [7u8; 32];
+        let mut session = Session::new("1910000000", "password");
+        let mut cookies = HashMap::new();
+        cookies.insert(String::from("JSESSIONID"), String::from("0123456789"));
+        session.cookies.insert(String::from("sc.sit.edu.cn"), cookies);
+
+        let mut storage = open_storage().with_key(key);
+        storage.insert(&session).unwrap();
+        let value = storage.backend.get(&session.account).unwrap().unwrap();
+        assert!(!value.windows(10).any(|x| x == b"0123456789"));
+        assert_eq!(storage.query("1910000000").unwrap(), Some(session.clone()));
+
+        // Not readable without the key, or by a wrong one.
+        let storage = SessionStorage::with_backend(storage.backend.clone()).unwrap();
+        assert!(storage.query("1910000000").is_err());
+        let storage = storage.with_key([8u8; 32]);
+        let error = storage.query("1910000000").unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(AgentError::SessionCrypto)));
+
+        // A modified value fails to authenticate.
+        let mut tampered = value.clone();
+        let last = tampered.len() - 20;
+        tampered[last] ^= 1;
+        storage.backend.insert("1910000000", tampered).unwrap();
+        let storage = storage.with_key(key);
+        assert!(storage.query("1910000000").is_err());
+        storage.backend.insert("1910000000", value).unwrap();
+        assert_eq!(storage.query("1910000000").unwrap(), Some(session));
+    }
+
+    #[test]
+    fn test_shared_backend() {
+        // Two agents on one backend.
+        let backend = Arc::new(MemorySessions::default());
+        let mut agent_a = SessionStorage::with_backend(backend.clone()).unwrap();
+        let mut agent_b = SessionStorage::with_backend(backend).unwrap();
+        assert!(agent_b.choose_randomly().unwrap().is_none());
+
+        agent_a.insert(&Session::new("1910000000", "password")).unwrap();
+        let session = agent_b.choose_randomly().unwrap().unwrap();
+        assert_eq!(session.account, "1910000000");
+        assert_eq!(agent_b.query_or("1910000000", "password").unwrap(), session);
+        assert_eq!(agent_b.len().unwrap(), 1);
+
+        agent_b.clear().unwrap();
+        assert!(agent_a.query("1910000000").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_session_ttl() {
+        let mut storage = open_storage().with_ttl(std::time::Duration::from_secs(3600));
+
+        let mut stale = Session::new("1910000000", "password");
+        stale.last_update -= chrono::Duration::hours(2);
+        let mut cookies = HashMap::new();
+        cookies.insert(String::from("JSESSIONID"), String::from("expired"));
+        stale.cookies.insert(String::from("sc.sit.edu.cn"), cookies);
+        storage.insert(&stale).unwrap();
+
+        // Cookies are cleared when chosen, and the account is kept to log in again.
+        let session = storage.choose_randomly().unwrap().unwrap();
+        assert_eq!(session.account, "1910000000");
+        assert!(session.cookies.is_empty());
+        let stored = storage.backend.get("1910000000").unwrap().unwrap();
+        let stored = storage.decode(&stored).unwrap();
+        assert!(stored.cookies.is_empty());
+        assert_eq!(stored.password, "password");
+
+        storage.insert(&stale).unwrap();
+        storage.insert(&Session::new("1910000001", "password")).unwrap();
+        let session = storage.query_or("1910000000", "password").unwrap();
+        assert!(session.cookies.is_empty());
+        let fresh = storage.query("1910000001").unwrap();
+        assert_eq!(fresh, Some(Session::new("1910000001", "password")));
+        assert_eq!(storage.len().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_session_lease() {
+        let mut storage = open_storage();
+        storage.insert(&Session::new("1910000000", "password")).unwrap();
+        storage.insert(&Session::new("1910000001", "password")).unwrap();
+        let client = reqwest::Client::new();
+
+        let first = SessionGuard::random(&mut storage, &client).unwrap().unwrap();
+        let second = SessionGuard::random(&mut storage, &client).unwrap().unwrap();
+        assert_ne!(first.session.account, second.session.account);
+        // All in use.
+        assert!(SessionGuard::random(&mut storage, &client).unwrap().is_none());
+
+        // Returned on drop.
+        let account = first.session.account.clone();
+        drop(first);
+        let third = SessionGuard::random(&mut storage, &client).unwrap().unwrap();
+        assert_eq!(third.session.account, account);
+
+        // A request on the account waits until the session is returned.
+        let waiting = {
+            let storage = storage.clone();
+            let account = account.clone();
+            tokio::spawn(async move {
+                let client = reqwest::Client::new();
+                SessionGuard::new(&storage, &client, &account, "password").await.is_ok()
+            })
+        };
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+        drop(third);
+        assert!(waiting.await.unwrap());
+    }
+
+    #[test]
+    fn test_session_health() {
+        let mut storage = open_storage();
+        storage.insert(&Session::new("1910000000", "password")).unwrap();
+        storage.insert(&Session::new("1910000001", "password")).unwrap();
+        let count_chosen = |storage: &mut SessionStorage| {
+            let mut chosen = 0;
+            for _ in 0..1000 {
+                if storage.choose_randomly().unwrap().unwrap().account == "1910000000" {
+                    chosen += 1;
+                }
+            }
+            chosen
+        };
+
+        // Chosen a quarter as often as the healthy one, after two failures.
+        storage.record_failure("1910000000");
+        storage.record_failure("1910000000");
+        let chosen = count_chosen(&mut storage);
+        assert!(chosen > 50 && chosen < 400);
+
+        // Benched.
+        storage.record_failure("1910000000");
+        assert_eq!(count_chosen(&mut storage), 0);
+
+        // Any session is chosen if all are benched.
+        for _ in 0..3 {
+            storage.record_failure("1910000001");
+        }
+        assert!(storage.choose_randomly().unwrap().is_some());
+
+        storage.record_success("1910000000");
+        assert_eq!(count_chosen(&mut storage), 1000);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("kite-sessions-{}", uuid::Uuid::new_v4()));
+        let key = [7u8; 32];
+
+        let mut old = open_storage().with_key(key);
+        old.insert(&Session::new("1910000000", "password")).unwrap();
+        old.insert(&Session::new("1910000001", "password")).unwrap();
+        old.save_to(&path).unwrap();
+
+        let mut new = open_storage().with_key(key);
+        assert_eq!(new.load_from(&path).unwrap(), 2);
+        assert_eq!(new.all().unwrap(), old.all().unwrap());
+        // The file is encrypted too.
+        assert!(open_storage().load_from(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_legacy_session() {
+        #[derive(serde::Serialize)]
+        struct LegacySession {
+            account: String,
+            password: String,
+            cookies: super::AccountCookies,
+            last_update: chrono::NaiveDateTime,
+        }
+        let session = Session::new("1910000000", "password");
+        let legacy = LegacySession {
+            account: session.account.clone(),
+            password: session.password.clone(),
+            cookies: session.cookies.clone(),
+            last_update: session.last_update,
+        };
+
+        let decoded = decode_session(&bincode::serialize(&legacy).unwrap()).unwrap();
+        assert_eq!(decoded, session);
+        assert_eq!(decoded.college, None);
+    }
+
+    #[test]
+    fn test_compressed_session() {
+        let mut session = Session::new("1910000000", "password");
+        for domain in &["sc.sit.edu.cn", "authserver.sit.edu.cn", "jwxt.sit.edu.cn"] {
+            let mut cookies = std::collections::HashMap::new();
+            cookies.insert(String::from("JSESSIONID"), String::from("0123456789ABCDEF0123456789ABCDEF"));
+            cookies.insert(String::from("route"), String::from("0123456789abcdef0123456789abcdef"));
+            session.cookies.insert(domain.to_string(), cookies);
+        }
+        let mut plain = open_storage();
+        plain.insert(&session).unwrap();
+        let mut compressed = open_storage().with_compression(true);
+        compressed.insert(&session).unwrap();
+
+        let value_in = |storage: &SessionStorage| storage.backend.get(&session.account).unwrap().unwrap();
+        let plain_size = value_in(&plain).len();
+        let compressed_size = value_in(&compressed).len();
+        assert!(compressed_size < plain_size);
+        assert_eq!(compressed.query("1910000000").unwrap(), Some(session.clone()));
+
+        // Sessions stored without compression are still readable.
+        let mut mixed = plain.with_compression(true);
+        mixed.insert(&Session::new("1910000001", "password")).unwrap();
+        assert_eq!(mixed.query("1910000000").unwrap(), Some(session));
+        assert!(mixed.query("1910000001").unwrap().is_some());
+    }
+
+    /// Answer any request relayed with the name of the proxy.
+    async fn serve_proxy(listener: TcpListener, name: &'static str) {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 4096];
+                let _ = stream.read(&mut buf).await;
+
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    name.len(),
+                    name
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn test_account_proxies() {
+        let accounts = [("1910000000", "proxy-a"), ("1910000001", "proxy-b")];
+        let mut proxies = HashMap::new();
+        for &(account, name) in accounts.iter() {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy = format!("http://{}", listener.local_addr().unwrap());
+            proxies.insert(account.to_string(), proxy);
+            tokio::spawn(serve_proxy(listener, name));
+        }
+        let proxies = AccountProxies::new(&proxies, reqwest::Client::builder).unwrap();
+        let storage = open_storage().with_proxies(proxies);
+        let default = reqwest::Client::new();
+
+        for &(account, name) in accounts.iter() {
+            let client = SessionGuard::new(&storage, &default, account, "password").await.unwrap();
+            let request = client.raw_client.get("http://sc.sit.edu.cn/");
+            let body = request.send().await.unwrap().text().await.unwrap();
+            assert_eq!(body, name);
+        }
+        // Accounts without a designated proxy use the default client.
+        assert!(std::ptr::eq(storage.client_of("1910000002", &default), &default));
+    }
+}
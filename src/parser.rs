@@ -3,25 +3,89 @@ pub use edu::{
     parse_major_list_page, parse_profile_page, parse_score_list_page, parse_timetable_page,
 };
 pub use edu::{Class, Course, Major, Profile, SchoolYear, Score, ScoreDetail, SelectCourse, Semester};
+pub use charset::decode_body;
 pub use expense::{ExpensePage,ExpenseRecord,PageInfo};
+pub use portal::{LoginForm, PortalProfile};
 pub use sc::{
-    get_my_activity_list, get_my_score_list, Activity, ActivityDetail, JoinedActivity, ScActivityItem,
-    ScImages, ScScoreItem, ScScoreSummary,
+    count_category_credits, dedup_activities, diff_score_items, filter_by_credit, filter_by_date,
+    get_error_message, get_my_activity_list, get_my_score_list, get_pending_evaluation_list,
+    get_score_terms, group_by_term, is_blank_list_page, is_busy_page, is_login_page, login_return_url,
+    parse_activity_list, Activity, ActivityBrief, ActivityDetail, ActivityStatus, DedupStrategy,
+    DescriptionFormat, ImageFetchStatus, ListTotal, ScActivityComments, ScActivityItem, ScCancelResult,
+    ScCategoryCredit, ScCategoryTotal, ScImages, ScJoinResult, ScRanking, ScScoreDiff, ScScoreItem,
+    ScScoreSummary, ScTermCredit, ScTranscript, VolunteerHours,
 };
 
 pub use library::{HoldingPreviews, SearchLibraryResult};
 
 use crate::error::Result;
 
+/// Version of the parsers output, bumped when any parser changes the shape of its output. It is sent
+/// with each response, so that clients can tell which parsers produced the data.
+pub const PARSER_VERSION: u32 = 11;
+
+mod charset;
 mod edu;
 mod expense;
 mod library;
+mod portal;
 mod sc;
 
 pub trait Parse {
     fn from_html(html_page: &str) -> Result<Self>
     where
         Self: std::marker::Sized;
+
+    /// Parse the JSON which some AJAX endpoints return for the same data.
+    fn from_json(_json: &str) -> Result<Self>
+    where
+        Self: std::marker::Sized,
+    {
+        Err(ParserError::UnsupportedFormat(String::from("json")).into())
+    }
+}
+
+/// Whether the Content-Type declares a JSON body, like `application/json;charset=UTF-8`.
+pub fn is_json_content(content_type: Option<&str>) -> bool {
+    content_type
+        .map(|x| x.split(';').next().unwrap_or_default().trim().ends_with("json"))
+        .unwrap_or(false)
+}
+
+/// Parse the response body by its Content-Type, as HTML if not declared.
+pub fn parse_body<T: Parse>(body: &str, content_type: Option<&str>) -> Result<T> {
+    if is_json_content(content_type) {
+        T::from_json(body)
+    } else {
+        T::from_html(body)
+    }
+}
+
+/// Rows of a list parsed one by one, so that a malformed row does not fail the whole list.
+#[derive(Debug)]
+pub struct ParsedRows<T> {
+    pub rows: Vec<T>,
+    /// Why each row skipped failed to parse.
+    pub warnings: Vec<String>,
+}
+
+impl<T> ParsedRows<T> {
+    /// Keep rows parsed, and skip the others with a warning. Fail on the first malformed row instead
+    /// if `strict`.
+    pub fn collect(rows: impl IntoIterator<Item = Result<T>>, strict: bool) -> Result<Self> {
+        let mut result = ParsedRows {
+            rows: Vec::new(),
+            warnings: Vec::new(),
+        };
+        for (i, row) in rows.into_iter().enumerate() {
+            match row {
+                Ok(row) => result.rows.push(row),
+                Err(e) if strict => return Err(e),
+                Err(e) => result.warnings.push(format!("row {} skipped: {}", i + 1, e)),
+            }
+        }
+        Ok(result)
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -34,4 +98,6 @@ pub enum ParserError {
     MissingField,
     #[error("Invalid semester valid given.")]
     SemesterError,
+    #[error("不支持的响应格式: {0}")]
+    UnsupportedFormat(String),
 }
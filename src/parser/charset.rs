@@ -0,0 +1,81 @@
+use encoding_rs::{Encoding, GB18030, UTF_8};
+use regex::bytes::Regex;
+
+lazy_static! {
+    static ref META_CHARSET: Regex =
+        Regex::new(r#"(?i)<meta[^>]+charset\s*=\s*["']?([a-z0-9_-]+)"#).unwrap();
+}
+
+/// Bytes scanned for the charset declared in `<meta>`, which is required to be near the beginning.
+const META_SCAN_LEN: usize = 1024;
+
+/// Encoding of the `charset` parameter in the Content-Type, like `text/html;charset=GBK`.
+fn charset_of_content_type(content_type: &str) -> Option<&'static Encoding> {
+    content_type
+        .split(';')
+        .filter_map(|x| x.split_once('='))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, v)| Encoding::for_label(v.trim().trim_matches('"').as_bytes()))
+}
+
+/// Encoding declared by `<meta charset>` or `<meta http-equiv="Content-Type">` in the page.
+fn charset_of_meta(body: &[u8]) -> Option<&'static Encoding> {
+    let head = &body[..body.len().min(META_SCAN_LEN)];
+
+    META_CHARSET
+        .captures(head)
+        .and_then(|c| Encoding::for_label(&c[1]))
+}
+
+/// Decode the response body to text. The charset in the Content-Type is tried first, then the one
+/// in `<meta>`, skipping any the body does not decode by. Otherwise the body is taken as UTF-8 if
+/// valid, or GB18030, a superset of GBK and GB2312, which older pages of the school use.
+pub fn decode_body(body: &[u8], content_type: Option<&str>) -> String {
+    let declared = content_type.and_then(charset_of_content_type);
+
+    for encoding in declared.into_iter().chain(charset_of_meta(body)) {
+        if let Some(text) = encoding.decode_without_bom_handling_and_without_replacement(body) {
+            return text.into_owned();
+        }
+    }
+    let encoding = if std::str::from_utf8(body).is_ok() {
+        UTF_8
+    } else {
+        GB18030
+    };
+    encoding.decode_without_bom_handling(body).0.into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::decode_body;
+    use crate::parser::get_error_message;
+
+    #[test]
+    fn test_decode_gbk() {
+        let expected = std::fs::read_to_string("html/第二课堂错误页面.html")
+            .unwrap()
+            .replace("charset=utf-8", "charset=gbk");
+        let body = std::fs::read("html/第二课堂错误页面_GBK.html").unwrap();
+
+        // Declared right, declared wrong, and not declared in the header.
+        assert_eq!(decode_body(&body, Some("text/html;charset=GBK")), expected);
+        assert_eq!(decode_body(&body, Some("text/html;charset=UTF-8")), expected);
+        assert_eq!(decode_body(&body, None), expected);
+        assert_eq!(
+            get_error_message(&decode_body(&body, None)).as_deref(),
+            Some("对不起，该活动不存在或已被删除！")
+        );
+
+        // Without the meta tag either.
+        let plain = expected.replace("charset=gbk", "");
+        let (body, _, _) = encoding_rs::GBK.encode(&plain);
+        assert_eq!(decode_body(&body, None), plain);
+
+        let body = std::fs::read("html/第二课堂错误页面.html").unwrap();
+        assert_eq!(
+            decode_body(&body, Some("text/html")),
+            String::from_utf8(body).unwrap()
+        );
+    }
+}
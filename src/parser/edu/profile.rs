@@ -26,6 +26,8 @@ pub struct Profile {
     enrollment_date: String,
     /// 学生类型
     types: String,
+    /// 学院
+    pub college: Option<String>,
 }
 
 static ELEMENTS: [(&str, &str); 11] = [
@@ -42,6 +44,9 @@ static ELEMENTS: [(&str, &str); 11] = [
     ("type", "#col_xslxdm > p:nth-child(1)"),
 ];
 
+/// College is not shown for some students, so it's optional.
+const COLLEGE_ELEMENT: &str = "#col_jg_id > p:nth-child(1)";
+
 pub fn parse_profile_page(text: &str) -> Result<Profile> {
     use scraper::{Html, Selector};
 
@@ -57,6 +62,12 @@ pub fn parse_profile_page(text: &str) -> Result<Profile> {
             .ok_or(ParserError::MissingField)?;
         values.push(value);
     }
+    let college = pages
+        .select(&Selector::parse(COLLEGE_ELEMENT).unwrap())
+        .next()
+        .map(|x| x.inner_html().trim().to_string())
+        .filter(|x| !x.is_empty());
+
     // It can be true that element.len() == ELEMENTS.len().
     let profile = Profile {
         student_no: Clone::clone(&values[0]),
@@ -70,6 +81,7 @@ pub fn parse_profile_page(text: &str) -> Result<Profile> {
         hometown: Clone::clone(&values[8]),
         enrollment_date: Clone::clone(&values[9]),
         types: Clone::clone(&values[10]),
+        college,
     };
     Ok(profile)
 }
@@ -0,0 +1,163 @@
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use serde::Serialize;
+
+use crate::error::Result;
+use crate::parser::{Parse, ParserError};
+
+lazy_static! {
+    static ref BIND_MOBILE: Selector = Selector::parse("#bindMobile").unwrap();
+    static ref BIND_EMAIL: Selector = Selector::parse("#bindEmail").unwrap();
+    static ref FORM: Selector = Selector::parse("form").unwrap();
+    static ref INPUT: Selector = Selector::parse("input").unwrap();
+    static ref SALT_INPUT: Selector =
+        Selector::parse("#pwdEncryptSalt, #pwdDefaultEncryptSalt").unwrap();
+    static ref SALT_VAR: Regex = Regex::new(r#"pwdDefaultEncryptSalt\s*=\s*"(.*?)""#).unwrap();
+}
+
+/// Contact info bound to the SSO account. Both fields are masked by authserver, e.g. `138****5678`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PortalProfile {
+    /// Bound email
+    pub email: Option<String>,
+    /// Bound mobile phone
+    pub phone: Option<String>,
+}
+
+/// Select the text of the element, and treat "未绑定" or a blank value as no contact.
+fn select_bound_value(document: &Html, selector: &Selector) -> Option<String> {
+    document
+        .select(selector)
+        .next()
+        .map(|e| e.text().collect::<String>().trim().to_string())
+        .filter(|s| !s.is_empty() && s != "未绑定")
+}
+
+impl Parse for PortalProfile {
+    fn from_html(html_page: &str) -> Result<Self> {
+        let document = Html::parse_document(html_page);
+
+        Ok(PortalProfile {
+            email: select_bound_value(&document, &BIND_EMAIL),
+            phone: select_bound_value(&document, &BIND_MOBILE),
+        })
+    }
+}
+
+/// A form on the pages of login flow, either the login form or a form to continue the flow, whose
+/// fields are read from the page instead of hard-coded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoginForm {
+    /// Form action, which may be relative to the page.
+    pub action: String,
+    /// Hidden fields to submit as they are.
+    pub fields: Vec<(String, String)>,
+    /// Name of the user name input.
+    pub username_field: Option<String>,
+    /// Name of the password input, or None if the form is not to log in.
+    pub password_field: Option<String>,
+    /// Salt to encrypt the password.
+    pub salt: Option<String>,
+}
+
+fn input_of_type(form: ElementRef, types: &[&str]) -> Option<String> {
+    form.select(&INPUT)
+        .map(|e| e.value())
+        .find(|e| types.contains(&e.attr("type").unwrap_or("text")))
+        .and_then(|e| e.attr("name"))
+        .map(ToString::to_string)
+}
+
+impl Parse for LoginForm {
+    fn from_html(html_page: &str) -> Result<Self> {
+        let document = Html::parse_document(html_page);
+        // The form with a password input, or the first form.
+        let form = document
+            .select(&FORM)
+            .find(|f| input_of_type(*f, &["password"]).is_some())
+            .or_else(|| document.select(&FORM).next())
+            .ok_or_else(|| ParserError::NoSuchElement(String::from("form")))?;
+
+        let fields = form
+            .select(&INPUT)
+            .map(|e| e.value())
+            .filter(|e| e.attr("type") == Some("hidden"))
+            .filter_map(|e| {
+                Some((
+                    e.attr("name")?.to_string(),
+                    e.attr("value").unwrap_or("").to_string(),
+                ))
+            })
+            .collect();
+        let salt = document
+            .select(&SALT_INPUT)
+            .next()
+            .and_then(|e| e.value().attr("value"))
+            .map(ToString::to_string)
+            .or_else(|| SALT_VAR.captures(html_page).map(|c| c[1].to_string()));
+
+        Ok(LoginForm {
+            action: form.value().attr("action").unwrap_or("").trim().to_string(),
+            fields,
+            username_field: input_of_type(form, &["text"]),
+            password_field: input_of_type(form, &["password"]),
+            salt,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LoginForm, Parse, PortalProfile};
+
+    #[test]
+    fn test_portal_profile() {
+        let html_page = std::fs::read_to_string("html/统一认证个人资料页面.html").unwrap();
+        let profile = PortalProfile::from_html(&html_page).unwrap();
+
+        assert_eq!(profile.phone.as_deref(), Some("138****5678"));
+        assert_eq!(profile.email.as_deref(), Some("sun***@yeah.net"));
+    }
+
+    #[test]
+    fn test_portal_profile_unbound() {
+        let html_page = std::fs::read_to_string("html/统一认证个人资料页面.html")
+            .unwrap()
+            .replace("138****5678", "未绑定")
+            .replace("sun***@yeah.net", "");
+        let profile = PortalProfile::from_html(&html_page).unwrap();
+
+        assert_eq!(profile, PortalProfile { email: None, phone: None });
+    }
+
+    #[test]
+    fn test_login_form() {
+        let html_page = std::fs::read_to_string("html/统一认证登录页面.html").unwrap();
+        let form = LoginForm::from_html(&html_page).unwrap();
+
+        assert_eq!(form.username_field.as_deref(), Some("username"));
+        assert_eq!(form.password_field.as_deref(), Some("password"));
+        assert_eq!(form.salt.as_deref(), Some("rjBQOUmAPp4qJ9dA"));
+        assert!(form
+            .fields
+            .contains(&(String::from("lt"), String::from("LT-123456-abcdefg-cas"))));
+
+        let html_page = std::fs::read_to_string("html/统一认证登录页面_新版.html").unwrap();
+        let form = LoginForm::from_html(&html_page).unwrap();
+
+        assert_eq!(form.salt.as_deref(), Some("kX3fU8pQ2mN7rT1v"));
+        assert!(form
+            .fields
+            .contains(&(String::from("execution"), String::from("e2s1-8f1c2a7d"))));
+
+        let html_page = std::fs::read_to_string("html/统一认证登录跳转页面.html").unwrap();
+        let form = LoginForm::from_html(&html_page).unwrap();
+
+        assert_eq!(form.password_field, None);
+        assert_eq!(
+            form.action,
+            "https://authserver.sit.edu.cn/authserver/upgrade/callback"
+        );
+        assert_eq!(form.fields.len(), 2);
+    }
+}
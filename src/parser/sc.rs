@@ -1,7 +1,26 @@
-pub use detail::{ActivityDetail, ScImages};
-pub use list::{Activity, JoinedActivity};
-pub use score::{get_my_activity_list, get_my_score_list, ScActivityItem, ScScoreItem, ScScoreSummary};
+pub use comment::ScActivityComments;
+pub use detail::{
+    ActivityBrief, ActivityDetail, DescriptionFormat, ImageFetchStatus, ScCancelResult, ScImages,
+    ScJoinResult,
+};
+pub use error_page::{get_error_message, is_busy_page};
+pub use list::{
+    dedup_activities, filter_by_credit, filter_by_date, is_blank_list_page, parse_activity_list,
+    Activity, ActivityStatus, DedupStrategy, ListTotal,
+};
+pub use ranking::ScRanking;
+pub use score::{
+    count_category_credits, diff_score_items, get_my_activity_list, get_my_score_list,
+    get_pending_evaluation_list, get_score_terms, group_by_term, is_login_page, login_return_url,
+    ScActivityItem, ScCategoryCredit, ScCategoryTotal, ScScoreDiff, ScScoreItem, ScScoreSummary,
+    ScTermCredit, ScTranscript,
+};
+pub use volunteer::VolunteerHours;
 
+mod comment;
 mod detail;
+mod error_page;
 mod list;
+mod ranking;
 mod score;
+mod volunteer;
@@ -0,0 +1,119 @@
+use std::cmp::Reverse;
+
+use chrono::{DateTime, Local};
+use scraper::{ElementRef, Html, Selector};
+use serde::Serialize;
+
+use crate::error::Result;
+use crate::parser::Parse;
+
+use super::detail::try_parse_date_time;
+
+lazy_static! {
+    static ref COMMENT: Selector = Selector::parse("ul.comment_list > li").unwrap();
+    static ref AUTHOR: Selector = Selector::parse(".comment_author").unwrap();
+    static ref TIME: Selector = Selector::parse(".comment_time").unwrap();
+    static ref CONTENT: Selector = Selector::parse(".comment_content").unwrap();
+    static ref TOTAL: Selector = Selector::parse("#count").unwrap();
+    static ref CLOSED: Selector = Selector::parse("#comment_closed").unwrap();
+}
+
+/// A student comment under an activity.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScActivityComment {
+    /// Author name, masked like "李**"
+    pub author: String,
+    pub content: String,
+    pub posted_at: DateTime<Local>,
+}
+
+/// A page of the comment thread.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScActivityComments {
+    /// Whether the activity accepts comments. No comment is listed if not.
+    pub enabled: bool,
+    /// Count of comments on all pages.
+    pub total: u32,
+    /// Comments on the page, the latest first.
+    pub comments: Vec<ScActivityComment>,
+}
+
+fn text_of(e: ElementRef) -> String {
+    e.text().collect::<String>().trim().to_string()
+}
+
+fn parse_comment(item: ElementRef) -> Option<ScActivityComment> {
+    let field = |selector: &Selector| item.select(selector).next().map(text_of);
+
+    Some(ScActivityComment {
+        author: field(&AUTHOR)?,
+        content: field(&CONTENT)?,
+        posted_at: try_parse_date_time(&field(&TIME)?)?,
+    })
+}
+
+impl Parse for ScActivityComments {
+    fn from_html(html_page: &str) -> Result<Self> {
+        let document = Html::parse_document(html_page);
+
+        // Comments disabled by the organizer, "该活动未开放评论".
+        if document.select(&CLOSED).next().is_some() {
+            return Ok(ScActivityComments {
+                enabled: false,
+                total: 0,
+                comments: Vec::new(),
+            });
+        }
+        let mut comments: Vec<ScActivityComment> =
+            document.select(&COMMENT).filter_map(parse_comment).collect();
+        // The page is not always in time order.
+        comments.sort_by_key(|x| Reverse(x.posted_at));
+        let total = document
+            .select(&TOTAL)
+            .next()
+            .and_then(|e| text_of(e).parse().ok())
+            .unwrap_or(comments.len() as u32);
+
+        Ok(ScActivityComments {
+            enabled: true,
+            total,
+            comments,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{try_parse_date_time, Parse, ScActivityComments};
+
+    #[test]
+    fn test_activity_comments() {
+        let html_page = std::fs::read_to_string("html/第二课堂活动评论页面.html").unwrap();
+        let page = ScActivityComments::from_html(&html_page).unwrap();
+
+        assert!(page.enabled);
+        assert_eq!(page.total, 23);
+        let authors: Vec<&str> = page.comments.iter().map(|x| x.author.as_str()).collect();
+        assert_eq!(authors, vec!["王**", "李**", "张**"]);
+        assert_eq!(page.comments[0].content, "评审结果什么时候公布？");
+        assert_eq!(
+            Some(page.comments[0].posted_at),
+            try_parse_date_time("2020-05-30 09:15:02")
+        );
+    }
+
+    #[test]
+    fn test_comments_disabled() {
+        let html_page = std::fs::read_to_string("html/第二课堂活动评论页面.html").unwrap();
+        let (head, rest) = html_page.split_once("<ul class=\"comment_list\">").unwrap();
+        let (_, tail) = rest.split_once("</ul>").unwrap();
+        let html_page = format!(
+            "{}<div id=\"comment_closed\">该活动未开放评论</div>{}",
+            head, tail
+        );
+        let page = ScActivityComments::from_html(&html_page).unwrap();
+
+        assert!(!page.enabled);
+        assert!(page.comments.is_empty());
+    }
+}
@@ -1,7 +1,7 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use base64::decode;
-use chrono::{DateTime, FixedOffset, Local, TimeZone};
+use chrono::{DateTime, Duration, FixedOffset, Local, TimeZone};
 use regex::Regex;
 use scraper::{ElementRef, Html, Selector};
 
@@ -15,13 +15,25 @@ lazy_static! {
     static ref RE_DESCRIPTION_SPACES: Regex = Regex::new(r"\s+").unwrap();
     static ref RE_IMAGES: Regex = Regex::new(r#"<img(.*?)src="(.*?)""#).unwrap();
     static ref RE_IMAGES_BASE64: Regex = Regex::new(r"([^,]+)$").unwrap();
+    // <a href="..._original.png"><img src="....png"></a>
+    static ref RE_ORIGINAL_LINK: Regex =
+        Regex::new(r#"<a[^>]*href="([^"]+\.(?i:png|jpe?g|gif|bmp))"[^>]*>\s*<img"#).unwrap();
+    static ref RE_ORIGINAL_ATTR: Regex = Regex::new(r#"<img[^>]*data-original="(.*?)""#).unwrap();
     static ref RE_IMAGES_FILE: Regex = Regex::new(r"(image/)\S+;").unwrap();
+    // 活动人限</span></strong><span><span style="...">：</span><span style="...">12人</span>
+    // <script>alert('申请成功，下面将为您跳转至我的活动页面！');location.href='...'</script>
+    static ref RE_ALERT: Regex = Regex::new(r"alert\('(.*?)'\)").unwrap();
+    static ref RE_CAPACITY: Regex = Regex::new(r"活动人限(?:<[^>]*>|\s)*：(?:<[^>]*>|\s)*(\d+)\s*人").unwrap();
     static ref SELECTOR_FRAME: Selector = Selector::parse(".box-1").unwrap();
     static ref SELECTOR_TITLE: Selector = Selector::parse("h1").unwrap();
     static ref SELECTOR_BANNER: Selector =
         Selector::parse("div[style=\" color:#7a7a7a; text-align:center\"]").unwrap();
     static ref SELECTOR_DESCRIPTION: Selector =
         Selector::parse("div[style=\"padding:30px 50px; font-size:14px;\"]").unwrap();
+    static ref SELECTOR_SHARE_POSTER: Selector = Selector::parse("div.share-poster img").unwrap();
+    static ref SELECTOR_APPLY: Selector = Selector::parse("input.ip-3").unwrap();
+    static ref SELECTOR_LINK: Selector = Selector::parse("a[href]").unwrap();
+    static ref SELECTOR_IMAGE: Selector = Selector::parse("img").unwrap();
 }
 
 /// Activity link, used for list recent activities.
@@ -35,10 +47,16 @@ pub struct ActivityDetail {
     pub title: String,
     /// Activity start date time
     pub start_time: DateTime<Local>,
-    /// Sign date time
+    /// Activity end date time, computed by the start time and duration.
+    pub end_time: Option<DateTime<Local>>,
+    /// Sign-in window start, or the activity start time if no separate window.
     pub sign_start_time: DateTime<Local>,
-    /// Activity end date time
+    /// Sign-in window end, or the activity end time if no separate window.
     pub sign_end_time: DateTime<Local>,
+    /// Start time as on the page, kept for debugging when it is malformed.
+    pub raw_start_time: String,
+    /// Sign-in window as on the page, if any.
+    pub raw_sign_time: Option<String>,
     /// Place
     pub place: Option<String>,
     /// Duration
@@ -51,10 +69,59 @@ pub struct ActivityDetail {
     pub organizer: Option<String>,
     /// Activity undertaker
     pub undertaker: Option<String>,
+    /// Count of students applied
+    pub applied: Option<u32>,
+    /// Max count of students
+    pub capacity: Option<u32>,
     /// Description in text[]
     pub description: String,
-    /// Image attachment.
+    /// Description split to paragraphs, if requested in `DescriptionFormat::Blocks`.
+    pub description_blocks: Option<Vec<String>>,
+    /// Description in plain text besides the html, if requested in `DescriptionFormat::HtmlWithText`.
+    pub description_text: Option<String>,
+    /// Images shown inline in the description.
     pub images: Vec<ScImages>,
+    /// Images linked in the description for download.
+    pub image_attachments: Vec<ScAttachment>,
+    /// Documents linked in the description, like the application form.
+    pub documents: Vec<ScAttachment>,
+    /// Banner (the first image) in original resolution, or the thumbnail if no original one.
+    pub banner: Option<ScImages>,
+    /// Poster with QR code linking to the activity, for sharing. Not included in `images`.
+    pub share_poster: Option<ScImages>,
+    /// When the credit is awarded, as the rules in description say.
+    pub award_timing: AwardTiming,
+    /// How downloading images went, so that a CDN outage is told from an activity without images.
+    pub image_fetch_status: ImageFetchStatus,
+    /// Time left to apply by the server clock when fetched, or `None` if closed.
+    pub registration_time_left: Option<std::time::Duration>,
+    /// Parsed values not plausible, found by `validate` if `sc.validate` is enabled.
+    pub warnings: Vec<String>,
+}
+
+/// When the credit of an activity is awarded.
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum AwardTiming {
+    /// Once the student signs up.
+    OnSignup,
+    /// After the student attends, by sign-in records.
+    AfterAttendance,
+    /// After the student attends and evaluates the activity.
+    AfterEvaluation,
+    Unknown,
+}
+
+/// Outcome of downloading images of an activity.
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum ImageFetchStatus {
+    /// All images are downloaded, or none is requested.
+    AllOk,
+    /// Some images failed to download.
+    PartialFailure,
+    /// All images failed to download, with the host answering.
+    AllFailed,
+    /// The image host could not be connected to, for any image.
+    CdnUnreachable,
 }
 
 #[derive(serde::Serialize, Debug)]
@@ -62,6 +129,228 @@ pub struct ScImages {
     pub new_name: String,
     pub old_name: String,
     pub content: Vec<u8>,
+    /// The client already has the image, so the content is left empty.
+    pub client_cached: bool,
+}
+
+/// A file linked in the description.
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct ScAttachment {
+    /// Link text, usually the file name.
+    pub name: String,
+    pub url: String,
+}
+
+/// Kind of an asset in the description.
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum AssetKind {
+    /// Image shown inline.
+    Image,
+    /// Image linked for download.
+    ImageAttachment,
+    /// Document linked for download.
+    Document,
+}
+
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "bmp", "webp"];
+const DOCUMENT_EXTENSIONS: &[&str] = &[
+    "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "txt", "zip", "rar", "7z",
+];
+
+/// Classify an asset by whether it is shown inline and the extension of its url. Links to other
+/// pages are not assets.
+fn classify_asset(url: &str, inline: bool) -> Option<AssetKind> {
+    if inline {
+        return Some(AssetKind::Image);
+    }
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let file_name = path.rsplit('/').next().unwrap_or_default();
+    let (_, extension) = file_name.rsplit_once('.')?;
+    let extension = extension.to_lowercase();
+
+    if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+        Some(AssetKind::ImageAttachment)
+    } else if DOCUMENT_EXTENSIONS.contains(&extension.as_str()) {
+        Some(AssetKind::Document)
+    } else {
+        None
+    }
+}
+
+/// Find files linked in the description, and return (image attachments, documents). Links wrapping an
+/// image point to the original of an inline one, which are skipped.
+fn parse_attachments(description: &str) -> (Vec<ScAttachment>, Vec<ScAttachment>) {
+    let fragment = Html::parse_fragment(description);
+    let mut image_attachments = Vec::new();
+    let mut documents = Vec::new();
+
+    for link in fragment.select(&SELECTOR_LINK) {
+        if link.select(&SELECTOR_IMAGE).next().is_some() {
+            continue;
+        }
+        let url = link.value().attr("href").unwrap_or_default().to_string();
+        let attachment = ScAttachment {
+            name: link.text().collect::<String>().trim().to_string(),
+            url,
+        };
+        match classify_asset(&attachment.url, false) {
+            Some(AssetKind::ImageAttachment) => image_attachments.push(attachment),
+            Some(AssetKind::Document) => documents.push(attachment),
+            _ => {}
+        }
+    }
+    (image_attachments, documents)
+}
+
+/// Format of the activity description returned.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum DescriptionFormat {
+    /// Html as it is on the page.
+    #[default]
+    Html,
+    /// Plain text, a line per paragraph or list item.
+    PlainText,
+    /// Plain text paragraphs in `description_blocks`.
+    Blocks,
+    /// Html as it is, with the plain text in `description_text`.
+    HtmlWithText,
+}
+
+/// Placeholder of an image in plain text, by the url which `images` are named after.
+fn image_placeholder(src: &str) -> String {
+    format!("[image:{}]", src)
+}
+
+/// Elements which start a new line in plain text.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "p", "div", "ul", "ol", "li", "h1", "h2", "h3", "h4", "h5", "h6", "table", "tr", "blockquote",
+];
+
+fn collect_text(element: ElementRef, text: &mut String) {
+    for child in element.children() {
+        if let Some(e) = ElementRef::wrap(child) {
+            let name = e.value().name();
+            if name == "br" {
+                text.push('\n');
+                continue;
+            }
+            if name == "img" {
+                let src = e.value().attr("src").unwrap_or_default();
+                text.push_str(&format!("\n{}\n", image_placeholder(src)));
+                continue;
+            }
+            let is_block = BLOCK_ELEMENTS.contains(&name);
+            if is_block {
+                text.push('\n');
+            }
+            collect_text(e, text);
+            if is_block {
+                text.push('\n');
+            }
+        } else if let Some(t) = child.value().as_text() {
+            text.push_str(&t.text);
+        }
+    }
+}
+
+/// Strip tags of the html description, and return lines of plain text. Entities are decoded, spaces are
+/// collapsed and blank lines are removed. Images are kept as placeholders on their own lines.
+fn description_lines(html: &str) -> Vec<String> {
+    let fragment = Html::parse_fragment(html);
+    let mut text = String::new();
+    collect_text(fragment.root_element(), &mut text);
+
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Find the award timing in lines about credit (加分) of the description.
+fn parse_award_timing(description: &str) -> AwardTiming {
+    let rules: Vec<String> = description_lines(description)
+        .into_iter()
+        .filter(|line| line.contains("加分"))
+        .collect();
+    let mentions = |keywords: &[&str]| {
+        rules
+            .iter()
+            .any(|line| keywords.iter().any(|k| line.contains(k)))
+    };
+
+    // Check the stricter ones first, "报名即加分，无需签到" also mentions sign-in.
+    if mentions(&["评价后"]) {
+        AwardTiming::AfterEvaluation
+    } else if mentions(&["报名即", "报名成功即"]) {
+        AwardTiming::OnSignup
+    } else if mentions(&["签到", "签退", "参加活动后", "活动结束后"]) {
+        AwardTiming::AfterAttendance
+    } else {
+        AwardTiming::Unknown
+    }
+}
+
+impl ActivityDetail {
+    /// Convert the description to the format the client wants.
+    pub fn format_description(&mut self, format: DescriptionFormat) {
+        match format {
+            DescriptionFormat::Html => {}
+            DescriptionFormat::PlainText => {
+                self.description = description_lines(&self.description).join("\n");
+            }
+            DescriptionFormat::Blocks => {
+                self.description_blocks = Some(description_lines(&self.description));
+                self.description.clear();
+            }
+            DescriptionFormat::HtmlWithText => {
+                self.description_text = Some(description_lines(&self.description).join("\n"));
+            }
+        }
+    }
+
+    /// Time left to apply at `now`. Students can apply until the activity starts, or is full.
+    pub fn time_left_to_apply(&self, now: DateTime<Local>) -> Option<std::time::Duration> {
+        if let (Some(applied), Some(capacity)) = (self.applied, self.capacity) {
+            if applied >= capacity {
+                return None;
+            }
+        }
+        (self.start_time - now).to_std().ok().filter(|x| !x.is_zero())
+    }
+
+    /// Check parsed values, and return a warning for each one not plausible, which usually means the
+    /// page has changed and the parser goes wrong.
+    pub fn validate(&self, now: DateTime<Local>) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if let (Some(applied), Some(capacity)) = (self.applied, self.capacity) {
+            if applied > capacity {
+                warnings.push(format!("applied {} is more than the capacity {}", applied, capacity));
+            }
+        }
+        let far = Duration::days(PLAUSIBLE_DAYS);
+        if try_parse_date_time(&self.raw_start_time).is_none() {
+            warnings.push(format!("start time \"{}\" is malformed", self.raw_start_time));
+        } else if self.start_time < now - far || self.start_time > now + far {
+            warnings.push(format!("start time {} is far from now", self.start_time));
+        }
+        if let Some(end_time) = self.end_time {
+            if end_time < self.start_time {
+                warnings.push(format!("end time {} is before the start time", end_time));
+            }
+        }
+        warnings
+    }
+
+    /// Mark images the client already has by `old_name`, which will not be downloaded or sent again.
+    pub fn mark_client_cached(&mut self, known_images: &HashSet<String>) {
+        for image in self.images.iter_mut() {
+            if known_images.contains(&image.old_name) {
+                image.content.clear();
+                image.client_cached = true;
+            }
+        }
+    }
 }
 fn clean_text(banner: &str) -> String {
     let banner = banner.replace("&nbsp;", " ");
@@ -87,41 +376,94 @@ fn split_activity_properties(banner: &str) -> HashMap<String, String> {
         })
 }
 
-fn parse_date_time(date_time: &str) -> DateTime<Local> {
+pub(super) fn try_parse_date_time(date_time: &str) -> Option<DateTime<Local>> {
     let tz = FixedOffset::east(8 * 3600);
-    let dt = tz
-        .datetime_from_str(date_time, "%Y-%m-%d %H:%M:%S")
-        .unwrap_or_else(|_| tz.timestamp_nanos(0));
+    let dt = tz.datetime_from_str(date_time.trim(), "%Y-%m-%d %H:%M:%S").ok()?;
 
-    DateTime::<Local>::from(dt)
+    Some(DateTime::<Local>::from(dt))
 }
 
-fn parse_sign_time(value: &str) -> (DateTime<Local>, DateTime<Local>) {
-    let (start_s, end_s) = value.split_once("  --至--  ").unwrap_or_default();
+fn parse_date_time(date_time: &str) -> DateTime<Local> {
+    try_parse_date_time(date_time).unwrap_or_else(|| Local.timestamp_nanos(0))
+}
 
-    (parse_date_time(start_s), parse_date_time(end_s))
+/// Parse "2020-05-29 10:05:32  --至--  2020-05-29 11:45:40" to the sign-in window. The end is `None`
+/// if it is left empty or written as "至今", which means the window is open until the activity ends.
+fn parse_sign_time(value: Option<&String>) -> Option<(DateTime<Local>, Option<DateTime<Local>>)> {
+    let (start_s, end_s) = value?.split_once("--至--")?;
+    let end_s = end_s.trim();
+    let end = if end_s.is_empty() || end_s == "至今" {
+        None
+    } else {
+        Some(try_parse_date_time(end_s)?)
+    };
+
+    Some((try_parse_date_time(start_s)?, end))
+}
+
+/// Parse "90 分钟" to the duration.
+fn parse_duration(value: Option<&String>) -> Option<Duration> {
+    let minutes = value?.trim().trim_end_matches("分钟").trim().parse().ok()?;
+
+    Some(Duration::minutes(minutes))
+}
+
+/// Parse "报名人数：35/60" to (applied, capacity).
+fn parse_apply_count(value: Option<&String>) -> (Option<u32>, Option<u32>) {
+    let (applied, capacity) = value
+        .and_then(|x| x.split_once('/'))
+        .unwrap_or_default();
+
+    (applied.trim().parse().ok(), capacity.trim().parse().ok())
 }
 
 fn parse_properties(banner: &str) -> ActivityDetail {
-    let properties = split_activity_properties(banner);
+    properties_to_detail(&split_activity_properties(banner))
+}
+
+fn properties_to_detail(properties: &HashMap<String, String>) -> ActivityDetail {
     let to_o = |x: &String| if x.is_empty() { None } else { Some(x.to_string()) };
 
-    let sign_time = parse_sign_time(&properties["刷卡时间段"]);
+    let raw_start_time = properties["活动开始时间"].trim().to_string();
+    let raw_sign_time = properties.get("刷卡时间段").map(|x| x.trim().to_string());
+    let start_time = parse_date_time(&raw_start_time);
+    let end_time = parse_duration(properties.get("活动时长")).map(|d| start_time + d);
+    // Students sign in during the activity if there is no separate window, or no end of it.
+    let activity_end = end_time.unwrap_or(start_time);
+    let sign_time = parse_sign_time(raw_sign_time.as_ref())
+        .map(|(start, end)| (start, end.unwrap_or(activity_end)))
+        .unwrap_or((start_time, activity_end));
+    let (applied, capacity) = parse_apply_count(properties.get("报名人数"));
     ActivityDetail {
         id: properties["活动编号"].parse().unwrap_or_default(),
         category: 0,
         title: "".to_string(),
-        start_time: parse_date_time(&properties["活动开始时间"]),
+        start_time,
+        end_time,
         sign_start_time: sign_time.0,
         sign_end_time: sign_time.1,
+        raw_start_time,
+        raw_sign_time,
         place: to_o(&properties["活动地点"]),
         duration: to_o(&properties["活动时长"]),
         manager: to_o(&properties["负责人"]),
         contact: to_o(&properties["负责人电话"]),
         organizer: to_o(&properties["主办方"]),
         undertaker: to_o(&properties["承办方"]),
+        applied,
+        capacity,
         description: "".to_string(),
+        description_blocks: None,
+        description_text: None,
         images: vec![],
+        image_attachments: vec![],
+        documents: vec![],
+        banner: None,
+        share_poster: None,
+        award_timing: AwardTiming::Unknown,
+        image_fetch_status: ImageFetchStatus::AllOk,
+        registration_time_left: None,
+        warnings: vec![],
     }
 }
 
@@ -178,6 +520,7 @@ fn replace_image_by_base64(old_name: String) -> ScImages {
         new_name,
         old_name,
         content: image,
+        client_cached: false,
     }
 }
 
@@ -192,9 +535,30 @@ fn default_replace_image(old_name: String) -> ScImages {
         new_name,
         old_name,
         content: vec![],
+        client_cached: false,
     }
 }
 
+/// Find the banner url. Original image is linked by the thumbnail or written in `data-original`.
+fn parse_banner(description: &str) -> Option<ScImages> {
+    let original = RE_ORIGINAL_LINK
+        .captures(description)
+        .or_else(|| RE_ORIGINAL_ATTR.captures(description))
+        .map(|c| c[1].to_string());
+    let thumbnail = || RE_IMAGES.captures(description).map(|c| c[2].to_string());
+
+    original.or_else(thumbnail).map(match_image_url)
+}
+
+/// Find the share poster, which is shown out of the description on some pages.
+fn parse_share_poster(frame: ElementRef) -> Option<ScImages> {
+    frame
+        .select(&SELECTOR_SHARE_POSTER)
+        .next()
+        .and_then(|e| e.value().attr("src"))
+        .map(|src| match_image_url(src.to_string()))
+}
+
 fn parse_description(frame: ElementRef) -> (String, Vec<ScImages>) {
     let description = select_text(frame, &SELECTOR_DESCRIPTION);
     let description = RE_DESCRIPTION_SPACES.replace_all(&description, " ").to_string();
@@ -209,6 +573,57 @@ fn parse_description(frame: ElementRef) -> (String, Vec<ScImages>) {
     (description, images)
 }
 
+/// Activity detail returned by the AJAX endpoint.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonActivityDetail {
+    activity_id: String,
+    activity_name: String,
+    start_time: String,
+    #[serde(default)]
+    address: String,
+    #[serde(default)]
+    duration: String,
+    #[serde(default)]
+    manager: String,
+    #[serde(default)]
+    manager_phone: String,
+    #[serde(default)]
+    organizer: String,
+    #[serde(default)]
+    undertaker: String,
+    /// Like "2020-05-29 10:05:32  --至--  2020-05-29 11:45:40"
+    sign_time: Option<String>,
+    apply_num: Option<u32>,
+    limit_num: Option<u32>,
+    /// Description in html
+    #[serde(default)]
+    content: String,
+}
+
+impl JsonActivityDetail {
+    /// Convert to the properties shown on the html page, so that they are parsed the same way.
+    fn properties(self) -> HashMap<String, String> {
+        let mut properties: HashMap<String, String> = vec![
+            ("活动编号", self.activity_id),
+            ("活动开始时间", self.start_time),
+            ("活动地点", self.address),
+            ("活动时长", self.duration),
+            ("负责人", self.manager),
+            ("负责人电话", self.manager_phone),
+            ("主办方", self.organizer),
+            ("承办方", self.undertaker),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect();
+        if let Some(sign_time) = self.sign_time {
+            properties.insert(String::from("刷卡时间段"), sign_time);
+        }
+        properties
+    }
+}
+
 impl Parse for ActivityDetail {
     fn from_html(html_page: &str) -> Result<ActivityDetail> {
         let document = Html::parse_document(html_page);
@@ -223,32 +638,161 @@ impl Parse for ActivityDetail {
 
         let title = select_text(frame, &SELECTOR_TITLE);
         let banner = select_text(frame, &SELECTOR_BANNER);
-        let (description, images) = parse_description(frame);
+        let raw_description = select_text(frame, &SELECTOR_DESCRIPTION);
+        let poster = parse_banner(&raw_description);
+        let (image_attachments, documents) = parse_attachments(&raw_description);
+        let (description, mut images) = parse_description(frame);
+        let share_poster = parse_share_poster(frame);
+        if let Some(share_poster) = &share_poster {
+            images.retain(|x| x.old_name != share_poster.old_name);
+        }
 
         let mut result = parse_properties(&banner);
+        // Some activities only write the limit in description.
+        if result.capacity.is_none() {
+            result.capacity = RE_CAPACITY
+                .captures(&description)
+                .and_then(|c| c[1].parse().ok());
+        }
         result.title = title;
+        result.award_timing = parse_award_timing(&description);
         result.description = description;
         result.images = images;
+        result.image_attachments = image_attachments;
+        result.documents = documents;
+        result.banner = poster;
+        result.share_poster = share_poster;
         Ok(result)
     }
+
+    fn from_json(json: &str) -> Result<ActivityDetail> {
+        let detail: JsonActivityDetail = serde_json::from_str(json)?;
+        let title = detail.activity_name.clone();
+        let (applied, capacity) = (detail.apply_num, detail.limit_num);
+        let content = RE_DESCRIPTION_SPACES.replace_all(&detail.content, " ").to_string();
+
+        let mut result = properties_to_detail(&detail.properties());
+        let (description, images) = replace_images(&content);
+        result.applied = applied;
+        result.capacity = capacity.or_else(|| {
+            RE_CAPACITY
+                .captures(&description)
+                .and_then(|c| c[1].parse().ok())
+        });
+        result.title = title;
+        result.banner = parse_banner(&content);
+        let (image_attachments, documents) = parse_attachments(&content);
+        result.image_attachments = image_attachments;
+        result.documents = documents;
+        result.award_timing = parse_award_timing(&description);
+        result.description = description;
+        result.images = images;
+        Ok(result)
+    }
+}
+
+/// Title and apply status of an activity, without description and images.
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct ActivityBrief {
+    /// Whether the activity exists
+    pub exists: bool,
+    /// Activity title
+    pub title: Option<String>,
+    /// Text on the apply button, such as "我要申请".
+    pub status: Option<String>,
 }
 
+impl ActivityBrief {
+    pub fn not_found() -> Self {
+        Self {
+            exists: false,
+            title: None,
+            status: None,
+        }
+    }
+}
+
+impl Parse for ActivityBrief {
+    fn from_html(html_page: &str) -> Result<Self> {
+        let document = Html::parse_document(html_page);
+
+        let frame = document
+            .select(&SELECTOR_FRAME)
+            .next()
+            .ok_or_else(|| ParserError::NoSuchElement(String::from(".box-1")))?;
+        let title = select_text(frame, &SELECTOR_TITLE).trim().to_string();
+        if title.is_empty() {
+            return Ok(Self::not_found());
+        }
+        let status = document
+            .select(&SELECTOR_APPLY)
+            .next()
+            .and_then(|e| e.value().attr("value"))
+            .map(ToString::to_string);
+
+        Ok(Self {
+            exists: true,
+            title: Some(title),
+            status,
+        })
+    }
+}
+
+/// Keywords of the duplicate registration message.
+const DUPLICATE_KEYWORDS: &[&str] = &["重复报名", "已报名", "已申请过该活动"];
+
+/// Keyword of the message that the activity is full.
+const FULL_KEYWORD: &str = "人数已达上限";
+
+/// Days from now a start time is taken as plausible.
+const PLAUSIBLE_DAYS: i64 = 10 * 365;
+
+#[derive(serde::Serialize, Debug, PartialEq)]
 pub enum ScJoinResult {
     Ok,
+    /// The student has joined the activity before.
+    AlreadyJoined,
     Err(String),
+    /// The activity has as many applicants as it takes.
+    Full,
+}
+
+impl ScJoinResult {
+    /// Parse the alert on the apply page, like "申请成功，下面将为您跳转至我的活动页面！".
+    fn from_alert(html_page: &str) -> Result<ScJoinResult> {
+        let message = RE_ALERT
+            .captures(html_page)
+            .map(|c| c[1].to_string())
+            .ok_or(ActionError::ParsingError)?;
+
+        if DUPLICATE_KEYWORDS.iter().any(|k| message.contains(k)) {
+            Ok(ScJoinResult::AlreadyJoined)
+        } else if message.contains(FULL_KEYWORD) {
+            Ok(ScJoinResult::Full)
+        } else if message.contains("申请成功") {
+            Ok(ScJoinResult::Ok)
+        } else {
+            Ok(ScJoinResult::Err(message))
+        }
+    }
 }
 
 impl Parse for ScJoinResult {
+    /// Parse the code returned by the check, or the alert on the apply page.
     fn from_html(html_page: &str) -> Result<ScJoinResult> {
-        let code = html_page.parse::<i32>().map_err(|_| ActionError::ParsingError)?;
-        if code == 0 {
-            return Ok(ScJoinResult::Ok);
+        let code = match html_page.trim().parse::<i32>() {
+            Ok(code) => code,
+            Err(_) => return Self::from_alert(html_page),
+        };
+        match code {
+            0 => return Ok(ScJoinResult::Ok),
+            2 => return Ok(ScJoinResult::AlreadyJoined),
+            4 => return Ok(ScJoinResult::Full),
+            _ => {}
         }
         let message = match code {
             1 => "您的个人信息不全，请补全您的信息！",
-            2 => "您已申请过该活动，不能重复申请！",
             3 => "对不起，您今天的申请次数已达上限！",
-            4 => "对不起，该活动的申请人数已达上限！",
             5 => "对不起，该活动已过期并停止申请！",
             6 => "您已申请过该时间段的活动，不能重复申请！",
             7 => "对不起，您不能申请该活动！",
@@ -259,6 +803,32 @@ impl Parse for ScJoinResult {
     }
 }
 
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub enum ScCancelResult {
+    Ok,
+    /// The activity has started, and can not be cancelled any more.
+    Started,
+    Err(String),
+}
+
+impl Parse for ScCancelResult {
+    /// Parse the alert on the cancel page, like "取消成功！".
+    fn from_html(html_page: &str) -> Result<ScCancelResult> {
+        let message = RE_ALERT
+            .captures(html_page)
+            .map(|c| c[1].to_string())
+            .ok_or(ActionError::ParsingError)?;
+
+        if message.contains("已开始") {
+            Ok(ScCancelResult::Started)
+        } else if message.contains("取消成功") {
+            Ok(ScCancelResult::Ok)
+        } else {
+            Ok(ScCancelResult::Err(message))
+        }
+    }
+}
+
 #[tokio::test]
 async fn test_activity_detail() -> Result<()> {
     let html_page = std::fs::read_to_string("html/第二课堂详情页面2.html").unwrap();
@@ -267,6 +837,324 @@ async fn test_activity_detail() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_apply_count() {
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面.html").unwrap();
+    let detail = ActivityDetail::from_html(&html_page).unwrap();
+    assert_eq!((detail.applied, detail.capacity), (None, Some(12)));
+
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面2.html")
+        .unwrap()
+        .replace("承办方：鲁班书院", "承办方：鲁班书院&nbsp;&nbsp;&nbsp;&nbsp;报名人数：35/60");
+    let detail = ActivityDetail::from_html(&html_page).unwrap();
+    assert_eq!((detail.applied, detail.capacity), (Some(35), Some(60)));
+}
+
+#[test]
+fn test_banner() {
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面3.html").unwrap();
+    let detail = ActivityDetail::from_html(&html_page).unwrap();
+    assert_eq!(
+        detail.banner.unwrap().old_name,
+        "/js/kindeditor-4.1.7/attached/image/20200528/20200528101316_172_original.png"
+    );
+
+    // No original image, use the thumbnail.
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面2.html").unwrap();
+    let detail = ActivityDetail::from_html(&html_page).unwrap();
+    assert_eq!(
+        detail.banner.unwrap().old_name,
+        "/js/kindeditor-4.1.7/attached/image/20200528/20200528101316_172.png"
+    );
+}
+
+#[test]
+fn test_json_activity_detail() {
+    use crate::parser::parse_body;
+
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面2.html").unwrap();
+    let json = std::fs::read_to_string("html/第二课堂详情页面2.json").unwrap();
+    let from_html: ActivityDetail = parse_body(&html_page, None).unwrap();
+    let from_json: ActivityDetail = parse_body(&json, Some("application/json")).unwrap();
+
+    assert_eq!(from_json.id, from_html.id);
+    assert_eq!(from_json.title, from_html.title);
+    assert_eq!(
+        (from_json.start_time, from_json.end_time),
+        (from_html.start_time, from_html.end_time)
+    );
+    assert_eq!(
+        (from_json.sign_start_time, from_json.sign_end_time),
+        (from_html.sign_start_time, from_html.sign_end_time)
+    );
+    assert_eq!((&from_json.place, &from_json.manager), (&from_html.place, &from_html.manager));
+    assert_eq!(from_json.images.len(), from_html.images.len());
+    assert_eq!(from_json.banner.unwrap().old_name, from_html.banner.unwrap().old_name);
+}
+
+#[test]
+fn test_award_timing() {
+    let cases = [
+        ("html/第二课堂详情页面_报名加分.html", AwardTiming::OnSignup),
+        ("html/第二课堂详情页面_签到加分.html", AwardTiming::AfterAttendance),
+        ("html/第二课堂详情页面_评价加分.html", AwardTiming::AfterEvaluation),
+        ("html/第二课堂详情页面2.html", AwardTiming::Unknown),
+    ];
+    for (file, timing) in cases.iter() {
+        let html_page = std::fs::read_to_string(file).unwrap();
+        let detail = ActivityDetail::from_html(&html_page).unwrap();
+        assert_eq!(detail.award_timing, *timing, "{}", file);
+    }
+}
+
+#[test]
+fn test_share_poster() {
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面5.html").unwrap();
+    let detail = ActivityDetail::from_html(&html_page).unwrap();
+    let share_poster = detail.share_poster.unwrap();
+    assert_eq!(share_poster.old_name, "/upload/activity/poster/1061909_share.png");
+    assert!(detail.images.iter().all(|x| x.old_name != share_poster.old_name));
+    assert!(detail.banner.is_some());
+
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面3.html").unwrap();
+    let detail = ActivityDetail::from_html(&html_page).unwrap();
+    assert!(detail.share_poster.is_none());
+}
+
+#[test]
+fn test_mark_client_cached() {
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面2.html").unwrap();
+    let mut detail = ActivityDetail::from_html(&html_page).unwrap();
+    detail.images.push(default_replace_image(String::from("/js/kindeditor-4.1.7/new.png")));
+
+    let known: HashSet<String> = vec![String::from(
+        "/js/kindeditor-4.1.7/attached/image/20200528/20200528101316_172.png",
+    )]
+    .into_iter()
+    .collect();
+    detail.mark_client_cached(&known);
+
+    assert!(detail.images[0].client_cached);
+    assert!(!detail.images[1].client_cached);
+}
+
+#[test]
+fn test_attachments() {
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面_附件.html").unwrap();
+    let detail = ActivityDetail::from_html(&html_page).unwrap();
+
+    assert_eq!(detail.images.len(), 1);
+    assert_eq!(
+        detail.image_attachments,
+        vec![ScAttachment {
+            name: String::from("书法大赛海报.jpg"),
+            url: String::from("/js/kindeditor-4.1.7/attached/image/20200528/20200528101402_359.jpg"),
+        }]
+    );
+    assert_eq!(
+        detail.documents,
+        vec![ScAttachment {
+            name: String::from("鲁班书院书法大赛报名表.docx"),
+            url: String::from("/js/kindeditor-4.1.7/attached/file/20200528/20200528101525_843.docx?v=2"),
+        }]
+    );
+
+    // Links to the original of inline images are not attachments.
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面3.html").unwrap();
+    let detail = ActivityDetail::from_html(&html_page).unwrap();
+    assert!(detail.image_attachments.is_empty());
+    assert!(detail.documents.is_empty());
+
+    assert_eq!(classify_asset("/attached/image/a.PNG", true), Some(AssetKind::Image));
+    assert_eq!(classify_asset("http://www.sit.edu.cn/", false), None);
+}
+
+#[test]
+fn test_sign_time() {
+    let time = |s: &str| try_parse_date_time(s).unwrap();
+
+    // Sign-in window is earlier than the activity start.
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面2.html").unwrap();
+    let detail = ActivityDetail::from_html(&html_page).unwrap();
+    assert_eq!(detail.start_time, time("2020-05-29 10:19:48"));
+    assert_eq!(detail.end_time, Some(time("2020-05-29 11:49:48")));
+    assert_eq!(detail.sign_start_time, time("2020-05-29 10:05:32"));
+    assert_eq!(detail.sign_end_time, time("2020-05-29 11:45:40"));
+
+    // No separate window.
+    let html_page = html_page.replace(
+        "刷卡时间段：2020-05-29 10:05:32&nbsp;&nbsp;--至--&nbsp;&nbsp;2020-05-29 11:45:40",
+        "",
+    );
+    let detail = ActivityDetail::from_html(&html_page).unwrap();
+    assert_eq!(detail.sign_start_time, detail.start_time);
+    assert_eq!(Some(detail.sign_end_time), detail.end_time);
+}
+
+#[test]
+fn test_open_sign_time() {
+    let time = |s: &str| try_parse_date_time(s).unwrap();
+    let window = |s: &str| parse_sign_time(Some(&s.to_string()));
+
+    assert_eq!(
+        window("2020-05-29 10:05:32  --至--  2020-05-29 11:45:40"),
+        Some((time("2020-05-29 10:05:32"), Some(time("2020-05-29 11:45:40"))))
+    );
+    assert_eq!(
+        window("2020-05-29 10:05:32  --至--  至今"),
+        Some((time("2020-05-29 10:05:32"), None))
+    );
+    assert_eq!(
+        window("2020-05-29 10:05:32  --至--  "),
+        Some((time("2020-05-29 10:05:32"), None))
+    );
+    assert_eq!(window("2020-05-29  --至--  2020-05-29 11:45:40"), None);
+
+    // The window is open until the activity ends.
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面2.html").unwrap();
+    let html_page = html_page.replace("--至--&nbsp;&nbsp;2020-05-29 11:45:40", "--至--&nbsp;&nbsp;至今");
+    let detail = ActivityDetail::from_html(&html_page).unwrap();
+    assert_eq!(detail.sign_start_time, time("2020-05-29 10:05:32"));
+    assert_eq!(Some(detail.sign_end_time), detail.end_time);
+    assert_eq!(
+        detail.raw_sign_time.as_deref(),
+        Some("2020-05-29 10:05:32  --至--  至今")
+    );
+}
+
+#[test]
+fn test_malformed_start_time() {
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面2.html").unwrap();
+    let html_page = html_page.replace("2020-5-29 10:19:48", "2020年5月29日");
+    let detail = ActivityDetail::from_html(&html_page).unwrap();
+
+    assert_eq!(detail.raw_start_time, "2020年5月29日");
+    assert_eq!(detail.start_time, Local.timestamp_nanos(0));
+    let warnings = detail.validate(Local::now());
+    assert!(warnings.iter().any(|w| w.contains("2020年5月29日") && w.contains("malformed")));
+}
+
+#[test]
+fn test_activity_brief() {
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面2.html").unwrap();
+    let brief = ActivityBrief::from_html(&html_page).unwrap();
+    assert!(brief.exists);
+    assert!(brief.title.is_some());
+    assert_eq!(brief.status.as_deref(), Some("我要申请"));
+
+    let html_page = "<html><head><title>404</title></head><body><h1>Not Found</h1></body></html>";
+    assert!(ActivityBrief::from_html(html_page).is_err());
+}
+
+#[test]
+fn test_join_result() {
+    assert_eq!(ScJoinResult::from_html("0").unwrap(), ScJoinResult::Ok);
+    assert_eq!(ScJoinResult::from_html("2").unwrap(), ScJoinResult::AlreadyJoined);
+    assert_eq!(ScJoinResult::from_html("4").unwrap(), ScJoinResult::Full);
+    assert_eq!(
+        ScJoinResult::from_html("5").unwrap(),
+        ScJoinResult::Err(String::from("对不起，该活动已过期并停止申请！"))
+    );
+
+    let html_page = std::fs::read_to_string("html/第二课堂重复报名页面.html").unwrap();
+    assert_eq!(ScJoinResult::from_html(&html_page).unwrap(), ScJoinResult::AlreadyJoined);
+
+    let html_page = "<script>alert('申请成功，下面将为您跳转至我的活动页面！');location.href='/public/pcenter/activityOrderList.action'</script>";
+    assert_eq!(ScJoinResult::from_html(html_page).unwrap(), ScJoinResult::Ok);
+
+    let html_page = "<script>alert('对不起，该活动的申请人数已达上限！');history.back();</script>";
+    assert_eq!(ScJoinResult::from_html(html_page).unwrap(), ScJoinResult::Full);
+}
+
+#[test]
+fn test_cancel_result() {
+    let html_page = std::fs::read_to_string("html/第二课堂取消报名页面_已开始.html").unwrap();
+    assert_eq!(ScCancelResult::from_html(&html_page).unwrap(), ScCancelResult::Started);
+
+    let html_page = "<script>alert('取消成功！');location.href='/public/pcenter/activityOrderList.action'</script>";
+    assert_eq!(ScCancelResult::from_html(html_page).unwrap(), ScCancelResult::Ok);
+    assert!(ScCancelResult::from_html("<html></html>").is_err());
+}
+
+#[test]
+fn test_plain_text_description() {
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面4.html").unwrap();
+    let mut detail = ActivityDetail::from_html(&html_page).unwrap();
+    detail.format_description(DescriptionFormat::PlainText);
+
+    assert_eq!(
+        detail.description,
+        "活动时间：2020年5月29日 10:20\n活动地点： 线上\n活动内容：\n观看直播讲座\n提交 心得体会\n欢迎同学们\n积极参加！"
+    );
+
+    let mut detail = ActivityDetail::from_html(&html_page).unwrap();
+    detail.format_description(DescriptionFormat::Blocks);
+    assert!(detail.description.is_empty());
+    assert_eq!(detail.description_blocks.unwrap().len(), 7);
+
+    let mut detail = ActivityDetail::from_html(&html_page).unwrap();
+    let html = detail.description.clone();
+    detail.format_description(DescriptionFormat::HtmlWithText);
+    assert_eq!(detail.description, html);
+    let text = detail.description_text.unwrap();
+    assert!(text.starts_with("活动时间：2020年5月29日 10:20\n"));
+}
+
+#[test]
+fn test_description_lines() {
+    // Entities are decoded, and non-breaking spaces collapse like others.
+    let html = "<p>讲座&nbsp;&amp;&nbsp;&nbsp;沙龙 &lt;线上&gt; &quot;直播&quot;</p>";
+    assert_eq!(description_lines(html), vec!["讲座 & 沙龙 <线上> \"直播\""]);
+
+    // Tags nested in blocks are stripped, and inline ones do not break the line.
+    let html = r#"<div><p style="text-align:center"><span style="color:red">活动<b>时间</b></span>：
+        <br><strong><em>周三</em> 14:00</strong></p><ul><li>签到</li><li><a href="/x">签退</a></li></ul></div>"#;
+    assert_eq!(description_lines(html), vec!["活动时间：", "周三 14:00", "签到", "签退"]);
+
+    // Images are kept by their urls.
+    let html = r#"<p>海报：<img src="https://kite.sunnysab.cn/static/event/image/a.png" /></p>"#;
+    assert_eq!(
+        description_lines(html),
+        vec!["海报：", "[image:https://kite.sunnysab.cn/static/event/image/a.png]"]
+    );
+}
+
+#[test]
+fn test_time_left_to_apply() {
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面_即将截止.html").unwrap();
+    let detail = ActivityDetail::from_html(&html_page).unwrap();
+
+    // Starts at 2020-6-3 14:00:00 in Beijing time.
+    let beijing = FixedOffset::east_opt(8 * 3600).unwrap();
+    let now = beijing.with_ymd_and_hms(2020, 6, 1, 12, 0, 0).unwrap();
+    assert_eq!(
+        detail.time_left_to_apply(now.with_timezone(&Local)),
+        Some(std::time::Duration::from_secs((2 * 24 + 2) * 3600))
+    );
+    let now = beijing.with_ymd_and_hms(2020, 6, 3, 14, 0, 0).unwrap();
+    assert_eq!(detail.time_left_to_apply(now.with_timezone(&Local)), None);
+
+    // Not started yet, but full.
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面_名额已满.html").unwrap();
+    let detail = ActivityDetail::from_html(&html_page).unwrap();
+    let now = Local.with_ymd_and_hms(2020, 5, 20, 12, 0, 0).unwrap();
+    assert_eq!(detail.time_left_to_apply(now), None);
+}
+
+#[test]
+fn test_validate() {
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面_报名超额.html").unwrap();
+    let detail = ActivityDetail::from_html(&html_page).unwrap();
+    let now = Local.with_ymd_and_hms(2020, 5, 20, 12, 0, 0).unwrap();
+    assert_eq!(detail.validate(now), vec!["applied 63 is more than the capacity 60"]);
+
+    let html_page = std::fs::read_to_string("html/第二课堂详情页面_名额已满.html").unwrap();
+    let detail = ActivityDetail::from_html(&html_page).unwrap();
+    assert!(detail.validate(now).is_empty());
+    let now = Local.with_ymd_and_hms(2035, 5, 20, 12, 0, 0).unwrap();
+    assert_eq!(detail.validate(now).len(), 1);
+}
+
 #[test]
 fn test_image_file() -> Result<()> {
     let image = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAIAAACQd1PeAAAAGXRFWHRTb2Z0d2FyZQBBZG9iZSBJbWFnZVJlYWR5ccllPAAAAyBpVFh0WE1MOmNvbS5hZG9iZS54bXAAAAAAADw/eHBhY2tldCBiZWdpbj0i77u/IiBpZD0iVzVNME1wQ2VoaUh6cmVTek5UY3prYzlkIj8+IDx4OnhtcG1ldGEgeG1sbnM6eD0iYWRvYmU6bnM6bWV0YS8iIHg6eG1wdGs9IkFkb2JlIFhNUCBDb3JlIDUuMC1jMDYwIDYxLjEzNDc3NywgMjAxMC8wMi8xMi0xNzozMjowMCAgICAgICAgIj4gPHJkZjpSREYgeG1sbnM6cmRmPSJodHRwOi8vd3d3LnczLm9yZy8xOTk5LzAyLzIyLXJkZi1zeW50YXgtbnMjIj4gPHJkZjpEZXNjcmlwdGlvbiByZGY6YWJvdXQ9IiIgeG1sbnM6eG1wPSJodHRwOi8vbnMuYWRvYmUuY29tL3hhcC8xLjAvIiB4bWxuczp4bXBNTT0iaHR0cDovL25zLmFkb2JlLmNvbS94YXAvMS4wL21tLyIgeG1sbnM6c3RSZWY9Imh0dHA6Ly9ucy5hZG9iZS5jb20veGFwLzEuMC9zVHlwZS9SZXNvdXJjZVJlZiMiIHhtcDpDcmVhdG9yVG9vbD0iQWRvYmUgUGhvdG9zaG9wIENTNSBXaW5kb3dzIiB4bXBNTTpJbnN0YW5jZUlEPSJ4bXAuaWlkOkJDQzA1MTVGNkE2MjExRTRBRjEzODVCM0Q0NEVFMjFBIiB4bXBNTTpEb2N1bWVudElEPSJ4bXAuZGlkOkJDQzA1MTYwNkE2MjExRTRBRjEzODVCM0Q0NEVFMjFBIj4gPHhtcE1NOkRlcml2ZWRGcm9tIHN0UmVmOmluc3RhbmNlSUQ9InhtcC5paWQ6QkNDMDUxNUQ2QTYyMTFFNEFGMTM4NUIzRDQ0RUUyMUEiIHN0UmVmOmRvY3VtZW50SUQ9InhtcC5kaWQ6QkNDMDUxNUU2QTYyMTFFNEFGMTM4NUIzRDQ0RUUyMUEiLz4gPC9yZGY6RGVzY3JpcHRpb24+IDwvcmRmOlJERj4gPC94OnhtcG1ldGE+IDw/eHBhY2tldCBlbmQ9InIiPz6p+a6fAAAAD0lEQVR42mJ89/Y1QIABAAWXAsgVS/hWAAAAAElFTkSuQmCC";
@@ -0,0 +1,59 @@
+use scraper::{Html, Selector};
+
+lazy_static! {
+    static ref ERROR_MESSAGE: Selector = Selector::parse(".error_box .error_msg").unwrap();
+}
+
+/// Keywords of the busy page, which is the error page with a message like "系统繁忙，请稍后再试！".
+const BUSY_KEYWORDS: &[&str] = &["系统繁忙", "服务器繁忙"];
+
+/// Message on the generic error page of second classroom, which is returned with status 200.
+pub fn get_error_message(html_page: &str) -> Option<String> {
+    let document = Html::parse_document(html_page);
+    let message = document
+        .select(&ERROR_MESSAGE)
+        .next()?
+        .text()
+        .collect::<String>()
+        .trim()
+        .to_string();
+
+    if message.is_empty() {
+        None
+    } else {
+        Some(message)
+    }
+}
+
+/// Whether it is the busy page, which is returned under load and worth a retry later.
+pub fn is_busy_page(html_page: &str) -> bool {
+    get_error_message(html_page)
+        .map(|message| BUSY_KEYWORDS.iter().any(|k| message.contains(k)))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{get_error_message, is_busy_page};
+
+    #[test]
+    fn test_error_message() {
+        let html_page = std::fs::read_to_string("html/第二课堂错误页面.html").unwrap();
+        assert_eq!(
+            get_error_message(&html_page).as_deref(),
+            Some("对不起，该活动不存在或已被删除！")
+        );
+
+        let html_page = std::fs::read_to_string("html/第二课堂详情页面.html").unwrap();
+        assert_eq!(get_error_message(&html_page), None);
+    }
+
+    #[test]
+    fn test_busy_page() {
+        let html_page = std::fs::read_to_string("html/第二课堂系统繁忙页面.html").unwrap();
+        assert!(is_busy_page(&html_page));
+
+        let html_page = std::fs::read_to_string("html/第二课堂错误页面.html").unwrap();
+        assert!(!is_busy_page(&html_page));
+    }
+}
@@ -1,40 +1,295 @@
-use chrono::NaiveDateTime;
+use std::collections::HashMap;
+
+use chrono::{NaiveDate, NaiveDateTime};
 use regex::Regex;
-use scraper::{Html, Selector};
-use serde::Serialize;
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
 
 use crate::error::Result;
-use crate::parser::Parse;
+use crate::parser::{Parse, ParsedRows, ParserError};
 
 /// Activity link, used for list recent activities.
-#[derive(Debug, Serialize)]
+#[derive(Debug, PartialEq, Serialize)]
 pub struct Activity {
     pub id: i32,
     pub category: i32,
+    /// All categories the activity is listed under, when merged by `DedupStrategy::Merge`.
+    pub categories: Vec<i32>,
+    /// Credit awarded, which only the JSON endpoint tells. Unknown if crawled from the HTML page.
+    pub credit: Option<f32>,
+    /// Whether the activity can still be joined, as shown in the list.
+    pub status: ActivityStatus,
+    /// Start time shown in the list, or `None` if not recognized.
+    pub start_time: Option<NaiveDateTime>,
+}
+
+/// Apply status shown next to an activity in the list.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ActivityStatus {
+    /// "报名中", open to apply.
+    Open,
+    /// "已满", no place left.
+    Full,
+    /// "已结束", the activity is over.
+    Ended,
+    /// Not shown, or not recognized.
+    Unknown,
+}
+
+impl Activity {
+    /// Check parsed values, and return a warning for each one not plausible.
+    pub fn validate(&self) -> Vec<String> {
+        match self.credit {
+            Some(credit) if credit < 0.0 => {
+                vec![format!("credit {} of activity {} is negative", credit, self.id)]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl ActivityStatus {
+    fn from_text(text: &str) -> Option<Self> {
+        match text.trim() {
+            "报名中" => Some(ActivityStatus::Open),
+            "已满" => Some(ActivityStatus::Full),
+            "已结束" => Some(ActivityStatus::Ended),
+            _ => None,
+        }
+    }
+}
+
+/// Find the status among spans of a list item, the other of which is the start time.
+fn parse_status(item: Option<ElementRef>, selector: &Selector) -> ActivityStatus {
+    item.and_then(|item| {
+        item.select(selector)
+            .find_map(|e| ActivityStatus::from_text(&e.text().collect::<String>()))
+    })
+    .unwrap_or(ActivityStatus::Unknown)
+}
+
+/// Find the start time among spans of a list item.
+fn parse_start_time(item: Option<ElementRef>, selector: &Selector) -> Option<NaiveDateTime> {
+    item?
+        .select(selector)
+        .find_map(|e| parse_list_time(&e.text().collect::<String>()))
+}
+
+/// Parse time in the list, like "2020-6-24 13:30:00".
+fn parse_list_time(text: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(text.trim(), "%Y-%m-%d %H:%M:%S").ok()
+}
+
+/// Parse the activity list page. Rows not linking to an activity, like promotion banners injected
+/// into the list, are skipped with a warning unless `strict`.
+pub fn parse_activity_list(html_page: &str, strict: bool) -> Result<ParsedRows<Activity>> {
+    let document = Html::parse_document(html_page);
+    let selector = Selector::parse(".ul_7 li > a").unwrap();
+    let span_selector = Selector::parse("span").unwrap();
+    let re = Regex::new(r"(\d){7}")?;
+
+    let rows = document.select(&selector).map(|each_line| {
+        let link = each_line
+            .value()
+            .attr("href")
+            .ok_or_else(|| ParserError::NoSuchElement(String::from("a[href]")))?;
+        let id = re
+            .find(link)
+            .and_then(|x| x.as_str().parse::<i32>().ok())
+            .ok_or_else(|| ParserError::NoSuchElement(format!("activity id in {}", link)))?;
+        let item = each_line.parent().and_then(ElementRef::wrap);
+
+        Ok(Activity {
+            id,
+            category: 0,
+            categories: Vec::new(),
+            credit: None,
+            status: parse_status(item, &span_selector),
+            start_time: parse_start_time(item, &span_selector),
+        })
+    });
+    ParsedRows::collect(rows, strict)
 }
 
 impl Parse for Vec<Activity> {
     fn from_html(html_page: &str) -> Result<Self> {
-        let document = Html::parse_document(html_page);
-        let selector = Selector::parse(".ul_7 li > a").unwrap();
-        let re = Regex::new(r"(\d){7}")?;
-
-        let activities = document
-            .select(&selector)
-            .map(|each_line| {
-                let link = each_line.value().attr("href").unwrap();
-                let id = re
-                    .find(link)
-                    .map(|x| x.as_str().parse::<i32>().unwrap_or_default())
-                    .unwrap_or_default();
+        Ok(parse_activity_list(html_page, false)?.rows)
+    }
 
-                Activity { id, category: 0 }
+    fn from_json(json: &str) -> Result<Self> {
+        let list: JsonActivityList = serde_json::from_str(json)?;
+        let activities = list
+            .rows
+            .into_iter()
+            .map(|row| Activity {
+                id: row.activity_id.parse().unwrap_or_default(),
+                category: 0,
+                categories: Vec::new(),
+                credit: row.credit.and_then(|x| x.parse().ok()),
+                status: ActivityStatus::Unknown,
+                start_time: row.start_time.as_deref().and_then(parse_list_time),
             })
             .collect();
         Ok(activities)
     }
 }
 
+/// Activity list returned by the AJAX endpoint, like `{"rows": [{"activityId": "1062079", ...}]}`.
+#[derive(Deserialize)]
+struct JsonActivityList {
+    rows: Vec<JsonActivity>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonActivity {
+    activity_id: String,
+    /// Credit like "0.5", missing on some rows.
+    #[serde(default)]
+    credit: Option<String>,
+    #[serde(default)]
+    start_time: Option<String>,
+}
+
+/// Total results of the activity list, for pagination. `None` if the page does not tell.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ListTotal {
+    pub total_count: Option<u32>,
+    pub page_count: Option<u32>,
+}
+
+impl ListTotal {
+    fn new(total_count: Option<u32>, page_count: Option<u32>, page_size: Option<u32>) -> Self {
+        // The page count shown is filled by script, and left 0 in the page returned.
+        let page_count = page_count.filter(|x| *x > 0).or_else(|| match (total_count, page_size) {
+            (Some(total), Some(size)) if size > 0 => Some(total.div_ceil(size)),
+            _ => None,
+        });
+        ListTotal {
+            total_count,
+            page_count,
+        }
+    }
+}
+
+impl Parse for ListTotal {
+    /// Parse the indicator under the list, like "总条数：15,920 当前 1/0页", and the page size.
+    fn from_html(html_page: &str) -> Result<Self> {
+        let document = Html::parse_document(html_page);
+        let number = |selector: &str| {
+            let selector = Selector::parse(selector).unwrap();
+            let e = document.select(&selector).next()?;
+            let text = e.value().attr("value").map(str::to_string);
+            let text = text.unwrap_or_else(|| e.text().collect());
+            text.trim().replace(',', "").parse::<u32>().ok()
+        };
+
+        Ok(ListTotal::new(
+            number("#count"),
+            number(".totalPages"),
+            number("#pageSize"),
+        ))
+    }
+
+    fn from_json(json: &str) -> Result<Self> {
+        let list: JsonListTotal = serde_json::from_str(json)?;
+
+        Ok(ListTotal::new(list.total, None, list.page_size))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonListTotal {
+    #[serde(default)]
+    total: Option<u32>,
+    #[serde(default)]
+    page_size: Option<u32>,
+}
+
+/// How to handle an activity listed under several categories.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DedupStrategy {
+    /// Keep the first entry only.
+    KeepFirst,
+    /// Keep one entry, with categories of all entries merged into `categories`.
+    #[default]
+    Merge,
+    /// Keep one entry for each category.
+    KeepPerCategory,
+}
+
+/// Remove duplicated activities crawled from several categories, in the order they first appear.
+pub fn dedup_activities(activities: Vec<Activity>, strategy: DedupStrategy) -> Vec<Activity> {
+    let mut result: Vec<Activity> = Vec::new();
+    // Index in result of each (activity id, category), where category is 0 unless kept per category.
+    let mut index: HashMap<(i32, i32), usize> = HashMap::new();
+
+    for activity in activities {
+        let key = match strategy {
+            DedupStrategy::KeepPerCategory => (activity.id, activity.category),
+            _ => (activity.id, 0),
+        };
+        match index.get(&key) {
+            Some(&i) => {
+                let existing = &mut result[i];
+                let merged = existing.categories.contains(&activity.category);
+                if strategy == DedupStrategy::Merge && !merged {
+                    existing.categories.push(activity.category);
+                }
+            }
+            None => {
+                index.insert(key, result.len());
+                result.push(activity);
+            }
+        }
+    }
+    result
+}
+
+/// Keep activities whose credit is within `min` and `max`, both inclusive and optional. Activities of
+/// unknown credit are kept unless `exclude_unknown`.
+pub fn filter_by_credit(
+    activities: Vec<Activity>,
+    min: Option<f32>,
+    max: Option<f32>,
+    exclude_unknown: bool,
+) -> Vec<Activity> {
+    activities
+        .into_iter()
+        .filter(|x| match x.credit {
+            Some(credit) => min.is_none_or(|min| credit >= min) && max.is_none_or(|max| credit <= max),
+            None => !exclude_unknown,
+        })
+        .collect()
+}
+
+/// Keep activities starting within `from` and `to`, both inclusive and optional. Activities of unknown
+/// start time are kept.
+pub fn filter_by_date(
+    activities: Vec<Activity>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Vec<Activity> {
+    activities
+        .into_iter()
+        .filter(|x| match x.start_time.map(|x| x.date()) {
+            Some(date) => from.is_none_or(|from| date >= from) && to.is_none_or(|to| date <= to),
+            None => true,
+        })
+        .collect()
+}
+
+/// Whether the activity list page is blank, without the list container. A page with no activity
+/// still has an empty list, so a blank page usually means an upstream hiccup.
+pub fn is_blank_list_page(html_page: &str) -> bool {
+    let document = Html::parse_document(html_page);
+    let selector = Selector::parse("ul.ul_7").unwrap();
+
+    document.select(&selector).next().is_none()
+}
+
 #[derive(Debug)]
 pub struct JoinedActivity {
     pub title: String,
@@ -72,3 +327,189 @@ impl Parse for Vec<JoinedActivity> {
         Ok(activities)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+
+    use super::{
+        dedup_activities, filter_by_credit, filter_by_date, is_blank_list_page, parse_activity_list,
+        Activity, ActivityStatus, DedupStrategy, ListTotal, Parse,
+    };
+    use crate::parser::parse_body;
+
+    fn parse_category(file: &str, category: i32) -> Vec<Activity> {
+        let html_page = std::fs::read_to_string(file).unwrap();
+        let activities: Vec<Activity> = Parse::from_html(&html_page).unwrap();
+
+        activities
+            .into_iter()
+            .map(|mut s| {
+                s.category = category;
+                s.categories = vec![category];
+                s
+            })
+            .collect()
+    }
+
+    fn crawl() -> Vec<Activity> {
+        let mut activities = parse_category("html/第二课堂最近活动.html", 1);
+        activities.extend(parse_category("html/第二课堂活动列表跨类别页面.html", 7));
+        activities
+    }
+
+    #[test]
+    fn test_json_activity_list() {
+        let html_page = std::fs::read_to_string("html/第二课堂最近活动.html").unwrap();
+        let json = std::fs::read_to_string("html/第二课堂最近活动.json").unwrap();
+
+        let from_html: Vec<Activity> = parse_body(&html_page, Some("text/html;charset=UTF-8")).unwrap();
+        let from_json: Vec<Activity> = parse_body(&json, Some("application/json")).unwrap();
+        assert!(!from_json.is_empty());
+        assert_eq!(from_html, from_json);
+    }
+
+    #[test]
+    fn test_dedup_activities() {
+        let first_len = parse_category("html/第二课堂最近活动.html", 1).len();
+
+        let merged = dedup_activities(crawl(), DedupStrategy::Merge);
+        assert_eq!(merged.len(), first_len + 1);
+        let cross_listed: Vec<&Activity> = merged.iter().filter(|x| x.id == 1062210).collect();
+        assert_eq!(cross_listed.len(), 1);
+        assert_eq!(cross_listed[0].categories, vec![1, 7]);
+        assert_eq!(merged.last().unwrap().categories, vec![7]);
+
+        let kept = dedup_activities(crawl(), DedupStrategy::KeepFirst);
+        assert_eq!(kept.len(), first_len + 1);
+        assert!(kept.iter().all(|x| x.categories.len() == 1));
+
+        let per_category = dedup_activities(crawl(), DedupStrategy::KeepPerCategory);
+        assert_eq!(per_category.len(), first_len + 2);
+    }
+
+    #[test]
+    fn test_blank_list_page() {
+        let html_page = std::fs::read_to_string("html/第二课堂活动列表无结果页面.html").unwrap();
+        let activities: Vec<Activity> = Parse::from_html(&html_page).unwrap();
+        assert!(activities.is_empty());
+        assert!(!is_blank_list_page(&html_page));
+
+        let html_page = std::fs::read_to_string("html/第二课堂活动列表空白页面.html").unwrap();
+        let activities: Vec<Activity> = Parse::from_html(&html_page).unwrap();
+        assert!(activities.is_empty());
+        assert!(is_blank_list_page(&html_page));
+
+        let html_page = std::fs::read_to_string("html/第二课堂最近活动.html").unwrap();
+        assert!(!is_blank_list_page(&html_page));
+    }
+
+    #[test]
+    fn test_list_total() {
+        let html_page = std::fs::read_to_string("html/第二课堂活动列表无结果页面.html").unwrap();
+        let total = ListTotal::from_html(&html_page).unwrap();
+        assert_eq!(total.total_count, Some(15920));
+        assert_eq!(total.page_count, Some(80));
+
+        let html_page = std::fs::read_to_string("html/第二课堂活动列表增量页面.html").unwrap();
+        let total = ListTotal::from_html(&html_page).unwrap();
+        assert_eq!((total.total_count, total.page_count), (Some(5), Some(1)));
+
+        let json = std::fs::read_to_string("html/第二课堂活动列表_学分.json").unwrap();
+        let total: ListTotal = parse_body(&json, Some("application/json")).unwrap();
+        assert_eq!((total.total_count, total.page_count), (Some(5), Some(1)));
+
+        // No indicator on the blank page.
+        let html_page = std::fs::read_to_string("html/第二课堂活动列表空白页面.html").unwrap();
+        assert_eq!(ListTotal::from_html(&html_page).unwrap(), ListTotal::default());
+    }
+
+    #[test]
+    fn test_filter_by_credit() {
+        let json = std::fs::read_to_string("html/第二课堂活动列表_学分.json").unwrap();
+        let activities: Vec<Activity> = parse_body(&json, Some("application/json")).unwrap();
+        let ids = |activities: Vec<Activity>| activities.iter().map(|x| x.id).collect::<Vec<i32>>();
+
+        let filtered = filter_by_credit(activities, Some(0.5), Some(1.0), true);
+        assert_eq!(ids(filtered), vec![1062210, 1062212]);
+
+        let activities: Vec<Activity> = parse_body(&json, Some("application/json")).unwrap();
+        let filtered = filter_by_credit(activities, Some(0.5), None, false);
+        assert_eq!(ids(filtered), vec![1062210, 1062212, 1062211, 1062209]);
+    }
+
+    #[test]
+    fn test_validate() {
+        let json = std::fs::read_to_string("html/第二课堂活动列表_学分.json").unwrap();
+        let mut activities: Vec<Activity> = parse_body(&json, Some("application/json")).unwrap();
+        assert!(activities.iter().all(|x| x.validate().is_empty()));
+
+        activities[0].credit = Some(-0.5);
+        let warnings = activities[0].validate();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("credit -0.5 of activity"));
+    }
+
+    #[test]
+    fn test_activity_status() {
+        let html_page = std::fs::read_to_string("html/第二课堂活动列表状态页面.html").unwrap();
+        let activities: Vec<Activity> = Parse::from_html(&html_page).unwrap();
+        let status: Vec<(i32, ActivityStatus)> = activities.iter().map(|x| (x.id, x.status)).collect();
+
+        assert_eq!(
+            status,
+            vec![
+                (1062309, ActivityStatus::Open),
+                (1062308, ActivityStatus::Full),
+                (1062307, ActivityStatus::Ended),
+                (1062305, ActivityStatus::Open),
+                (1062210, ActivityStatus::Unknown),
+            ]
+        );
+
+        // Not shown in pages without the indicator.
+        let html_page = std::fs::read_to_string("html/第二课堂最近活动.html").unwrap();
+        let activities: Vec<Activity> = Parse::from_html(&html_page).unwrap();
+        assert!(activities.iter().all(|x| x.status == ActivityStatus::Unknown));
+    }
+
+    #[test]
+    fn test_filter_by_date() {
+        let html_page = std::fs::read_to_string("html/第二课堂活动列表状态页面.html").unwrap();
+        let activities: Vec<Activity> = Parse::from_html(&html_page).unwrap();
+        let start_time = activities[0].start_time.unwrap();
+        assert_eq!(start_time.to_string(), "2020-06-24 13:30:00");
+
+        let date = |day| NaiveDate::from_ymd_opt(2020, 6, day);
+        let filtered = filter_by_date(activities, date(20), date(23));
+        let ids: Vec<i32> = filtered.iter().map(|x| x.id).collect();
+        assert_eq!(ids, vec![1062308, 1062307, 1062305]);
+
+        let json = std::fs::read_to_string("html/第二课堂活动列表_学分.json").unwrap();
+        let activities: Vec<Activity> = parse_body(&json, Some("application/json")).unwrap();
+        assert!(activities.iter().all(|x| x.start_time.is_some()));
+        let filtered = filter_by_date(activities, date(18), None);
+        assert_eq!(filtered.iter().map(|x| x.id).collect::<Vec<i32>>(), vec![1062079]);
+    }
+
+    #[test]
+    fn test_malformed_rows() {
+        let html_page = std::fs::read_to_string("html/第二课堂活动列表增量页面.html").unwrap();
+        let banner = r#"<li><a href="/public/notice/banner.html">· 【通知】暑期活动安排</a> </li>
+        <li><a href="/public/activity/activityDetail.action?activityId=1062309">"#;
+        let html_page = html_page.replace(
+            r#"<li><a href="/public/activity/activityDetail.action?activityId=1062309">"#,
+            banner,
+        );
+
+        let parsed = parse_activity_list(&html_page, false).unwrap();
+        assert_eq!(parsed.rows.len(), 5);
+        assert_eq!(parsed.rows[0].id, 1062309);
+        assert_eq!(parsed.warnings.len(), 1);
+        assert!(parsed.warnings[0].starts_with("row 1 skipped"));
+        assert!(parse_activity_list(&html_page, true).is_err());
+
+        let html_page = std::fs::read_to_string("html/第二课堂最近活动.html").unwrap();
+        assert!(parse_activity_list(&html_page, true).unwrap().warnings.is_empty());
+    }
+}
@@ -0,0 +1,63 @@
+use scraper::{Html, Selector};
+use serde::Serialize;
+
+use crate::error::Result;
+use crate::parser::Parse;
+
+lazy_static! {
+    static ref RANK: Selector = Selector::parse("#ranking_rank").unwrap();
+    static ref POINTS: Selector = Selector::parse("#ranking_points").unwrap();
+}
+
+/// Position of the student on the participation leaderboard, shown in the personal center by some
+/// deployments only.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScRanking {
+    /// Rank on the leaderboard, or None if not ranked or not shown.
+    pub rank: Option<u32>,
+    /// Participation points counted for the rank.
+    pub points: Option<u32>,
+}
+
+fn number_of(document: &Html, selector: &Selector) -> Option<u32> {
+    document
+        .select(selector)
+        .next()
+        .and_then(|e| e.text().collect::<String>().trim().parse().ok())
+}
+
+impl Parse for ScRanking {
+    fn from_html(html_page: &str) -> Result<Self> {
+        let document = Html::parse_document(html_page);
+
+        // Both are None if the widget is not shown, and the rank is None for those not ranked yet.
+        let rank = number_of(&document, &RANK);
+        let points = number_of(&document, &POINTS);
+
+        Ok(ScRanking { rank, points })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Parse, ScRanking};
+
+    #[test]
+    fn test_ranking() {
+        let html_page = std::fs::read_to_string("html/第二课堂得分页面_排名.html").unwrap();
+        let ranking = ScRanking::from_html(&html_page).unwrap();
+
+        assert_eq!(ranking.rank, Some(12));
+        assert_eq!(ranking.points, Some(340));
+    }
+
+    #[test]
+    fn test_no_ranking() {
+        // The widget is not there at all.
+        let html_page = std::fs::read_to_string("html/第二课堂得分页面.html").unwrap();
+        let ranking = ScRanking::from_html(&html_page).unwrap();
+
+        assert_eq!(ranking.rank, None);
+        assert_eq!(ranking.points, None);
+    }
+}
@@ -5,7 +5,9 @@ use regex::Regex;
 use scraper::{ElementRef, Html, Selector};
 
 use crate::error::Result;
-use crate::parser::Parse;
+use crate::parser::{Parse, ParsedRows, ParserError};
+
+const AUTHSERVER: &str = "https://authserver.sit.edu.cn";
 
 const CLASSIFICATION: &[&str] = &[
     "主题报告",
@@ -27,6 +29,8 @@ lazy_static! {
     static ref ID_DETAIL: Selector = Selector::parse("td:nth-child(3)").unwrap();
     static ref CATEGORY_DETAIL: Selector = Selector::parse("td:nth-child(2)").unwrap();
     static ref SCORE_DETAIL: Selector = Selector::parse("td:nth-child(5) > span").unwrap();
+    static ref DATE_DETAIL: Selector = Selector::parse("td:nth-child(4)").unwrap();
+    static ref START_DATE: Regex = Regex::new(r"(\d{4})-(\d{1,2})-\d{1,2}").unwrap();
     static ref SCORE_DETAIL_PAGE: Selector =
         Selector::parse("#div1 > div.table_style_4 > form > table:nth-child(4) > tbody > tr").unwrap();
     static ref TOTAL_SCORE: Selector =
@@ -39,10 +43,13 @@ lazy_static! {
     static ref ACTIVITY_ID_DETAIL: Selector = Selector::parse("td:nth-child(2)").unwrap();
     static ref TIME_DETAL: Selector = Selector::parse("td:nth-child(4)").unwrap();
     static ref STATUS_DETAIL: Selector = Selector::parse("td:nth-child(5)").unwrap();
+    static ref OPERATION_DETAIL: Selector = Selector::parse("td:nth-child(6)").unwrap();
     static ref ACTIVITY_ID: Regex = Regex::new(r"activityId=(\d+)").unwrap();
+    static ref USER_NAME: Regex = Regex::new(r"欢迎您：\s*([^\s&<|]+)").unwrap();
+    static ref LOGIN_FORM: Selector = Selector::parse("#casLoginForm").unwrap();
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct ScScoreSummary {
     /// Effective score.
     pub effect: f32,
@@ -85,6 +92,39 @@ impl From<Vec<String>> for ScScoreSummary {
     }
 }
 
+/// Credits earned in a category on the score summary, against the requirement.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ScCategoryTotal {
+    /// Category name, like "主题报告".
+    pub name: String,
+    pub earned: f32,
+    /// Credits required in the category, if set.
+    pub required: Option<f32>,
+}
+
+impl ScScoreSummary {
+    /// Credits of each category, in the order of `CLASSIFICATION`, with requirements indexed by name.
+    pub fn by_category(&self, required: &HashMap<String, f32>) -> Vec<ScCategoryTotal> {
+        let earned = [
+            self.theme_report,
+            self.social_practice,
+            self.creativity,
+            self.safety_civilization,
+            self.charity,
+            self.campus_culture,
+        ];
+        CLASSIFICATION
+            .iter()
+            .zip(earned)
+            .map(|(&name, earned)| ScCategoryTotal {
+                name: name.to_string(),
+                earned,
+                required: required.get(name).copied(),
+            })
+            .collect()
+    }
+}
+
 impl Parse for ScScoreSummary {
     fn from_html(html_page: &str) -> Result<Self> {
         let document = Html::parse_document(html_page);
@@ -114,13 +154,25 @@ impl Parse for ScScoreSummary {
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct ScScoreItem {
     pub activity_id: i32,
     pub category: i32,
     pub amount: f32,
 }
 
+impl ScScoreItem {
+    /// Check parsed values, and return a warning for each one not plausible.
+    pub fn validate(&self) -> Vec<String> {
+        if self.amount < 0.0 {
+            let (amount, id) = (self.amount, self.activity_id);
+            vec![format!("amount {} of activity {} is negative", amount, id)]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
 fn score_map_detail(item: ElementRef) -> Result<ScScoreItem> {
     let id: Option<i32> = item
         .select(&ID_DETAIL)
@@ -135,7 +187,8 @@ fn score_map_detail(item: ElementRef) -> Result<ScScoreItem> {
     let add_score: Option<f32> = item
         .select(&SCORE_DETAIL)
         .next()
-        .map(|x| x.inner_html().trim().parse().unwrap_or_default());
+        .map(|x| x.inner_html().trim().parse())
+        .transpose()?;
 
     // TODO: Add error handler.
     Ok(ScScoreItem {
@@ -160,24 +213,45 @@ fn trans_category_to_i32(x: &str) -> i32 {
     }
 }
 
+/// Drop rows without score, and keep malformed ones to report.
 fn filter_zero_score(x: &Result<ScScoreItem>) -> bool {
-    if let Ok(e) = x {
-        e.amount > 0.01
-    } else {
-        false
-    }
+    x.as_ref().map_or(true, |e| e.amount > 0.01)
+}
+
+/// Whether the page is the authserver login page, which the score page redirects to when the score
+/// service requires authentication again.
+pub fn is_login_page(html_page: &str) -> bool {
+    let document = Html::parse_document(html_page);
+    document.select(&LOGIN_FORM).next().is_some()
+}
+
+/// Return URL carried by the login page, in the `service` parameter of the login form. Authserver
+/// redirects to it after login.
+pub fn login_return_url(html_page: &str) -> Option<String> {
+    let document = Html::parse_document(html_page);
+    let action = document.select(&LOGIN_FORM).next()?.value().attr("action")?;
+    let url = reqwest::Url::parse(AUTHSERVER).ok()?.join(action).ok()?;
+
+    url.query_pairs()
+        .find(|(k, _)| k == "service")
+        .map(|(_, v)| v.to_string())
 }
 
 pub fn get_my_score_list(html_page: &str) -> Result<Vec<ScScoreItem>> {
+    Ok(parse_my_score_list(html_page, false)?.rows)
+}
+
+/// Parse the score page, skipping malformed rows unless `strict`.
+fn parse_my_score_list(html_page: &str, strict: bool) -> Result<ParsedRows<ScScoreItem>> {
     let document = Html::parse_document(html_page);
-    let score_items = document
+    let rows = document
         .select(&SCORE_DETAIL_PAGE)
         .map(score_map_detail)
-        .filter(filter_zero_score)
-        .collect::<Result<Vec<ScScoreItem>>>()?;
+        .filter(filter_zero_score);
+    let ParsedRows { rows, warnings } = ParsedRows::collect(rows, strict)?;
 
     // Group and accumulate score by activity id.
-    let map = score_items
+    let map = rows
         .into_iter()
         .fold(HashMap::<(i32, i32), f32>::new(), |mut map, x| {
             if let Some(old) = map.get_mut(&(x.activity_id, x.category)) {
@@ -196,14 +270,193 @@ pub fn get_my_score_list(html_page: &str) -> Result<Vec<ScScoreItem>> {
             amount,
         })
         .collect();
-    Ok(result)
+    Ok(ParsedRows {
+        rows: result,
+        warnings,
+    })
+}
+
+/// Term of the date, like "2019-2020-1". The first term starts in September and lasts until January,
+/// and the rest of the year, summer vacation included, falls in the second term.
+fn term_of(year: i32, month: u32) -> String {
+    match month {
+        9..=12 => format!("{}-{}-1", year, year + 1),
+        1 => format!("{}-{}-1", year - 1, year),
+        _ => format!("{}-{}-2", year - 1, year),
+    }
+}
+
+/// Terms of activities on the score page, indexed by activity id, by the start date column. Activities
+/// without a date are left out.
+pub fn get_score_terms(html_page: &str) -> HashMap<i32, String> {
+    let document = Html::parse_document(html_page);
+    let mut terms = HashMap::new();
+
+    for item in document.select(&SCORE_DETAIL_PAGE) {
+        let id = item
+            .select(&ID_DETAIL)
+            .next()
+            .and_then(|x| x.inner_html().trim().parse::<i32>().ok());
+        let date = item.select(&DATE_DETAIL).next().map(|x| x.inner_html());
+        let term = date.as_deref().and_then(|x| START_DATE.captures(x)).and_then(|c| {
+            let year = c[1].parse().ok()?;
+            let month = c[2].parse().ok()?;
+            Some(term_of(year, month))
+        });
+        if let (Some(id), Some(term)) = (id, term) {
+            terms.insert(id, term);
+        }
+    }
+    terms
 }
 
+/// Credits earned in a term.
 #[derive(Debug, Clone, serde::Serialize)]
+pub struct ScTermCredit {
+    /// Term like "2019-2020-1", or `None` if unknown.
+    pub term: Option<String>,
+    /// Score items in the term, ordered by category and activity id.
+    pub items: Vec<ScScoreItem>,
+    pub term_credit_total: f32,
+}
+
+/// Group score items by term in `terms`, and return terms in time order. Items of unknown term are
+/// put together at the end.
+pub fn group_by_term(items: Vec<ScScoreItem>, terms: &HashMap<i32, String>) -> Vec<ScTermCredit> {
+    let mut map = HashMap::<Option<String>, Vec<ScScoreItem>>::new();
+    for item in items {
+        let term = terms.get(&item.activity_id).cloned();
+        map.entry(term).or_default().push(item);
+    }
+
+    let mut result: Vec<ScTermCredit> = map
+        .into_iter()
+        .map(|(term, mut items)| {
+            items.sort_by_key(|x| (x.category, x.activity_id));
+            ScTermCredit {
+                term,
+                term_credit_total: items.iter().map(|x| x.amount).sum(),
+                items,
+            }
+        })
+        .collect();
+    // Terms are named by years, so they sort in time order as strings.
+    result.sort_by(|a, b| match (&a.term, &b.term) {
+        (Some(x), Some(y)) => x.cmp(y),
+        (x, y) => y.is_some().cmp(&x.is_some()),
+    });
+    result
+}
+
+/// Score items changed since the state the client knows.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ScScoreDiff {
+    /// Items newly awarded.
+    pub added: Vec<ScScoreItem>,
+    /// Items no longer on the score page.
+    pub removed: Vec<ScScoreItem>,
+    /// Items whose amount is corrected, with the current amount.
+    pub changed: Vec<ScScoreItem>,
+    /// Current score summary.
+    pub summary: ScScoreSummary,
+}
+
+/// Compare score items by (activity id, category), and return (added, removed, changed) items.
+pub fn diff_score_items(
+    prior: &[ScScoreItem],
+    current: &[ScScoreItem],
+) -> (Vec<ScScoreItem>, Vec<ScScoreItem>, Vec<ScScoreItem>) {
+    let key = |x: &ScScoreItem| (x.activity_id, x.category);
+    let prior_map: HashMap<(i32, i32), &ScScoreItem> = prior.iter().map(|x| (key(x), x)).collect();
+    let current_map: HashMap<(i32, i32), &ScScoreItem> = current.iter().map(|x| (key(x), x)).collect();
+
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    for item in current {
+        match prior_map.get(&key(item)) {
+            None => added.push(item.clone()),
+            Some(old) if (old.amount - item.amount).abs() > 0.001 => changed.push(item.clone()),
+            _ => {}
+        }
+    }
+    let mut removed: Vec<ScScoreItem> = prior
+        .iter()
+        .filter(|x| !current_map.contains_key(&key(x)))
+        .cloned()
+        .collect();
+
+    for items in [&mut added, &mut removed, &mut changed] {
+        items.sort_by_key(key);
+    }
+    (added, removed, changed)
+}
+
+/// Credits earned in a category.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ScCategoryCredit {
+    /// Category id
+    pub category: i32,
+    /// Count of score items in the category.
+    pub count: usize,
+    /// Total amount earned in the category.
+    pub amount: f32,
+}
+
+/// Group score items by category, and return categories with nonzero credit, ordered by category id.
+pub fn count_category_credits(items: &[ScScoreItem]) -> Vec<ScCategoryCredit> {
+    let mut map = HashMap::<i32, ScCategoryCredit>::new();
+    for item in items.iter().filter(|x| x.amount > 0.001) {
+        let credit = map.entry(item.category).or_insert(ScCategoryCredit {
+            category: item.category,
+            count: 0,
+            amount: 0.0,
+        });
+        credit.count += 1;
+        credit.amount += item.amount;
+    }
+
+    let mut result: Vec<ScCategoryCredit> = map.into_values().collect();
+    result.sort_by_key(|x| x.category);
+    result
+}
+
+/// Second classroom credit transcript, composed of the summary, credits by category and all items.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ScTranscript {
+    /// Student name shown on the page.
+    pub student: String,
+    pub summary: ScScoreSummary,
+    /// Credits by category, ordered by category id.
+    pub categories: Vec<ScCategoryCredit>,
+    /// Score items, ordered by category and activity id.
+    pub items: Vec<ScScoreItem>,
+}
+
+impl Parse for ScTranscript {
+    fn from_html(html_page: &str) -> Result<Self> {
+        let student = USER_NAME
+            .captures(html_page)
+            .map(|c| c[1].to_string())
+            .unwrap_or_default();
+        let mut items = get_my_score_list(html_page)?;
+        items.sort_by_key(|x| (x.category, x.activity_id));
+
+        Ok(ScTranscript {
+            student,
+            summary: ScScoreSummary::from_html(html_page)?,
+            categories: count_category_credits(&items),
+            items,
+        })
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ScActivityItem {
     pub activity_id: i32,
     pub time: DateTime<Local>,
     pub status: String,
+    /// Attended, and the "评价" link is shown, but the student has not evaluated yet.
+    pub pending_evaluation: bool,
 }
 
 fn activity_map_detail(item: ElementRef) -> Result<ScActivityItem> {
@@ -219,43 +472,60 @@ fn activity_map_detail(item: ElementRef) -> Result<ScActivityItem> {
         })
     });
 
-    let time: Option<DateTime<Local>> = item.select(&TIME_DETAL).next().map(|x| {
-        let native_time =
-            NaiveDateTime::parse_from_str(x.inner_html().trim(), "%Y-%m-%d %H:%M:%S").unwrap();
-        let mut time = DateTime::from_utc(native_time, FixedOffset::east(8 * 3600));
-        let eight_hour = Duration::hours(8);
-        time = time - eight_hour;
-        time
-    });
+    let time_text = item
+        .select(&TIME_DETAL)
+        .next()
+        .ok_or_else(|| ParserError::NoSuchElement(String::from("td:nth-child(4)")))?
+        .inner_html();
+    let native_time = NaiveDateTime::parse_from_str(time_text.trim(), "%Y-%m-%d %H:%M:%S")?;
+    let mut time: DateTime<Local> = DateTime::from_utc(native_time, FixedOffset::east(8 * 3600));
+    let eight_hour = Duration::hours(8);
+    time -= eight_hour;
 
     let status: Option<String> = item
         .select(&STATUS_DETAIL)
         .next()
         .map(|x| String::from(x.inner_html().trim()));
 
+    let pending_evaluation = item
+        .select(&OPERATION_DETAIL)
+        .next()
+        .map(|x| x.inner_html().contains("showassess("))
+        .unwrap_or(false);
+
     Ok(ScActivityItem {
         activity_id: activity_id.unwrap_or_default(),
-        time: time.unwrap(),
+        time,
         status: status.unwrap_or_default(),
+        pending_evaluation,
     })
 }
 
+/// Drop deleted activities, and keep malformed rows to report.
 fn filter_delete_activity(x: &Result<ScActivityItem>) -> bool {
-    if let Ok(e) = x {
-        e.activity_id != 0
-    } else {
-        false
-    }
+    x.as_ref().map_or(true, |e| e.activity_id != 0)
 }
 
 pub fn get_my_activity_list(html_page: &str) -> Result<Vec<ScActivityItem>> {
-    let document = Html::parse_document(html_page);
+    Ok(parse_my_activity_list(html_page, false)?.rows)
+}
 
-    document
+/// Parse activities the student joined, skipping malformed rows unless `strict`.
+fn parse_my_activity_list(html_page: &str, strict: bool) -> Result<ParsedRows<ScActivityItem>> {
+    let document = Html::parse_document(html_page);
+    let rows = document
         .select(&ACTIVITY_DETAIL)
         .map(activity_map_detail)
-        .filter(filter_delete_activity)
-        .collect()
+        .filter(filter_delete_activity);
+
+    ParsedRows::collect(rows, strict)
+}
+
+/// Activities attended but not evaluated yet, whose credit is finalized after evaluation.
+pub fn get_pending_evaluation_list(html_page: &str) -> Result<Vec<ScActivityItem>> {
+    let activities = get_my_activity_list(html_page)?;
+
+    Ok(activities.into_iter().filter(|x| x.pending_evaluation).collect())
 }
 #[cfg(test)]
 mod test {
@@ -279,6 +549,26 @@ mod test {
         assert_eq!(origin, target)
     }
 
+    #[test]
+    fn test_summary_by_category() {
+        use std::collections::HashMap;
+
+        use super::{Parse, ScScoreSummary};
+
+        let html_page = std::fs::read_to_string("html/第二课堂得分页面.html").unwrap();
+        let summary = ScScoreSummary::from_html(&html_page).unwrap();
+        let mut required = HashMap::new();
+        required.insert(String::from("主题报告"), 1.5);
+
+        let categories = summary.by_category(&required);
+        let names: Vec<&str> = categories.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, super::CLASSIFICATION);
+        assert_eq!(categories[0].earned, 1.35);
+        assert_eq!(categories[0].required, Some(1.5));
+        assert_eq!(categories[5].earned, 0.8);
+        assert_eq!(categories[5].required, None);
+    }
+
     #[test]
     fn test_score_detail() {
         use crate::parser::sc::score::get_my_score_list;
@@ -287,6 +577,165 @@ mod test {
         println!("{:?}", detail);
     }
 
+    #[test]
+    fn test_validate_score_item() {
+        use crate::parser::sc::score::get_my_score_list;
+
+        let html_page = std::fs::read_to_string("html/第二课堂得分页面.html").unwrap();
+        let mut items = get_my_score_list(&html_page).unwrap();
+        assert!(items.iter().all(|x| x.validate().is_empty()));
+
+        items[0].amount = -1.0;
+        let warnings = items[0].validate();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("amount -1 of activity"));
+    }
+
+    #[test]
+    fn test_login_return_url() {
+        use crate::parser::sc::score::login_return_url;
+
+        let html_page = std::fs::read_to_string("html/统一认证登录页面_会话超时.html").unwrap();
+        assert_eq!(
+            login_return_url(&html_page).as_deref(),
+            Some("http://sc.sit.edu.cn/public/pcenter/activityOrderList.action?pageSize=200")
+        );
+        let html_page = std::fs::read_to_string("html/第二课堂得分页面.html").unwrap();
+        assert_eq!(login_return_url(&html_page), None);
+    }
+
+    #[test]
+    fn test_pending_evaluation() {
+        use crate::parser::sc::score::{get_my_activity_list, get_pending_evaluation_list};
+        let html_page = std::fs::read_to_string("html/第二课堂待评价活动页面.html").unwrap();
+        assert_eq!(get_my_activity_list(&html_page).unwrap().len(), 2);
+
+        let pending = get_pending_evaluation_list(&html_page).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].activity_id, 1067223);
+        assert_eq!(pending[0].status, "通过");
+    }
+
+    #[test]
+    fn test_malformed_rows() {
+        use crate::parser::sc::score::{parse_my_activity_list, parse_my_score_list};
+
+        let html_page = std::fs::read_to_string("html/第二课堂待评价活动页面.html").unwrap();
+        let parsed = parse_my_activity_list(&html_page, true).unwrap();
+        assert!(parsed.warnings.is_empty());
+        let html_page = std::fs::read_to_string("html/第二课堂得分页面.html").unwrap();
+        let parsed = parse_my_score_list(&html_page, true).unwrap();
+        assert!(parsed.warnings.is_empty());
+
+        let html_page = std::fs::read_to_string("html/第二课堂待评价活动页面.html")
+            .unwrap()
+            .replace("2021-5-18 12:26:37", "2021年5月18日");
+        let parsed = parse_my_activity_list(&html_page, false).unwrap();
+        assert_eq!(parsed.rows.len(), 1);
+        assert_eq!(parsed.rows[0].activity_id, 1067223);
+        assert_eq!(parsed.warnings.len(), 1);
+        assert!(parse_my_activity_list(&html_page, true).is_err());
+    }
+
+    #[test]
+    fn test_login_page() {
+        use crate::parser::sc::score::is_login_page;
+
+        let html_page = std::fs::read_to_string("html/统一认证登录页面.html").unwrap();
+        assert!(is_login_page(&html_page));
+        let html_page = std::fs::read_to_string("html/第二课堂得分页面.html").unwrap();
+        assert!(!is_login_page(&html_page));
+    }
+
+    #[test]
+    fn test_score_diff() {
+        use crate::parser::sc::score::{diff_score_items, get_my_score_list};
+        let html_page = std::fs::read_to_string("html/第二课堂得分页面.html").unwrap();
+        let current = get_my_score_list(&html_page).unwrap();
+
+        let mut prior = current.clone();
+        let new_item = prior.pop().unwrap();
+        prior[0].amount += 0.5;
+        let corrected = current
+            .iter()
+            .find(|x| x.activity_id == prior[0].activity_id && x.category == prior[0].category)
+            .cloned();
+
+        let (added, removed, changed) = diff_score_items(&prior, &current);
+        assert_eq!(added, vec![new_item]);
+        assert!(removed.is_empty());
+        assert_eq!(changed, corrected.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_category_credits() {
+        use crate::parser::sc::score::{count_category_credits, get_my_score_list};
+        let html_page = std::fs::read_to_string("html/第二课堂得分页面.html").unwrap();
+        let items = get_my_score_list(&html_page).unwrap();
+        let credits = count_category_credits(&items);
+
+        let categories: Vec<(i32, usize)> = credits.iter().map(|x| (x.category, x.count)).collect();
+        assert_eq!(
+            categories,
+            vec![(1, 2), (2, 2), (3, 20), (4, 3), (5, 4), (7, 7), (8, 7), (9, 1)]
+        );
+        assert!(credits.iter().all(|x| x.amount > 0.0));
+    }
+
+    #[test]
+    fn test_term_credits() {
+        use crate::parser::sc::score::{get_my_score_list, get_score_terms, group_by_term};
+
+        let html_page = std::fs::read_to_string("html/第二课堂得分页面_两学期.html").unwrap();
+        let items = get_my_score_list(&html_page).unwrap();
+        let terms = get_score_terms(&html_page);
+        assert_eq!(terms.len(), 5);
+        assert_eq!(terms[&1063220], "2019-2020-1");
+
+        let credits = group_by_term(items, &terms);
+        let grouped: Vec<(Option<&str>, Vec<i32>)> = credits
+            .iter()
+            .map(|x| (x.term.as_deref(), x.items.iter().map(|x| x.activity_id).collect()))
+            .collect();
+        assert_eq!(
+            grouped,
+            vec![
+                (Some("2019-2020-1"), vec![1061101, 1057314, 1063220]),
+                (Some("2019-2020-2"), vec![1065308, 1066012]),
+                (None, vec![1066504]),
+            ]
+        );
+        let totals: Vec<f32> = credits.iter().map(|x| x.term_credit_total).collect();
+        for (x, y) in totals.iter().zip([1.0, 1.0, 0.1]) {
+            assert!((x - y).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_transcript() {
+        use crate::parser::sc::score::{count_category_credits, get_my_score_list, ScTranscript};
+        use crate::parser::Parse;
+
+        let html_page = std::fs::read_to_string("html/第二课堂得分页面.html").unwrap();
+        let transcript = ScTranscript::from_html(&html_page).unwrap();
+        let items = get_my_score_list(&html_page).unwrap();
+
+        assert_eq!(transcript.student, "xxx");
+        assert_eq!(transcript.summary.effect, 5.85);
+        // Amounts are summed in another order, so compare them approximately.
+        let categories = count_category_credits(&items);
+        assert_eq!(transcript.categories.len(), categories.len());
+        for (x, y) in transcript.categories.iter().zip(categories.iter()) {
+            assert_eq!((x.category, x.count), (y.category, y.count));
+            assert!((x.amount - y.amount).abs() < 1e-4);
+        }
+        assert_eq!(transcript.items.len(), items.len());
+        assert!(transcript
+            .items
+            .windows(2)
+            .all(|w| (w[0].category, w[0].activity_id) <= (w[1].category, w[1].activity_id)));
+    }
+
     #[test]
     fn test_activity_detail() {
         use crate::parser::sc::score::get_my_activity_list;
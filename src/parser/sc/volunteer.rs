@@ -0,0 +1,90 @@
+use scraper::{ElementRef, Html, Selector};
+use serde::Serialize;
+
+use crate::error::Result;
+use crate::parser::Parse;
+
+lazy_static! {
+    static ref TOTAL_HOURS: Selector = Selector::parse("#volunteer_hours").unwrap();
+    static ref ENTRY: Selector = Selector::parse("#volunteer_list > tbody > tr").unwrap();
+    static ref COLUMN: Selector = Selector::parse("td").unwrap();
+}
+
+/// Volunteer service of an activity.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VolunteerEntry {
+    pub activity_id: i32,
+    pub title: String,
+    /// Service date, like "2021-4-10"
+    pub date: String,
+    pub hours: f32,
+}
+
+/// Volunteer hours, which are counted apart from credits.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VolunteerHours {
+    pub total_hours: f32,
+    pub entries: Vec<VolunteerEntry>,
+}
+
+fn text_of(e: ElementRef) -> String {
+    e.text().collect::<String>().trim().to_string()
+}
+
+fn parse_entry(row: ElementRef) -> Option<VolunteerEntry> {
+    let cols: Vec<String> = row.select(&COLUMN).map(text_of).collect();
+    if cols.len() < 4 {
+        return None;
+    }
+    Some(VolunteerEntry {
+        activity_id: cols[0].parse().ok()?,
+        title: cols[1].clone(),
+        date: cols[2].clone(),
+        hours: cols[3].parse().unwrap_or_default(),
+    })
+}
+
+impl Parse for VolunteerHours {
+    fn from_html(html_page: &str) -> Result<Self> {
+        let document = Html::parse_document(html_page);
+
+        let entries: Vec<VolunteerEntry> = document.select(&ENTRY).filter_map(parse_entry).collect();
+        // Students without any service may see "暂无" or nothing.
+        let total_hours = document
+            .select(&TOTAL_HOURS)
+            .next()
+            .and_then(|e| text_of(e).parse().ok())
+            .unwrap_or_else(|| entries.iter().map(|x| x.hours).sum());
+
+        Ok(VolunteerHours { total_hours, entries })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Parse, VolunteerHours};
+
+    #[test]
+    fn test_volunteer_hours() {
+        let html_page = std::fs::read_to_string("html/第二课堂志愿时长页面.html").unwrap();
+        let hours = VolunteerHours::from_html(&html_page).unwrap();
+
+        assert_eq!(hours.total_hours, 12.5);
+        assert_eq!(hours.entries.len(), 2);
+        assert_eq!(hours.entries[0].activity_id, 1066909);
+        assert_eq!(hours.entries[0].title, "2021年春季学期图书馆志愿服务");
+        assert_eq!(hours.entries[1].hours, 4.5);
+    }
+
+    #[test]
+    fn test_zero_volunteer_hours() {
+        let html_page = std::fs::read_to_string("html/第二课堂志愿时长页面.html").unwrap();
+        let (head, rest) = html_page.split_once("<tbody>").unwrap();
+        let (_, tail) = rest.split_once("</tbody>").unwrap();
+        let html_page = format!("{}<tbody></tbody>{}", head, tail).replace(">12.5<", ">暂无<");
+        let hours = VolunteerHours::from_html(&html_page).unwrap();
+
+        assert_eq!(hours.total_hours, 0.0);
+        assert!(hours.entries.is_empty());
+    }
+}
@@ -1,31 +1,59 @@
 use serde::{Deserialize, Serialize};
+use strum_macros::IntoStaticStr;
 
-use auth::{PortalAuthRequest, PortalAuthResponse};
+use auth::{
+    CredentialTestRequest, CredentialTestResult, PortalAuthRequest, PortalAuthResponse,
+    PortalProfileRequest,
+};
 pub use edu::{
     ClassRequest, CourseRequest, MajorRequest, ProfileRequest, ScoreDetailRequest, ScoreRequest,
     TimeTableRequest,
 };
 pub use error::{ActionError, ErrorResponse};
+pub use metrics::{Metrics, MetricsExportRequest};
+pub use policy::{watch_config, CommandPolicy};
+pub use recent_errors::{ErrorRecord, RecentErrors, RecentErrorsRequest};
 pub use library::{BookHoldingRequest, SearchLibraryRequest, SearchWay, SortOrder, SortWay};
-use report::AgentInfo;
-pub use report::AgentInfoRequest;
-pub use sc::{ActivityDetailRequest, ActivityListRequest, ScActivityRequest, ScScoreItemRequest};
+use report::{AgentInfo, ConfigSummary};
+pub use report::{AgentInfoRequest, ConfigRequest};
+pub use sc::{
+    ActivityCsvRequest, ActivityDetailBatchRequest, ActivityDetailBatchResponse, ActivityDetailRequest,
+    ActivityExistsRequest, ActivityHistory, ActivityHistoryRequest, ActivityEvent,
+    ActivityListDeltaRequest, ActivityListRequest, ActivityListResponse, ApplySnapshot,
+    AvailableActivitiesRequest, DashboardCache, ExportedDashboard, ExportedImage, ImageCache,
+    JoinedActivityDetail, JoinedDetailsRequest, OrganizerActivitiesRequest, ResolveActivityRequest, ResolvedActivity, ScActivityCommentsRequest,
+    ScActivityRequest, ScCancelRequest, ScCategory, ScCategoryCreditRequest, ScCategoryListRequest,
+    ScDashboard, ScDashboardRequest, ScJoinBatchRequest, ScJoinRequest, ScJoinResponse,
+    ScPendingEvaluationRequest, ScRankingRequest, ScScoreDiffRequest, ScScoreItemRequest,
+    ScScoreSummaryRequest, ScScoreSummaryResponse, ScTermCreditRequest, ScTranscriptRequest,
+    ScVolunteerHoursRequest, SubscribeActivityRequest, Subscriptions, UnsubscribeActivityRequest,
+    UpcomingActivitiesRequest, HISTORY_TREE,
+};
 
-use crate::agent::SharedData;
+use crate::agent::{ControlCommand, SharedData};
 pub use crate::net::auth::portal_login;
-use crate::parser::{Activity, ActivityDetail, Course, HoldingPreviews, Major, ScActivityItem, ScScoreItem, Score, ScoreDetail, SearchLibraryResult, ExpensePage};
+use crate::parser::{
+    ActivityBrief, ActivityDetail, Course, ExpensePage, HoldingPreviews, Major, PortalProfile, Profile,
+    ScActivityComments, ScActivityItem, ScCategoryCredit, ScJoinResult, ScRanking, ScScoreDiff,
+    ScScoreItem, ScTermCredit, ScTranscript, Score, ScoreDetail, SearchLibraryResult, VolunteerHours,
+};
 use crate::service::expense::ExpenseRequest;
 
 mod auth;
 mod edu;
 mod error;
 mod library;
+mod metrics;
+mod policy;
+mod recent_errors;
 pub mod report;
 mod sc;
 mod expense;
 
 /// Response payload
-#[derive(Debug, Deserialize)]
+///
+/// Bincode encodes a variant by its position, so new variants are appended at the end.
+#[derive(Debug, Deserialize, IntoStaticStr)]
 pub enum RequestPayload {
     None,
     Ping(String),
@@ -38,36 +66,100 @@ pub enum RequestPayload {
     MajorList(MajorRequest),
     // ClassList(ClassRequest),
     // CourseList(CourseRequest),
-    // Profile(ProfileRequest),
     TimeTable(TimeTableRequest),
     Score(ScoreRequest),
     ScoreDetail(ScoreDetailRequest),
     SearchLibrary(SearchLibraryRequest),
     BookHoldingInfo(BookHoldingRequest),
     CardExpense(ExpenseRequest),
+    PortalProfile(PortalProfileRequest),
+    ActivityHistory(ActivityHistoryRequest),
+    ScScoreDiff(ScScoreDiffRequest),
+    ScCategoryCredit(ScCategoryCreditRequest),
+    ActivityExists(ActivityExistsRequest),
+    ScJoin(ScJoinRequest),
+    Config(ConfigRequest),
+    ScJoinBatch(ScJoinBatchRequest),
+    MetricsExport(MetricsExportRequest),
+    Profile(ProfileRequest),
+    ScTranscript(ScTranscriptRequest),
+    ScPendingEvaluation(ScPendingEvaluationRequest),
+    SubscribeActivity(SubscribeActivityRequest),
+    UnsubscribeActivity(UnsubscribeActivityRequest),
+    ScVolunteerHours(ScVolunteerHoursRequest),
+    ActivityListDelta(ActivityListDeltaRequest),
+    OrganizerActivities(OrganizerActivitiesRequest),
+    ScActivityComments(ScActivityCommentsRequest),
+    ScDashboard(ScDashboardRequest),
+    AvailableActivities(AvailableActivitiesRequest),
+    RecentErrors(RecentErrorsRequest),
+    CredentialTest(CredentialTestRequest),
+    UpcomingActivities(UpcomingActivitiesRequest),
+    ActivityCsv(ActivityCsvRequest),
+    ResolveActivity(ResolveActivityRequest),
+    JoinedDetails(JoinedDetailsRequest),
+    ScCancel(ScCancelRequest),
+    ScTermCredit(ScTermCreditRequest),
+    ScCategoryList(ScCategoryListRequest),
+    ScScoreSummary(ScScoreSummaryRequest),
+    ActivityDetailBatch(ActivityDetailBatchRequest),
+    /// Command pushed by the server rather than a client.
+    Control(ControlCommand),
+    ScRanking(ScRankingRequest),
 }
 
 /// Response payload
+///
+/// Bincode encodes a variant by its position, so new variants are appended at the end.
 #[derive(Debug, Serialize)]
 pub enum ResponsePayload {
     None,
     Pong(String),
     Credential(AgentInfo),
     PortalAuth(PortalAuthResponse),
-    ActivityList(Vec<Activity>),
+    ActivityList(ActivityListResponse),
     ActivityDetail(Box<ActivityDetail>),
     ScMyScore(Vec<ScScoreItem>),
     ScMyActivity(Vec<ScActivityItem>),
     MajorList(Vec<Major>),
     // ClassList(Vec<Class>),
     // CourseList(Vec<Course>),
-    // Profile(Profile),
     TimeTable(Vec<Course>),
     Score(Vec<Score>),
     ScoreDetail(Vec<ScoreDetail>),
     SearchLibrary(SearchLibraryResult),
     BookHoldingInfo(HoldingPreviews),
     CardExpense(ExpensePage),
+    PortalProfile(PortalProfile),
+    ActivityHistory(Vec<ApplySnapshot>),
+    ScScoreDiff(ScScoreDiff),
+    ScCategoryCredit(Vec<ScCategoryCredit>),
+    ActivityExists(ActivityBrief),
+    ScJoin(ScJoinResponse),
+    Config(ConfigSummary),
+    ScJoinBatch(Vec<(i32, ScJoinResult)>),
+    Metrics(String),
+    Profile(Profile),
+    ScTranscript(ScTranscript),
+    ScPendingEvaluation(Vec<ScActivityItem>),
+    ActivityEvents(Vec<ActivityEvent>),
+    ScVolunteerHours(VolunteerHours),
+    OrganizerActivities(Vec<ActivityDetail>),
+    ScActivityComments(ScActivityComments),
+    ScDashboard(ScDashboard),
+    AvailableActivities(Vec<ActivityDetail>),
+    RecentErrors(Vec<ErrorRecord>),
+    CredentialTest(CredentialTestResult),
+    UpcomingActivities(Vec<ActivityDetail>),
+    ActivityCsv(String),
+    ResolveActivity(ResolvedActivity),
+    JoinedDetails(Vec<JoinedActivityDetail>),
+    ScCancel(Vec<ScActivityItem>),
+    ScTermCredit(Vec<ScTermCredit>),
+    ScCategories(Vec<ScCategory>),
+    ScScoreSummary(ScScoreSummaryResponse),
+    ActivityDetailBatch(ActivityDetailBatchResponse),
+    ScRanking(ScRanking),
 }
 
 #[async_trait::async_trait]
@@ -91,26 +183,213 @@ macro_rules! make_parameter {
 pub type ResponseResult = std::result::Result<ResponsePayload, ErrorResponse>;
 
 impl RequestPayload {
+    /// Command name, which is the variant name.
+    pub fn name(&self) -> &'static str {
+        self.into()
+    }
+
+    /// Account and password carried by the request, if any.
+    fn credential(&self) -> Option<(&str, &str)> {
+        let (account, password) = match self {
+            RequestPayload::PortalAuth(r) => (&r.account, &r.credential),
+            RequestPayload::PortalProfile(r) => (&r.account, &r.password),
+            RequestPayload::CredentialTest(r) => (&r.account, &r.password),
+            RequestPayload::UpcomingActivities(r) => (&r.account, &r.password),
+            RequestPayload::ScMyScore(r) => (&r.account, &r.password),
+            RequestPayload::ScScoreDiff(r) => (&r.account, &r.password),
+            RequestPayload::ScCategoryCredit(r) => (&r.account, &r.password),
+            RequestPayload::ScTermCredit(r) => (&r.account, &r.password),
+            RequestPayload::ScScoreSummary(r) => (&r.account, &r.password),
+            RequestPayload::ScTranscript(r) => (&r.account, &r.password),
+            RequestPayload::ScMyActivity(r) => (&r.account, &r.password),
+            RequestPayload::JoinedDetails(r) => (&r.account, &r.password),
+            RequestPayload::ScPendingEvaluation(r) => (&r.account, &r.password),
+            RequestPayload::ScVolunteerHours(r) => (&r.account, &r.password),
+            RequestPayload::ScRanking(r) => (&r.account, &r.password),
+            RequestPayload::ScDashboard(r) => (&r.account, &r.password),
+            RequestPayload::ScJoin(r) => (&r.account, &r.password),
+            RequestPayload::ScJoinBatch(r) => (&r.account, &r.password),
+            RequestPayload::ScCancel(r) => (&r.account, &r.password),
+            RequestPayload::MajorList(r) => (&r.account, &r.password),
+            RequestPayload::Profile(r) => (&r.account, &r.password),
+            RequestPayload::TimeTable(r) => (&r.account, &r.password),
+            RequestPayload::Score(r) => (&r.account, &r.password),
+            RequestPayload::ScoreDetail(r) => (&r.account, &r.password),
+            RequestPayload::CardExpense(r) => (&r.account, &r.password),
+            _ => return None,
+        };
+        Some((account, password))
+    }
+
     pub(crate) async fn dispatch(self, data: SharedData) -> ResponseResult {
+        let command = self.name();
+        let metrics = data.metrics.clone();
+        let recent_errors = data.recent_errors.clone();
+        let credential = self.credential().map(|(a, p)| (a.to_string(), p.to_string()));
+        let start = std::time::Instant::now();
+
+        let result = if data.command_policy.is_enabled(command) {
+            self.process(data).await
+        } else {
+            Err(ActionError::CommandDisabled.into())
+        };
+        let status = match &result {
+            Ok(_) => String::from("ok"),
+            Err(e) => {
+                let credential = credential.as_ref().map(|(a, p)| (a.as_str(), p.as_str()));
+                recent_errors.record(command, e.code, &e.msg, credential);
+                e.code.to_string()
+            }
+        };
+        metrics.record(command, status, start.elapsed());
+        result
+    }
+
+    async fn process(self, data: SharedData) -> ResponseResult {
         match self {
             RequestPayload::None => Ok(ResponsePayload::None),
             RequestPayload::Ping(r) => Ok(ResponsePayload::Pong(r)),
             RequestPayload::AgentInfo(r) => r.process(data).await,
+            RequestPayload::Config(r) => r.process(data).await,
+            RequestPayload::MetricsExport(r) => r.process(data).await,
+            RequestPayload::RecentErrors(r) => r.process(data).await,
             RequestPayload::PortalAuth(r) => r.process(data).await,
+            RequestPayload::PortalProfile(r) => r.process(data).await,
+            RequestPayload::CredentialTest(r) => r.process(data).await,
             RequestPayload::ActivityList(r) => r.process(data).await,
+            RequestPayload::ActivityListDelta(r) => r.process(data).await,
+            RequestPayload::ActivityCsv(r) => r.process(data).await,
             RequestPayload::ActivityDetail(r) => r.process(data).await,
+            RequestPayload::ActivityDetailBatch(r) => r.process(data).await,
+            RequestPayload::ActivityExists(r) => r.process(data).await,
+            RequestPayload::ResolveActivity(r) => r.process(data).await,
+            RequestPayload::ActivityHistory(r) => r.process(data).await,
+            RequestPayload::OrganizerActivities(r) => r.process(data).await,
+            RequestPayload::AvailableActivities(r) => r.process(data).await,
+            RequestPayload::UpcomingActivities(r) => r.process(data).await,
+            RequestPayload::ScActivityComments(r) => r.process(data).await,
+            RequestPayload::SubscribeActivity(r) => r.process(data).await,
+            RequestPayload::UnsubscribeActivity(r) => r.process(data).await,
             RequestPayload::ScMyScore(r) => r.process(data).await,
+            RequestPayload::ScScoreDiff(r) => r.process(data).await,
+            RequestPayload::ScCategoryCredit(r) => r.process(data).await,
+            RequestPayload::ScTermCredit(r) => r.process(data).await,
+            RequestPayload::ScScoreSummary(r) => r.process(data).await,
+            RequestPayload::ScTranscript(r) => r.process(data).await,
             RequestPayload::ScMyActivity(r) => r.process(data).await,
+            RequestPayload::JoinedDetails(r) => r.process(data).await,
+            RequestPayload::ScPendingEvaluation(r) => r.process(data).await,
+            RequestPayload::ScVolunteerHours(r) => r.process(data).await,
+            RequestPayload::ScRanking(r) => r.process(data).await,
+            RequestPayload::ScDashboard(r) => r.process(data).await,
+            RequestPayload::ScJoin(r) => r.process(data).await,
+            RequestPayload::ScJoinBatch(r) => r.process(data).await,
+            RequestPayload::ScCancel(r) => r.process(data).await,
+            RequestPayload::ScCategoryList(r) => r.process(data).await,
             RequestPayload::MajorList(r) => r.process(data).await,
             // RequestPayload::ClassList(r) => r.process(data).await,
             // RequestPayload::CourseList(r) => r.process(data).await,
-            // RequestPayload::Profile(r) => r.process(data).await,
+            RequestPayload::Profile(r) => r.process(data).await,
             RequestPayload::TimeTable(r) => r.process(data).await,
             RequestPayload::Score(r) => r.process(data).await,
             RequestPayload::ScoreDetail(r) => r.process(data).await,
             RequestPayload::SearchLibrary(r) => r.process(data).await,
             RequestPayload::BookHoldingInfo(r) => r.process(data).await,
             RequestPayload::CardExpense(r)=>r.process(data).await,
+            RequestPayload::Control(r) => r.process(data).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fmt;
+
+    use bincode::Options;
+    use serde::de::{self, Deserialize, Deserializer, Visitor};
+
+    use super::{RequestPayload, ResponsePayload, ScRanking};
+
+    /// Deserializer failing with the variant names of the enum to deserialize, in the order of indices.
+    struct VariantNames;
+
+    #[derive(Debug)]
+    struct Names(&'static [&'static str]);
+
+    impl fmt::Display for Names {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:?}", self.0)
         }
     }
+
+    impl std::error::Error for Names {}
+
+    impl de::Error for Names {
+        fn custom<T: fmt::Display>(_msg: T) -> Self {
+            Names(&[])
+        }
+    }
+
+    impl<'de> Deserializer<'de> for VariantNames {
+        type Error = Names;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Names> {
+            Err(Names(&[]))
+        }
+
+        fn deserialize_enum<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            variants: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Names> {
+            Err(Names(variants))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+            unit unit_struct newtype_struct seq tuple tuple_struct map struct identifier ignored_any
+        }
+    }
+
+    #[test]
+    fn test_request_variant_index() {
+        let names = RequestPayload::deserialize(VariantNames).unwrap_err().0;
+        let expected = "\
+            None Ping AgentInfo PortalAuth ActivityList ActivityDetail ScMyScore ScMyActivity \
+            MajorList TimeTable Score ScoreDetail SearchLibrary BookHoldingInfo CardExpense \
+            PortalProfile ActivityHistory ScScoreDiff ScCategoryCredit ActivityExists ScJoin Config \
+            ScJoinBatch MetricsExport Profile ScTranscript ScPendingEvaluation SubscribeActivity \
+            UnsubscribeActivity ScVolunteerHours ActivityListDelta OrganizerActivities \
+            ScActivityComments ScDashboard AvailableActivities RecentErrors CredentialTest \
+            UpcomingActivities ActivityCsv ResolveActivity JoinedDetails ScCancel ScTermCredit \
+            ScCategoryList ScScoreSummary ActivityDetailBatch Control ScRanking";
+        let expected: Vec<&str> = expected.split_whitespace().collect();
+        // Variants may be appended, but the known ones keep their indices.
+        assert_eq!(&names[..expected.len()], expected.as_slice());
+
+        // As kite-server encodes them.
+        let payload: RequestPayload = bincode::options().deserialize(&[1, 2, b'h', b'i']).unwrap();
+        assert!(matches!(payload, RequestPayload::Ping(s) if s == "hi"));
+        let payload: RequestPayload = bincode::options().deserialize(&[43]).unwrap();
+        assert!(matches!(payload, RequestPayload::ScCategoryList(_)));
+    }
+
+    #[test]
+    fn test_response_variant_index() {
+        let index_of = |payload: ResponsePayload| bincode::options().serialize(&payload).unwrap()[0];
+
+        assert_eq!(index_of(ResponsePayload::None), 0);
+        assert_eq!(index_of(ResponsePayload::Pong(String::new())), 1);
+        assert_eq!(index_of(ResponsePayload::MajorList(Vec::new())), 8);
+        assert_eq!(index_of(ResponsePayload::Score(Vec::new())), 10);
+        assert_eq!(index_of(ResponsePayload::ActivityHistory(Vec::new())), 16);
+        assert_eq!(index_of(ResponsePayload::Metrics(String::new())), 23);
+        assert_eq!(index_of(ResponsePayload::ActivityEvents(Vec::new())), 27);
+        assert_eq!(index_of(ResponsePayload::RecentErrors(Vec::new())), 33);
+        assert_eq!(index_of(ResponsePayload::ActivityCsv(String::new())), 36);
+        assert_eq!(index_of(ResponsePayload::ScCategories(Vec::new())), 41);
+        let ranking = ScRanking { rank: None, points: None };
+        assert_eq!(index_of(ResponsePayload::ScRanking(ranking)), 44);
+    }
 }
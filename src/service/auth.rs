@@ -1,13 +1,28 @@
+use std::future::Future;
+
 use crate::agent::SharedData;
-use crate::net::auth::portal_login;
-use crate::service::{ResponsePayload, ResponseResult};
+use crate::error::Result;
+use crate::net::auth::{portal_login, portal_logout};
+use crate::net::client::default_response_hook;
+use crate::net::{Session, SessionGuard};
+use crate::parser::{Parse, PortalProfile};
+use crate::service::{ActionError, ResponsePayload, ResponseResult};
 
 use super::DoRequest;
 
+mod url {
+    /// Authserver login page, where we are redirected to if the session expired.
+    pub const LOGIN: &str = "https://authserver.sit.edu.cn/authserver/login";
+    /// Personal profile page on authserver, shows bound phone and email.
+    pub const PROFILE: &str = "https://authserver.sit.edu.cn/authserver/userAttributesEdit.do";
+}
+
 #[derive(Debug, serde::Deserialize)]
 pub struct PortalAuthRequest {
-    account: String,
-    credential: String,
+    pub account: String,
+    pub credential: String,
+    /// Store the session into the pool, or validate the credential only.
+    persist: bool,
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -19,9 +34,131 @@ pub enum PortalAuthResponse {
 #[async_trait::async_trait]
 impl DoRequest for PortalAuthRequest {
     async fn process(self, mut data: SharedData) -> ResponseResult {
-        let session = portal_login(&data.client, &self.account, &self.credential).await?;
+        let raw_client = data.session_store.client_of(&self.account, &data.client).clone();
+        let session = portal_login(&raw_client, &self.account, &self.credential).await?;
 
-        data.session_store.insert(&session)?;
+        if self.persist {
+            data.session_store.insert(&session)?;
+        }
         Ok(ResponsePayload::PortalAuth(PortalAuthResponse::Ok))
     }
 }
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CredentialTestRequest {
+    pub account: String,
+    pub password: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct CredentialTestResult {
+    pub valid: bool,
+    /// Why the credential is rejected.
+    pub reason: Option<String>,
+}
+
+/// Wait for the login, and logout at once if succeeded. Nothing of the session is kept, and logout
+/// failure is ignored since the credential is already verified.
+async fn check_credential<F, L, Fut>(login: F, logout: L) -> Result<CredentialTestResult>
+where
+    F: Future<Output = Result<Session>>,
+    L: FnOnce(Session) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    match login.await {
+        Ok(session) => {
+            let _ = logout(session).await;
+            Ok(CredentialTestResult {
+                valid: true,
+                reason: None,
+            })
+        }
+        Err(e) => match e.downcast::<ActionError>() {
+            Ok(ActionError::LoginFailed) => Ok(CredentialTestResult {
+                valid: false,
+                reason: Some(ActionError::LoginFailed.to_string()),
+            }),
+            Ok(e) => Err(e.into()),
+            Err(e) => Err(e),
+        },
+    }
+}
+
+#[async_trait::async_trait]
+impl DoRequest for CredentialTestRequest {
+    /// Check whether the account can log in, without touching the session pool.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let raw_client = data.session_store.client_of(&self.account, &data.client);
+        let login = portal_login(raw_client, &self.account, &self.password);
+        let result = check_credential(login, |session| portal_logout(raw_client, session)).await?;
+
+        Ok(ResponsePayload::CredentialTest(result))
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct PortalProfileRequest {
+    pub account: String,
+    pub password: String,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for PortalProfileRequest {
+    /// Fetch the email and phone bound to the SSO account, so that the user can check them.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let mut client =
+            SessionGuard::new(&data.session_store, &data.client, &self.account, &self.password).await?;
+        client.set_response_hook(Some(default_response_hook));
+
+        let request = client.raw_client.get(url::PROFILE).build()?;
+        let mut response = client.send(request).await?;
+        // Redirected to the login page, the session is expired.
+        if response.url().as_str().starts_with(url::LOGIN) {
+            client.login_with_session().await?;
+
+            let request = client.raw_client.get(url::PROFILE).build()?;
+            response = client.send(request).await?;
+        }
+        let html = response.text().await?;
+
+        client.save()?;
+
+        let profile = PortalProfile::from_html(&html)?;
+        Ok(ResponsePayload::PortalProfile(profile))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use super::check_credential;
+    use crate::net::Session;
+    use crate::service::ActionError;
+
+    #[tokio::test]
+    async fn test_check_credential() {
+        // Sessions handed to logout, which is where the session logged in goes instead of the pool.
+        let logged_out = RefCell::new(Vec::new());
+        let logout = |session: Session| async {
+            logged_out.borrow_mut().push(session.account);
+            Ok(())
+        };
+
+        let login = async { Ok(Session::new("1910000000", "password")) };
+        let result = check_credential(login, logout).await.unwrap();
+        assert!(result.valid);
+        assert!(result.reason.is_none());
+        assert_eq!(*logged_out.borrow(), vec![String::from("1910000000")]);
+
+        let login = async { Err(ActionError::LoginFailed.into()) };
+        let result = check_credential(login, logout).await.unwrap();
+        assert!(!result.valid);
+        assert_eq!(result.reason.as_deref(), Some("用户名或密码错误"));
+        assert_eq!(logged_out.borrow().len(), 1);
+
+        let login = async { Err(ActionError::FailToGetCaptcha.into()) };
+        assert!(check_credential(login, logout).await.is_err());
+        assert_eq!(logged_out.borrow().len(), 1);
+    }
+}
@@ -3,7 +3,7 @@ use serde::Deserialize;
 
 use crate::agent::SharedData;
 use crate::net::client::default_response_hook;
-use crate::net::UserClient;
+use crate::net::SessionGuard;
 use crate::parser::*;
 use crate::service::edu::make_sure_active;
 use crate::service::{DoRequest, ResponsePayload, ResponseResult};
@@ -120,9 +120,9 @@ pub struct MajorRequest {
 
 #[async_trait]
 impl DoRequest for MajorRequest {
-    async fn process(self, mut data: SharedData) -> ResponseResult {
-        let session = data.session_store.query_or(&self.account, &self.password)?;
-        let mut client = UserClient::new(session, &data.client);
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let mut client =
+            SessionGuard::new(&data.session_store, &data.client, &self.account, &self.password).await?;
         client.set_response_hook(Some(default_response_hook));
 
         make_sure_active(&mut client).await?;
@@ -130,7 +130,7 @@ impl DoRequest for MajorRequest {
         let request = client.raw_client.get(url::MAJOR_LIST).build()?;
         let response = client.send(request).await?;
 
-        data.session_store.insert(&client.session)?;
+        client.save()?;
 
         let text = response.text().await?;
         Ok(ResponsePayload::MajorList(parse_major_list_page(&text)?))
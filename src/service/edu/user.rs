@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::agent::SharedData;
 use crate::net::client::default_response_hook;
-use crate::net::UserClient;
+use crate::net::SessionGuard;
 use crate::parser::*;
 use crate::service::{DoRequest, ResponsePayload, ResponseResult};
 
@@ -16,29 +16,28 @@ pub struct ProfileRequest {
     pub password: String,
 }
 
-// #[async_trait]
-// impl DoRequest for ProfileRequest {
-//     async fn process(self, mut data: SharedData) -> ResponseResult {
-//         let session = data
-//             .session_store
-//             .query(&self.account)?
-//             .unwrap_or_else(|| Session::new(&self.account, &self.password));
-//         let mut client = UserClient::new(session, &data.client);
-//         client.set_response_hook(Some(default_response_hook));
-//
-//         make_sure_active(&mut client).await?;
-//
-//         let request = data.client.get(url::PROFILE).build()?;
-//         let response = client.send(request).await?;
-//
-//         // Save session after the last response is received.
-//         data.session_store.insert(&client.session);
-//
-//         let text = response.text().await?;
-//         let profile = parse_profile_page(&text)?;
-//         Ok(ResponsePayload::Profile(profile))
-//     }
-// }
+#[async_trait]
+impl DoRequest for ProfileRequest {
+    /// Fetch the profile, and remember the college of the account for choosing sessions.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let mut client =
+            SessionGuard::new(&data.session_store, &data.client, &self.account, &self.password).await?;
+        client.set_response_hook(Some(default_response_hook));
+
+        make_sure_active(&mut client).await?;
+
+        let request = client.raw_client.get(url::PROFILE).build()?;
+        let response = client.send(request).await?;
+        let text = response.text().await?;
+
+        let profile = parse_profile_page(&text)?;
+        client.session.college = profile.college.clone();
+        // Save session after the last response is received.
+        client.save()?;
+
+        Ok(ResponsePayload::Profile(profile))
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TimeTableRequest {
@@ -50,9 +49,9 @@ pub struct TimeTableRequest {
 
 #[async_trait]
 impl DoRequest for TimeTableRequest {
-    async fn process(self, mut data: SharedData) -> ResponseResult {
-        let session = data.session_store.query_or(&self.account, &self.password)?;
-        let mut client = UserClient::new(session, &data.client);
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let mut client =
+            SessionGuard::new(&data.session_store, &data.client, &self.account, &self.password).await?;
         client.set_response_hook(Some(default_response_hook));
 
         make_sure_active(&mut client).await?;
@@ -66,7 +65,7 @@ impl DoRequest for TimeTableRequest {
         let response = client.send(request).await?;
 
         // Save session after the last response is received.
-        data.session_store.insert(&client.session)?;
+        client.save()?;
 
         let text = response.text().await?;
         Ok(ResponsePayload::TimeTable(parse_timetable_page(&text)?))
@@ -83,9 +82,9 @@ pub struct ScoreRequest {
 
 #[async_trait]
 impl DoRequest for ScoreRequest {
-    async fn process(self, mut data: SharedData) -> ResponseResult {
-        let session = data.session_store.query_or(&self.account, &self.password)?;
-        let mut client = UserClient::new(session, &data.client);
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let mut client =
+            SessionGuard::new(&data.session_store, &data.client, &self.account, &self.password).await?;
         client.set_response_hook(Some(default_response_hook));
 
         make_sure_active(&mut client).await?;
@@ -96,11 +95,11 @@ impl DoRequest for ScoreRequest {
             ("queryModel.showCount", "5000".to_string()),
         ];
 
-        let request = data.client.post(url::SCORE_LIST).form(&params).build()?;
+        let request = client.raw_client.post(url::SCORE_LIST).form(&params).build()?;
         let response = client.send(request).await?;
 
         // Save session after the last response is received.
-        data.session_store.insert(&client.session)?;
+        client.save()?;
 
         let text = response.text().await?;
         Ok(ResponsePayload::Score(parse_score_list_page(&text)?))
@@ -118,9 +117,9 @@ pub struct ScoreDetailRequest {
 
 #[async_trait]
 impl DoRequest for ScoreDetailRequest {
-    async fn process(self, mut data: SharedData) -> ResponseResult {
-        let session = data.session_store.query_or(&self.account, &self.password)?;
-        let mut client = UserClient::new(session, &data.client);
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let mut client =
+            SessionGuard::new(&data.session_store, &data.client, &self.account, &self.password).await?;
         client.set_response_hook(Some(default_response_hook));
 
         make_sure_active(&mut client).await?;
@@ -131,11 +130,11 @@ impl DoRequest for ScoreDetailRequest {
             ("xqm", self.semester.to_raw().to_string()),
         ];
 
-        let request = data.client.post(url::SCORE_DETAIL).form(&params).build()?;
+        let request = client.raw_client.post(url::SCORE_DETAIL).form(&params).build()?;
         let response = client.send(request).await?;
         let html = response.text().await?;
 
-        data.session_store.insert(&client.session)?;
+        client.save()?;
 
         let score_detail = get_score_detail(&html)?;
         Ok(ResponsePayload::ScoreDetail(score_detail))
@@ -1,27 +1,85 @@
-use num_traits::ToPrimitive;
 use reqwest::Error as ReqwestError;
 use serde_json::Error as SerdeError;
 use sled::Error as SledError;
 
-#[derive(Debug, thiserror::Error, ToPrimitive)]
+#[derive(Debug, thiserror::Error)]
 /// ActionError, is used to transfer error in common, or not critical.
 pub enum ActionError {
     #[error("Invalid request payload.")]
-    BadRequest = 2,
+    BadRequest,
     #[error("用户名或密码错误")]
-    LoginFailed = 50,
+    LoginFailed,
     #[error("找不到可用的会话")]
-    NoSessionAvailable = 51,
+    NoSessionAvailable,
     #[error("未知错误")]
-    Unknown = 52,
+    Unknown,
     #[error("无法获取验证码")]
-    FailToGetCaptcha = 53,
+    FailToGetCaptcha,
     #[error("验证码错误")]
-    WrongCaptcha = 54,
+    WrongCaptcha,
     #[error("解析错误")]
-    ParsingError = 55,
+    ParsingError,
     #[error("参数错误")]
-    BadParameter = 56,
+    BadParameter,
+    #[error("该命令已被禁用")]
+    CommandDisabled,
+    /// An error page returned by the upstream, with its message.
+    #[error("{message}")]
+    UpstreamError { message: String },
+    /// The upstream is still busy after retries.
+    #[error("系统繁忙，请稍后再试")]
+    UpstreamBusy,
+    /// A shared link not leading to an activity, or expired.
+    #[error("链接无效或已过期")]
+    InvalidLink,
+    /// Too many requests in process, and the agent is set to reject more.
+    #[error("代理繁忙，请稍后再试")]
+    Busy,
+    /// The activity has started, and the upstream refuses to cancel.
+    #[error("活动已开始，无法取消报名")]
+    CancelRefused,
+    /// A category index not in the category mapping.
+    #[error("未知的活动分类：{0}")]
+    UnknownCategory(i32),
+    /// No complete response from the upstream in time.
+    #[error("请求超时")]
+    Timeout,
+    /// The activity has no place left.
+    #[error("活动报名人数已满")]
+    ActivityFull,
+    /// The activity is over.
+    #[error("活动已结束")]
+    ActivityEnded,
+    /// The login page is returned in place of a page, even though the session is activated.
+    #[error("会话已失效，请稍后再试")]
+    SessionExpired,
+}
+
+impl ActionError {
+    /// Error code to response.
+    pub fn code(&self) -> u16 {
+        match self {
+            ActionError::BadRequest => 2,
+            ActionError::LoginFailed => 50,
+            ActionError::NoSessionAvailable => 51,
+            ActionError::Unknown => 52,
+            ActionError::FailToGetCaptcha => 53,
+            ActionError::WrongCaptcha => 54,
+            ActionError::ParsingError => 55,
+            ActionError::BadParameter => 56,
+            ActionError::CommandDisabled => 57,
+            ActionError::UpstreamError { .. } => 58,
+            ActionError::UpstreamBusy => 59,
+            ActionError::InvalidLink => 60,
+            ActionError::Busy => 61,
+            ActionError::CancelRefused => 62,
+            ActionError::UnknownCategory(_) => 63,
+            ActionError::Timeout => 64,
+            ActionError::ActivityFull => 65,
+            ActionError::ActivityEnded => 66,
+            ActionError::SessionExpired => 67,
+        }
+    }
 }
 
 /// Error code and message to response
@@ -36,7 +94,7 @@ pub struct ErrorResponse {
 impl From<ActionError> for ErrorResponse {
     fn from(e: ActionError) -> Self {
         ErrorResponse {
-            code: e.to_u16().unwrap(),
+            code: e.code(),
             msg: e.to_string(),
         }
     }
@@ -59,7 +117,21 @@ convert_error_type!(ReqwestError);
 
 convert_error_type!(SledError);
 
-type E = anyhow::Error;
-convert_error_type!(E);
+// Keep the code of an ActionError raised deep in the call chain. A request timed out while reading
+// the body, after the client returned, is reported as ActionError::Timeout too.
+impl From<anyhow::Error> for ErrorResponse {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<ActionError>() {
+            Ok(e) => e.into(),
+            Err(e) if e.downcast_ref::<ReqwestError>().is_some_and(|e| e.is_timeout()) => {
+                ActionError::Timeout.into()
+            }
+            Err(e) => Self {
+                code: 1,
+                msg: e.to_string(),
+            },
+        }
+    }
+}
 
 convert_error_type!(SerdeError);
@@ -57,7 +57,8 @@ impl DoRequest for ExpenseRequest {
         let session = data.session_store.query_or(&self.account, &self.password)?;
 
         // 创建client
-        let mut client = UserClient::new(session, &data.client);
+        let raw_client = data.session_store.client_of(&self.account, &data.client);
+        let mut client = UserClient::new(session, raw_client);
 
         client.login_with_session().await?;
 
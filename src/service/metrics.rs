@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::agent::SharedData;
+use crate::service::{DoRequest, ResponsePayload, ResponseResult};
+
+#[derive(Debug, Default, Clone, Copy)]
+struct RequestStat {
+    count: u64,
+    seconds: f64,
+}
+
+/// Request counters of the agent, labeled by command and status. Status is "ok" or the error code.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(&'static str, String), RequestStat>>,
+}
+
+impl Metrics {
+    pub fn record(&self, command: &'static str, status: String, elapsed: Duration) {
+        let mut requests = self.requests.lock().unwrap();
+        let stat = requests.entry((command, status)).or_default();
+
+        stat.count += 1;
+        stat.seconds += elapsed.as_secs_f64();
+    }
+
+    /// Render metrics in Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let requests = self.requests.lock().unwrap().clone();
+        let mut text = String::new();
+
+        let mut write_metric = |name: &str, help: &str, value: fn(&RequestStat) -> String| {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} counter", name);
+            for ((command, status), stat) in requests.iter() {
+                let _ = writeln!(
+                    text,
+                    "{}{{command=\"{}\",status=\"{}\"}} {}",
+                    name,
+                    command,
+                    status,
+                    value(stat)
+                );
+            }
+        };
+        write_metric(
+            "kite_agent_requests_total",
+            "Requests processed by the agent.",
+            |s| s.count.to_string(),
+        );
+        write_metric(
+            "kite_agent_request_seconds_total",
+            "Time spent on processing requests.",
+            |s| format!("{:.6}", s.seconds),
+        );
+        text
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MetricsExportRequest;
+
+#[async_trait::async_trait]
+impl DoRequest for MetricsExportRequest {
+    /// Export metrics in Prometheus format, so that the host can scrape them over the connection.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        Ok(ResponsePayload::Metrics(data.metrics.render()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use regex::Regex;
+
+    use super::Metrics;
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = Metrics::default();
+        metrics.record("Ping", String::from("ok"), Duration::from_millis(2));
+        metrics.record("Ping", String::from("ok"), Duration::from_millis(3));
+        metrics.record("ScJoin", String::from("57"), Duration::from_millis(1));
+
+        let text = metrics.render();
+        let sample = Regex::new(
+            r#"^[a-zA-Z_:][a-zA-Z0-9_:]*(\{[a-zA-Z_]\w*="[^"]*"(,[a-zA-Z_]\w*="[^"]*")*\})? [0-9.]+$"#,
+        )
+        .unwrap();
+        let comment = Regex::new(r"^# (HELP|TYPE) [a-zA-Z_:][a-zA-Z0-9_:]* .+$").unwrap();
+        assert!(text
+            .lines()
+            .all(|line| sample.is_match(line) || comment.is_match(line)));
+
+        assert!(text.contains(r#"kite_agent_requests_total{command="Ping",status="ok"} 2"#));
+        assert!(text.contains(r#"kite_agent_requests_total{command="ScJoin",status="57"} 1"#));
+        assert!(
+            text.contains(r#"kite_agent_request_seconds_total{command="Ping",status="ok"} 0.005000"#)
+        );
+    }
+}
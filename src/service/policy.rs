@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::config::{load_config, CommandConfig};
+
+#[derive(Debug, Default)]
+struct Rules {
+    /// Commands allowed, or all commands if `None`.
+    allow: Option<HashSet<String>>,
+    /// Commands disabled, checked after `allow`.
+    deny: HashSet<String>,
+}
+
+impl Rules {
+    fn new(config: &CommandConfig) -> Self {
+        Self {
+            allow: config.allow.as_ref().map(|x| x.iter().cloned().collect()),
+            deny: config.deny.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Decide which request commands the agent accepts. Commands are named by `RequestPayload` variants,
+/// such as "ActivityList" or "ScJoin".
+#[derive(Debug, Default)]
+pub struct CommandPolicy {
+    rules: RwLock<Rules>,
+}
+
+impl CommandPolicy {
+    pub fn new(config: &CommandConfig) -> Self {
+        Self {
+            rules: RwLock::new(Rules::new(config)),
+        }
+    }
+
+    pub fn is_enabled(&self, command: &str) -> bool {
+        let rules = self.rules.read().unwrap();
+        let allowed = rules.allow.as_ref().map(|x| x.contains(command)).unwrap_or(true);
+
+        allowed && !rules.deny.contains(command)
+    }
+
+    /// Replace the rules with the reloaded config.
+    pub fn reload(&self, config: &CommandConfig) {
+        *self.rules.write().unwrap() = Rules::new(config);
+    }
+}
+
+/// Modification time and size of the file. The size helps where modification time is coarse.
+fn file_stamp(path: &str) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Poll the config file every `interval`, and reload the policy once it is modified. A broken config
+/// is reported and skipped, keeping the rules in use.
+pub async fn watch_config(policy: Arc<CommandPolicy>, path: String, interval: Duration) {
+    let mut last_stamp = file_stamp(&path);
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+
+        let stamp = file_stamp(&path);
+        if stamp == last_stamp {
+            continue;
+        }
+        last_stamp = stamp;
+        match load_config(&path) {
+            Ok(config) => policy.reload(&config.command),
+            Err(e) => eprintln!("Failed to reload {}: {}", path, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CommandPolicy;
+    use crate::config::CommandConfig;
+
+    #[test]
+    fn test_command_policy() {
+        let policy = CommandPolicy::new(&CommandConfig {
+            allow: None,
+            deny: vec![String::from("ScJoin")],
+            ..Default::default()
+        });
+        assert!(!policy.is_enabled("ScJoin"));
+        assert!(policy.is_enabled("ActivityList"));
+
+        let policy = CommandPolicy::new(&CommandConfig {
+            allow: Some(vec![String::from("ActivityList"), String::from("ScJoin")]),
+            deny: vec![String::from("ScJoin")],
+            ..Default::default()
+        });
+        assert!(!policy.is_enabled("ScJoin"));
+        assert!(!policy.is_enabled("ScMyScore"));
+        assert!(policy.is_enabled("ActivityList"));
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::agent::SharedData;
+use crate::service::{DoRequest, ResponsePayload, ResponseResult};
+
+/// A failed request, with credentials redacted.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorRecord {
+    pub time: DateTime<Local>,
+    pub command: &'static str,
+    pub code: u16,
+    pub msg: String,
+    /// Account masked like "191****000", if the request carries one.
+    pub account: Option<String>,
+}
+
+/// Last errors of the agent, the oldest dropped when full.
+#[derive(Debug)]
+pub struct RecentErrors {
+    capacity: usize,
+    records: Mutex<VecDeque<ErrorRecord>>,
+}
+
+/// Mask the middle of an account, keeping at most 3 characters on each side.
+fn mask_account(account: &str) -> String {
+    let chars: Vec<char> = account.chars().collect();
+    let keep = (chars.len() / 3).min(3);
+
+    let head: String = chars[..keep].iter().collect();
+    let tail: String = chars[chars.len() - keep..].iter().collect();
+    format!("{}****{}", head, tail)
+}
+
+impl RecentErrors {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Record an error. `credential` is the (account, password) pair of the request, which is
+    /// masked in the record and wiped from the message.
+    pub fn record(&self, command: &'static str, code: u16, msg: &str, credential: Option<(&str, &str)>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut msg = msg.to_string();
+        let mut account = None;
+        if let Some((user, password)) = credential {
+            let masked = mask_account(user);
+            if !password.is_empty() {
+                msg = msg.replace(password, "******");
+            }
+            if !user.is_empty() {
+                msg = msg.replace(user, &masked);
+            }
+            account = Some(masked);
+        }
+        let record = ErrorRecord {
+            time: Local::now(),
+            command,
+            code,
+            msg,
+            account,
+        };
+
+        let mut records = self.records.lock().unwrap();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Errors recorded, the oldest first.
+    pub fn list(&self) -> Vec<ErrorRecord> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecentErrorsRequest;
+
+#[async_trait::async_trait]
+impl DoRequest for RecentErrorsRequest {
+    /// Return the last errors, so that operators can triage without tailing logs.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        Ok(ResponsePayload::RecentErrors(data.recent_errors.list()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{mask_account, RecentErrors};
+
+    #[test]
+    fn test_recent_errors() {
+        let errors = RecentErrors::new(3);
+        for code in 50..55 {
+            errors.record("ScMyScore", code, "error", None);
+        }
+        let codes: Vec<u16> = errors.list().iter().map(|x| x.code).collect();
+        assert_eq!(codes, vec![52, 53, 54]);
+
+        errors.record(
+            "PortalAuth",
+            50,
+            "登录失败: 1910000000 / secret123",
+            Some(("1910000000", "secret123")),
+        );
+        let last = errors.list().pop().unwrap();
+        assert_eq!(last.account.as_deref(), Some("191****000"));
+        assert_eq!(last.msg, "登录失败: 191****000 / ******");
+        assert_eq!(errors.list().len(), 3);
+    }
+
+    #[test]
+    fn test_mask_account() {
+        assert_eq!(mask_account("1910000000"), "191****000");
+        assert_eq!(mask_account("ab"), "****");
+        assert_eq!(mask_account(""), "****");
+    }
+}
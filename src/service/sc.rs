@@ -1,39 +1,119 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveDate};
+use futures::future::BoxFuture;
+use futures::{stream, StreamExt};
+use reqwest::header::HeaderMap;
 use reqwest::StatusCode;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::agent::SharedData;
+use crate::config::CONFIG;
 use crate::error::Result;
-use crate::make_parameter;
-use crate::net::client::default_response_hook;
-use crate::net::UserClient;
+use crate::net::client::{build_url, default_response_hook, read_text};
+use crate::net::{SessionGuard, UserClient};
 use crate::parser::{
-    get_my_activity_list, get_my_score_list, Activity, ActivityDetail, Parse, ScImages,
+    count_category_credits, dedup_activities, diff_score_items, filter_by_credit, filter_by_date,
+    get_error_message, get_my_activity_list, get_my_score_list, get_pending_evaluation_list,
+    get_score_terms, group_by_term, is_blank_list_page, is_busy_page, is_json_content, is_login_page,
+    login_return_url, parse_activity_list, parse_body, Activity, ActivityBrief, ActivityDetail,
+    DescriptionFormat, ImageFetchStatus, ListTotal, Parse, ParsedRows, ScActivityComments,
+    ScCategoryTotal, ScImages, ScRanking, ScScoreDiff, ScScoreItem, ScScoreSummary, ScTranscript,
+    VolunteerHours,
 };
 use crate::service::{ActionError, DoRequest, ResponsePayload};
 
 use super::ResponseResult;
+use image::check_image;
+
+pub use available::{AvailableActivitiesRequest, UpcomingActivitiesRequest};
+pub use batch::{ActivityDetailBatchRequest, ActivityDetailBatchResponse};
+pub use dashboard::{DashboardCache, ExportedDashboard, ScDashboard, ScDashboardRequest};
+pub use export::ActivityCsvRequest;
+pub use history::{ActivityHistory, ActivityHistoryRequest, ApplySnapshot, HISTORY_TREE};
+pub use image::{ExportedImage, ImageCache};
+pub use join::{ScCancelRequest, ScJoinBatchRequest, ScJoinRequest, ScJoinResponse};
+pub use organizer::OrganizerActivitiesRequest;
+pub use prefetch::{JoinedActivityDetail, JoinedDetailsRequest};
+pub use resolve::{ResolveActivityRequest, ResolvedActivity};
+pub use subscribe::{ActivityEvent, SubscribeActivityRequest, Subscriptions, UnsubscribeActivityRequest};
 
-const CATEGORY_MAPPING: &[&str] = &[
-    "",
-    "001",                              // Subject report.(主题报告)
-    "8ab17f543fe62d5d013fe62efd3a0002", // Social practice.(社会实践)
-    "ff8080814e241104014eb867e1481dc3", // Innovation, entrepreneurship and creativity.(创新创业创意)
-    "8F963F2A04013A66E0540021287E4866", // Campus safety and civilization.(校园安全文明)
-    "8ab17f543fe62d5d013fe62e6dc70001", // Charity and Volunteer.(公益志愿)
-    "8ab17f2a3fe6585e013fe6596c300001", // Campus culture.(校园文化)
-    "ff808081674ec4720167ce60dda77cea", // Theme education (主题教育)
-    "8ab17f543fe626a8013fe6278a880001", // Yiban Community (易班社区)
-    "402881de5d62ba57015d6320f1a7000c", // Safe Online Education (安全网络教育)
-    "8ab17f533ff05c27013ff06d10bf0001", // Paper Patent (论文专利)
-    "ff8080814e241104014fedbbf7fd329d", // Meeting (会议)
+mod available;
+mod batch;
+mod dashboard;
+mod export;
+mod history;
+mod image;
+mod join;
+mod organizer;
+mod prefetch;
+mod resolve;
+mod subscribe;
+
+/// Category of activities, by the index clients use and the id in sc.sit.edu.cn.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ScCategory {
+    pub index: i32,
+    pub sc_id: &'static str,
+    /// Display name, like "主题报告".
+    pub name: &'static str,
+}
+
+impl ScCategory {
+    const fn new(index: i32, sc_id: &'static str, name: &'static str) -> Self {
+        Self { index, sc_id, name }
+    }
+}
+
+const CATEGORY_MAPPING: &[ScCategory] = &[
+    ScCategory::new(0, "", "全部"),
+    ScCategory::new(1, "001", "主题报告"),
+    ScCategory::new(2, "8ab17f543fe62d5d013fe62efd3a0002", "社会实践"),
+    ScCategory::new(3, "ff8080814e241104014eb867e1481dc3", "创新创业创意"),
+    ScCategory::new(4, "8F963F2A04013A66E0540021287E4866", "校园安全文明"),
+    ScCategory::new(5, "8ab17f543fe62d5d013fe62e6dc70001", "公益志愿"),
+    ScCategory::new(6, "8ab17f2a3fe6585e013fe6596c300001", "校园文化"),
+    ScCategory::new(7, "ff808081674ec4720167ce60dda77cea", "主题教育"),
+    ScCategory::new(8, "8ab17f543fe626a8013fe6278a880001", "易班社区"),
+    ScCategory::new(9, "402881de5d62ba57015d6320f1a7000c", "安全网络教育"),
+    ScCategory::new(10, "8ab17f533ff05c27013ff06d10bf0001", "论文专利"),
+    ScCategory::new(11, "ff8080814e241104014fedbbf7fd329d", "会议"),
 ];
 
+/// Display name of a category by its id in sc.sit.edu.cn.
+pub fn category_name(sc_id: &str) -> Option<&'static str> {
+    CATEGORY_MAPPING.iter().find(|x| x.sc_id == sc_id).map(|x| x.name)
+}
+
 mod url {
+    pub const HOME: &str = "http://sc.sit.edu.cn";
+
+    pub const ACTIVITY_LIST: &str = "/public/activity/activityList.action";
+
+    pub const ACTIVITY_DETAIL: &str = "/public/activity/activityDetail.action";
+
     pub const SSO_SC_REDIRECT: &str =
         "https://authserver.sit.edu.cn/authserver/login?service=http%3A%2F%2Fsc.sit.edu.cn%2F";
 
     pub const MY_SCORE: &str = "http://sc.sit.edu.cn/public/pcenter/scoreDetail.action";
 
+    /// Authserver login, which redirects to the `service` parameter with a ticket if logged in.
+    pub const SSO_LOGIN: &str = "https://authserver.sit.edu.cn/authserver/login";
+
+    pub const CHECK_USER: &str = "/public/pcenter/checkUser.action";
+
+    pub const APPLY_ACTIVITY: &str = "/public/pcenter/applyActivity.action";
+
+    /// Withdraw from a joined activity.
+    pub const CANCEL_ACTIVITY: &str = "/public/pcenter/cancelActivity.action";
+
+    /// Comment thread of an activity.
+    pub const ACTIVITY_COMMENT: &str = "/public/activity/activityComment.action";
+
+    /// Volunteer hours and the service records.
+    pub const VOLUNTEER_HOURS: &str =
+        "http://sc.sit.edu.cn/public/pcenter/volunteerHours.action?pageSize=200";
     pub const MY_ACTIVITY: &str =
         "http://sc.sit.edu.cn/public/pcenter/activityOrderList.action?pageSize=200";
 }
@@ -42,16 +122,60 @@ mod url {
 pub struct ActivityListRequest {
     /// Count of activities per page.
     pub count: u16,
-    /// Page index.
+    /// Page index from 1, or 0 to fetch all pages.
     pub index: u16,
     /// Category Id
     pub category: i32,
+    /// More categories to crawl together, whose activities are merged as `sc.dedup` configured.
+    pub more_categories: Vec<i32>,
+    /// Min credit of activities to return, inclusive.
+    pub min_credit: Option<f32>,
+    /// Max credit of activities to return, inclusive.
+    pub max_credit: Option<f32>,
+    /// Leave out activities of unknown credit when filtering by credit, which are kept by default since
+    /// the HTML list tells no credit.
+    pub exclude_unknown: bool,
+    /// Keyword in the activity title, searched by the upstream.
+    pub keyword: Option<String>,
+    /// Earliest start date of activities, inclusive. Searched by the upstream, and checked again on
+    /// activities returned, since the upstream may ignore the condition.
+    pub date_from: Option<NaiveDate>,
+    /// Latest start date of activities, inclusive, searched and checked as `date_from`.
+    pub date_to: Option<NaiveDate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ActivityListResponse {
+    pub activities: Vec<Activity>,
+    /// Count of activities in all pages, summed over categories crawled.
+    pub total_count: Option<u32>,
+    /// Count of pages, the most of categories crawled.
+    pub page_count: Option<u32>,
+    /// Rows skipped as malformed, like promotion banners injected into the list, and pages left out
+    /// by the cap when all pages are fetched.
+    pub warnings: Vec<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ActivityListDeltaRequest {
+    /// Category Id
+    pub category: i32,
+    /// The highest activity id the crawler has seen.
+    pub since_id: i32,
+}
+
+/// Count of activities per page when fetching the delta.
+const DELTA_PAGE_SIZE: u16 = 20;
+/// Max pages to fetch, in case `since_id` no longer appears in the list.
+const DELTA_PAGE_CAP: u16 = 10;
+/// Max pages of a category to fetch when all pages are requested, in case the pagination goes wrong.
+const ALL_PAGES_CAP: u16 = 50;
+
 async fn make_sure_active(client: &mut UserClient) -> Result<()> {
     let home_request = client.raw_client.get(url::SSO_SC_REDIRECT).build()?;
     let response = client.send(home_request).await?;
-    if response.url().as_str() == url::SSO_SC_REDIRECT {
+    // The login page may also be returned at another url, when the session expires silently.
+    if response.url().as_str() == url::SSO_SC_REDIRECT || is_login_page(&read_text(response).await?) {
         client.login_with_session().await?;
         let request = client.raw_client.get(url::SSO_SC_REDIRECT).build()?;
         let _ = client.send(request).await?;
@@ -59,14 +183,144 @@ async fn make_sure_active(client: &mut UserClient) -> Result<()> {
     Ok(())
 }
 
+/// Open the session of the account, make sure second classroom is active on it, and call `op` on the
+/// client. The session is saved if `op` succeeds.
+async fn with_active_session<T, F>(data: &SharedData, account: &str, password: &str, op: F) -> Result<T>
+where
+    F: for<'a> FnOnce(&'a mut UserClient) -> BoxFuture<'a, Result<T>>,
+{
+    let mut client = SessionGuard::new(&data.session_store, &data.client, account, password).await?;
+    client.set_response_hook(Some(default_response_hook));
+
+    make_sure_active(&mut client).await?;
+    let result = op(&mut client).await?;
+    client.save()?;
+    Ok(result)
+}
+
+/// If `page` fetched from `url` is the login page returned since the session timed out, the url to
+/// go after logging in again. It is the return URL the login page carries (or `url` if not any)
+/// through authserver, so that the flow resumes at the intended page.
+fn resume_url(page: &str, url: &str) -> Result<Option<String>> {
+    if !is_login_page(page) {
+        return Ok(None);
+    }
+    let return_url = login_return_url(page).unwrap_or_else(|| url.to_string());
+    Ok(Some(build_url(url::SSO_LOGIN, "", &[("service", &return_url)])?))
+}
+
+/// Response body, with the Content-Type to parse it as.
+struct Body {
+    content_type: Option<String>,
+    text: String,
+}
+
+async fn fetch_body(client: &mut UserClient, url: &str) -> Result<Body> {
+    let request = client.raw_client.get(url).build()?;
+    let response = client.send(request).await?;
+
+    Ok(Body {
+        content_type: content_type_of(&response),
+        text: read_text(response).await?,
+    })
+}
+
+/// Fetch the body, and log in again if the session timed out.
+async fn fetch_body_on_login(client: &mut UserClient, url: &str) -> Result<Body> {
+    let body = fetch_body(client, url).await?;
+    match resume_url(&body.text, url)? {
+        Some(resume_url) => {
+            client.login_with_session().await?;
+            fetch_body(client, &resume_url).await
+        }
+        None => Ok(body),
+    }
+}
+
+/// Return the message as an error, if the generic error page is returned.
+fn check_error_page(html: &str) -> Result<()> {
+    if is_busy_page(html) {
+        return Err(ActionError::UpstreamBusy.into());
+    }
+    match get_error_message(html) {
+        Some(message) => Err(ActionError::UpstreamError { message }.into()),
+        None => Ok(()),
+    }
+}
+
+/// Retries while second classroom is busy, at most `retries` times. The delay doubles on each retry.
+struct BusyRetry {
+    retries: u32,
+    delay: Duration,
+    attempt: u32,
+}
+
+impl BusyRetry {
+    fn new(retries: u32, delay: Duration) -> Self {
+        Self {
+            retries,
+            delay,
+            attempt: 0,
+        }
+    }
+
+    /// Retry with the `sc.busy_retries` and `sc.busy_retry_delay` configured.
+    fn configured() -> Self {
+        let delay = Duration::from_millis(CONFIG.sc.busy_retry_delay);
+        Self::new(CONFIG.sc.busy_retries, delay)
+    }
+
+    /// Delay before calling again after `result`, or `None` to return it.
+    fn next_delay<T>(&mut self, result: &Result<T>) -> Option<Duration> {
+        let busy = match result {
+            Err(e) => matches!(e.downcast_ref(), Some(ActionError::UpstreamBusy)),
+            Ok(_) => false,
+        };
+        if !busy || self.attempt >= self.retries {
+            return None;
+        }
+        let delay = self.delay * 2u32.pow(self.attempt);
+        self.attempt += 1;
+        Some(delay)
+    }
+}
+
+/// Fetch a page of second classroom, and log in again if the session timed out.
+async fn fetch_page(client: &mut UserClient, url: &str) -> Result<String> {
+    let mut retry = BusyRetry::configured();
+    loop {
+        let result = fetch_page_once(client, url).await;
+        match retry.next_delay(&result) {
+            Some(delay) => tokio::time::sleep(delay).await,
+            None => return result,
+        }
+    }
+}
+
+async fn fetch_page_once(client: &mut UserClient, url: &str) -> Result<String> {
+    let html = fetch_body_on_login(client, url).await?.text;
+
+    check_error_page(&html)?;
+    Ok(html)
+}
+
+/// Fetch the score page. The score page sometimes requires authentication again, even if second
+/// classroom has been activated.
+async fn fetch_score_page(client: &mut UserClient) -> Result<String> {
+    fetch_page(client, url::MY_SCORE).await
+}
+
 // When we fetch activity detail page, it costs lot if we go to SSO_SC_REDIRECT to checkout whether
 // we can access the page. So it's better to fetch first, and then decide to redirect.
+// Some pages establish the session and return the data at once. Cookies set are kept by `send_once`,
+// and the response is returned as is, so the data is parsed without another round trip. Redirects are
+// not followed by the response hook here, since one to authserver means the session is not active.
 async fn fetch_or_make_sure_active(
     client: &mut UserClient,
     url: &str,
 ) -> Result<Option<reqwest::Response>> {
     let home_request = client.raw_client.get(url).build()?;
-    let response = client.send(home_request).await?;
+    let response = client.send_once(home_request).await?;
 
     if response.status() == StatusCode::OK {
         Ok(Some(response))
@@ -77,35 +331,85 @@ async fn fetch_or_make_sure_active(
 }
 
 async fn tran_category(category: i32) -> Result<String> {
-    if let Some(category_key) = CATEGORY_MAPPING.get(category as usize) {
-        Ok(category_key.to_string())
+    if let Some(category) = CATEGORY_MAPPING.iter().find(|x| x.index == category) {
+        Ok(category.sc_id.to_string())
     } else {
-        Err(ActionError::BadParameter.into())
+        Err(ActionError::UnknownCategory(category).into())
     }
 }
 
-async fn fetch_image(images: &mut Vec<ScImages>, mut client: UserClient) -> Result<()> {
-    for image in images {
-        if image.content.is_empty() {
-            let image_url = match_image_url(&image.old_name);
+/// Whether the image host could not be connected to, rather than answered with an error.
+fn is_unreachable(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<reqwest::Error>().is_some_and(|e| e.is_connect())
+}
+
+/// Download images not cached by the client by `old_name`, at most `concurrency` at once, and tell
+/// how it went. Images are downloaded by copies of the client, through `cache` if given. Each image is
+/// filled in place, so the order is kept. A failed image is left empty, and the rest are still
+/// downloaded.
+async fn fetch_images(
+    client: &UserClient,
+    images: Vec<&mut ScImages>,
+    concurrency: usize,
+    cache: Option<&ImageCache>,
+) -> ImageFetchStatus {
+    let mut pending: Vec<&mut ScImages> = images
+        .into_iter()
+        .filter(|x| x.content.is_empty() && !x.client_cached)
+        .collect();
+    let attempted = pending.len();
+    let (mut failed, mut unreachable) = (0, 0);
 
-            let content = download_image(image_url, &mut client).await;
-            match content {
-                Ok(result) => image.content = result,
-                Err(e) => {
-                    println!("{:?}", e);
+    let names: Vec<(usize, String)> = pending.iter().map(|x| x.old_name.clone()).enumerate().collect();
+    let mut downloads = stream::iter(names)
+        .map(|(i, old_name)| {
+            let (mut client, image_url) = (client.fork(), match_image_url(&old_name));
+            async move { (i, download_image(&mut client, image_url, cache).await) }
+        })
+        .buffer_unordered(concurrency.max(1));
+    while let Some((i, result)) = downloads.next().await {
+        match result {
+            Ok(result) => pending[i].content = result,
+            Err(e) => {
+                println!("{:?}", e);
+                failed += 1;
+                if is_unreachable(&e) {
+                    unreachable += 1;
                 }
             }
         }
     }
-    Ok(())
+    if failed == 0 {
+        ImageFetchStatus::AllOk
+    } else if failed < attempted {
+        ImageFetchStatus::PartialFailure
+    } else if unreachable == attempted {
+        ImageFetchStatus::CdnUnreachable
+    } else {
+        ImageFetchStatus::AllFailed
+    }
 }
 
-async fn download_image(image_url: String, client: &mut UserClient) -> Result<Vec<u8>> {
-    client.set_response_hook(Some(default_response_hook));
+async fn download_image(
+    client: &mut UserClient,
+    image_url: String,
+    cache: Option<&ImageCache>,
+) -> Result<Vec<u8>> {
+    match cache {
+        Some(cache) => cache.fetch(client, &image_url).await,
+        None => fetch_image_bytes(client, image_url).await,
+    }
+}
 
+/// The image cache, if enabled by `sc.image_cache`.
+fn configured_cache(cache: &ImageCache) -> Option<&ImageCache> {
+    Some(cache).filter(|_| CONFIG.sc.image_cache)
+}
+
+/// Download an image with the client, on the session and the hook it is configured with.
+async fn fetch_image_bytes(client: &mut UserClient, image_url: String) -> Result<Vec<u8>> {
     let request = client.raw_client.get(image_url).build()?;
-    let response = client.send(request).await?;
+    let response = check_image(client.send(request).await?)?;
 
     let image_byte = response.bytes().await?;
     let result = image_byte.to_vec();
@@ -123,42 +427,310 @@ fn match_image_url(old_name: &str) -> String {
     image_url
 }
 
+/// Content-Type of the response, or `None` to parse it as html if JSON detection is disabled.
+fn content_type_of(response: &reqwest::Response) -> Option<String> {
+    if !CONFIG.sc.detect_json {
+        return None;
+    }
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .map(String::from)
+}
+
+/// Fetch activities of a category on the page.
+async fn fetch_activity_list(
+    client: &mut UserClient,
+    index: u16,
+    count: u16,
+    category: i32,
+) -> Result<Vec<Activity>> {
+    let filter = ActivityFilter::default();
+    let (parsed, _) = fetch_activity_page(client, index, count, category, &filter).await?;
+
+    Ok(parsed.rows)
+}
+
+/// Conditions of the activity list searched by the upstream. The date range is sent as `startTime` and
+/// `endTime`, which the list page takes as it seems but has not been confirmed to.
+#[derive(Debug, Clone, Default)]
+struct ActivityFilter {
+    keyword: Option<String>,
+    date_from: Option<NaiveDate>,
+    date_to: Option<NaiveDate>,
+}
+
+/// Build the url of the activity list page. Conditions not given are left out of the query.
+fn activity_list_url(
+    index: u16,
+    count: u16,
+    category_id: &str,
+    filter: &ActivityFilter,
+) -> Result<String> {
+    let index = index.to_string();
+    let count = count.to_string();
+    let date = |x: Option<NaiveDate>| x.map(|x| x.format("%Y-%m-%d").to_string());
+    let (date_from, date_to) = (date(filter.date_from), date(filter.date_to));
+
+    let mut query = vec![
+        ("pageNo", index.as_str()),
+        ("pageSize", count.as_str()),
+        ("categoryId", category_id),
+    ];
+    let conditions = [
+        ("activityName", filter.keyword.as_deref()),
+        ("startTime", date_from.as_deref()),
+        ("endTime", date_to.as_deref()),
+    ];
+    query.extend(conditions.iter().filter_map(|(k, v)| v.map(|v| (*k, v))));
+
+    build_url(url::HOME, url::ACTIVITY_LIST, &query)
+}
+
+/// Fetch activities of a category on the page, with the total count of the category.
+async fn fetch_activity_page(
+    client: &mut UserClient,
+    index: u16,
+    count: u16,
+    category: i32,
+    filter: &ActivityFilter,
+) -> Result<(ParsedRows<Activity>, ListTotal)> {
+    let mut retry = BusyRetry::configured();
+    loop {
+        let result = fetch_activity_page_once(client, index, count, category, filter).await;
+        match retry.next_delay(&result) {
+            Some(delay) => tokio::time::sleep(delay).await,
+            None => return result,
+        }
+    }
+}
+
+async fn fetch_activity_page_once(
+    client: &mut UserClient,
+    index: u16,
+    count: u16,
+    category: i32,
+    filter: &ActivityFilter,
+) -> Result<(ParsedRows<Activity>, ListTotal)> {
+    let category_id = tran_category(category).await?;
+    let url = activity_list_url(index, count, &category_id, filter)?;
+    let mut body = fetch_body_on_login(client, &url).await?;
+
+    let is_json = is_json_content(body.content_type.as_deref());
+    if CONFIG.sc.retry_blank_list && !is_json && is_blank_list_page(&body.text) {
+        tokio::time::sleep(std::time::Duration::from_millis(CONFIG.sc.retry_delay)).await;
+
+        body = fetch_body_on_login(client, &url).await?;
+    }
+
+    let content_type = body.content_type.as_deref();
+    if !is_json_content(content_type) {
+        check_error_page(&body.text)?;
+    }
+    let mut parsed = if is_json_content(content_type) {
+        let rows: Vec<Activity> = parse_body(&body.text, content_type)?;
+        ParsedRows {
+            rows,
+            warnings: Vec::new(),
+        }
+    } else {
+        parse_activity_list(&body.text, CONFIG.sc.strict_parsing)?
+    };
+    let total: ListTotal = parse_body(&body.text, content_type)?;
+    for activity in parsed.rows.iter_mut() {
+        activity.category = category;
+        activity.categories = vec![category];
+    }
+    if CONFIG.sc.validate {
+        let warnings: Vec<String> = parsed.rows.iter().flat_map(Activity::validate).collect();
+        parsed.warnings.extend(warnings);
+    }
+    Ok((parsed, total))
+}
+
+/// Sum up total counts of categories, which is unknown if any is.
+fn sum_totals(totals: &[ListTotal]) -> ListTotal {
+    let total_count = totals.iter().map(|x| x.total_count).sum();
+    let page_count = totals.iter().map(|x| x.page_count).collect::<Option<Vec<u32>>>();
+
+    ListTotal {
+        total_count,
+        page_count: page_count.and_then(|x| x.into_iter().max()),
+    }
+}
+
+/// Fetch pages from the first one until an empty page, the last page told by the total, or `page_cap`
+/// pages, on the same client.
+async fn fetch_all_pages(
+    client: &mut UserClient,
+    page_cap: u16,
+    count: u16,
+    category: i32,
+    filter: &ActivityFilter,
+) -> Result<(ParsedRows<Activity>, ListTotal)> {
+    let mut pages = AllPages::new(page_cap);
+    for index in 1..=page_cap {
+        let (parsed, total) = fetch_activity_page(client, index, count, category, filter).await?;
+        if !pages.push(index, parsed, total) {
+            break;
+        }
+    }
+    Ok(pages.finish())
+}
+
+/// Rows of pages fetched from the first one. Warnings of every page are kept, and the total is taken
+/// from the first page. A warning is added if the cap is reached before the last page.
+struct AllPages {
+    page_cap: u16,
+    result: ParsedRows<Activity>,
+    total: Option<ListTotal>,
+}
+
+impl AllPages {
+    fn new(page_cap: u16) -> Self {
+        Self {
+            page_cap,
+            result: ParsedRows {
+                rows: Vec::new(),
+                warnings: Vec::new(),
+            },
+            total: None,
+        }
+    }
+
+    /// Add the page of `index`, and tell whether to fetch the next one.
+    fn push(&mut self, index: u16, parsed: ParsedRows<Activity>, page_total: ListTotal) -> bool {
+        self.result.warnings.extend(parsed.warnings);
+        if parsed.rows.is_empty() {
+            return false;
+        }
+        self.result.rows.extend(parsed.rows);
+
+        let page_count = self.total.get_or_insert(page_total).page_count;
+        if page_count.is_some_and(|x| u32::from(index) >= x) {
+            return false;
+        }
+        let page_cap = self.page_cap;
+        if index >= page_cap {
+            let warning = format!("stopped at the cap of {} pages, later ones left out", page_cap);
+            self.result.warnings.push(warning);
+            return false;
+        }
+        true
+    }
+
+    fn finish(self) -> (ParsedRows<Activity>, ListTotal) {
+        (self.result, self.total.unwrap_or_default())
+    }
+}
+
 #[async_trait::async_trait]
 impl DoRequest for ActivityListRequest {
     /// Fetch and parse activity list page.
     async fn process(self, mut data: SharedData) -> ResponseResult {
-        let session = data
-            .session_store
-            .choose_randomly()?
+        let mut client = SessionGuard::random(&mut data.session_store, &data.client)?
             .ok_or(ActionError::NoSessionAvailable)?;
-        let mut client = UserClient::new(session, &data.client);
         client.set_response_hook(Some(default_response_hook));
 
         make_sure_active(&mut client).await?;
-        let category_id = tran_category(self.category).await?;
-        let request = client
-            .raw_client
-            .get(&format!(
-                "http://sc.sit.edu.cn/public/activity/activityList.action?{}",
-                make_parameter!("pageNo" => &self.index.to_string(),"pageSize" => &self.count.to_string(),
-                    "categoryId" => category_id.as_str()
-                )
-            ))
-            .build()?;
-        let response = client.send(request).await?;
-
-        data.session_store.insert(&client.session)?;
-
-        let html = response.text().await?;
-        let activities: Vec<Activity> = Parse::from_html(&html)?;
-        let result: Vec<Activity> = activities
-            .into_iter()
-            .map(|mut s| {
-                s.category = self.category;
-                s
-            })
-            .collect();
-        Ok(ResponsePayload::ActivityList(result))
+
+        let filter = ActivityFilter {
+            keyword: self.keyword,
+            date_from: self.date_from,
+            date_to: self.date_to,
+        };
+        let mut result = Vec::new();
+        let mut totals = Vec::new();
+        let mut warnings = Vec::new();
+        for category in std::iter::once(self.category).chain(self.more_categories) {
+            let (parsed, total) = match self.index {
+                0 => fetch_all_pages(&mut client, ALL_PAGES_CAP, self.count, category, &filter).await?,
+                index => fetch_activity_page(&mut client, index, self.count, category, &filter).await?,
+            };
+            result.extend(parsed.rows);
+            warnings.extend(parsed.warnings);
+            totals.push(total);
+        }
+        client.save()?;
+        let total = sum_totals(&totals);
+
+        let mut activities = dedup_activities(result, CONFIG.sc.dedup);
+        if self.min_credit.is_some() || self.max_credit.is_some() {
+            activities =
+                filter_by_credit(activities, self.min_credit, self.max_credit, self.exclude_unknown);
+        }
+        if self.date_from.is_some() || self.date_to.is_some() {
+            activities = filter_by_date(activities, self.date_from, self.date_to);
+        }
+        Ok(ResponsePayload::ActivityList(ActivityListResponse {
+            activities,
+            total_count: total.total_count,
+            page_count: total.page_count,
+            warnings,
+        }))
+    }
+}
+
+/// Fetch pages from the first one until `since_id` appears, an empty page or `page_cap` pages, and
+/// return activities newer than `since_id`. Activities may shift to the next page while crawling,
+/// so duplicates are skipped.
+async fn fetch_activities_since(
+    client: &mut UserClient,
+    since_id: i32,
+    page_cap: u16,
+    category: i32,
+) -> Result<Vec<Activity>> {
+    let mut seen = HashSet::new();
+    let mut result = Vec::new();
+
+    for index in 1..=page_cap {
+        let activities = fetch_activity_list(client, index, DELTA_PAGE_SIZE, category).await?;
+        if take_newer(activities, since_id, &mut seen, &mut result) {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+/// Move activities newer than `since_id` and not `seen` from a page to `result`, and tell whether to
+/// stop, on an empty page or the one `since_id` appears in.
+fn take_newer(
+    activities: Vec<Activity>,
+    since_id: i32,
+    seen: &mut HashSet<i32>,
+    result: &mut Vec<Activity>,
+) -> bool {
+    let reached = activities.is_empty() || activities.iter().any(|x| x.id == since_id);
+    for activity in activities {
+        if activity.id > since_id && seen.insert(activity.id) {
+            result.push(activity);
+        }
+    }
+    reached
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ActivityListDeltaRequest {
+    /// Fetch activities newer than `since_id` only.
+    async fn process(self, mut data: SharedData) -> ResponseResult {
+        let mut client = SessionGuard::random(&mut data.session_store, &data.client)?
+            .ok_or(ActionError::NoSessionAvailable)?;
+        client.set_response_hook(Some(default_response_hook));
+
+        make_sure_active(&mut client).await?;
+
+        let activities =
+            fetch_activities_since(&mut client, self.since_id, DELTA_PAGE_CAP, self.category).await?;
+        client.save()?;
+
+        // Pages are crawled until `since_id`, so the total is not told.
+        Ok(ResponsePayload::ActivityList(ActivityListResponse {
+            activities,
+            total_count: None,
+            page_count: None,
+            warnings: Vec::new(),
+        }))
     }
 }
 
@@ -166,41 +738,187 @@ impl DoRequest for ActivityListRequest {
 pub struct ActivityDetailRequest {
     /// Activity id in sc.sit.edu.cn
     pub id: i32,
+    /// Images (`old_name`) the client already has, which will not be downloaded.
+    pub known_images: HashSet<String>,
+    /// Download the banner in original resolution.
+    pub download_banner: bool,
+    /// Download the share poster, if the page has one.
+    pub download_share_poster: bool,
+    /// Format of the description returned.
+    pub description_format: DescriptionFormat,
+    /// College the activity is restricted to, like "理学院" in "【理学院】...". Sessions of the
+    /// college are preferred, since other students may not see the activity.
+    pub college: Option<String>,
 }
 
 #[async_trait::async_trait]
 impl DoRequest for ActivityDetailRequest {
     /// Fetch and parse activity detail page.
     async fn process(self, mut data: SharedData) -> ResponseResult {
-        let session = data
-            .session_store
-            .choose_randomly()?
+        let college = self.college.as_deref();
+        let mut client = SessionGuard::random_of_college(&mut data.session_store, &data.client, college)?
             .ok_or(ActionError::NoSessionAvailable)?;
-        let mut client = UserClient::new(session, &data.client);
+        // The same client goes through activation, the detail page and images.
+        client.set_response_hook(Some(default_response_hook));
 
-        let url = format!(
-            "http://sc.sit.edu.cn/public/activity/activityDetail.action?activityId={}",
-            self.id
-        );
-        let mut response = fetch_or_make_sure_active(&mut client, &url).await?;
-        if response.is_none() {
-            client.set_response_hook(Some(default_response_hook));
+        let mut activity = fetch_activity_detail(&mut client, self.id).await?;
+        client.save()?;
 
-            let request = client.raw_client.get(&url).build()?;
-            response = Some(client.send(request).await?);
+        data.activity_history.record(&activity)?;
+        activity.format_description(self.description_format);
+        activity.mark_client_cached(&self.known_images);
+        let mut images = Vec::new();
+        if self.download_banner {
+            images.extend(activity.banner.as_mut());
+        }
+        if self.download_share_poster {
+            images.extend(activity.share_poster.as_mut());
         }
+        images.extend(activity.images.iter_mut());
+
+        let (concurrency, cache) = (CONFIG.sc.image_concurrency, configured_cache(&data.image_cache));
+        activity.image_fetch_status = fetch_images(&client, images, concurrency, cache).await;
+
+        Ok(ResponsePayload::ActivityDetail(Box::from(activity)))
+    }
+}
 
-        let html = response.unwrap().text().await?;
+#[derive(Debug, Deserialize)]
+pub struct ActivityExistsRequest {
+    /// Activity id in sc.sit.edu.cn
+    pub id: i32,
+    /// College the activity is restricted to, see `ActivityDetailRequest`.
+    pub college: Option<String>,
+}
 
-        data.session_store.insert(&client.session)?;
+#[async_trait::async_trait]
+impl DoRequest for ActivityExistsRequest {
+    /// Check whether the activity exists, and return its title and apply status only.
+    async fn process(self, mut data: SharedData) -> ResponseResult {
+        let college = self.college.as_deref();
+        let mut client = SessionGuard::random_of_college(&mut data.session_store, &data.client, college)?
+            .ok_or(ActionError::NoSessionAvailable)?;
+        client.set_response_hook(Some(default_response_hook));
 
-        let mut activity: ActivityDetail = Parse::from_html(&html)?;
-        fetch_image(&mut activity.images, client).await?;
+        let brief = fetch_activity_brief(&mut client, self.id).await?;
+        client.save()?;
 
-        Ok(ResponsePayload::ActivityDetail(Box::from(activity)))
+        Ok(ResponsePayload::ActivityExists(brief))
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ScActivityCommentsRequest {
+    /// Activity id in sc.sit.edu.cn
+    pub activity_id: i32,
+    /// Page index, from 1.
+    pub page: u16,
+    /// Count of comments per page.
+    pub page_size: u16,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ScActivityCommentsRequest {
+    /// Fetch a page of student comments under the activity.
+    async fn process(self, mut data: SharedData) -> ResponseResult {
+        let mut client = SessionGuard::random(&mut data.session_store, &data.client)?
+            .ok_or(ActionError::NoSessionAvailable)?;
+        client.set_response_hook(Some(default_response_hook));
+
+        make_sure_active(&mut client).await?;
+
+        let url = build_url(
+            url::HOME,
+            url::ACTIVITY_COMMENT,
+            &[
+                ("activityId", &self.activity_id.to_string()),
+                ("pageNo", &self.page.to_string()),
+                ("pageSize", &self.page_size.to_string()),
+            ],
+        )?;
+        let html = fetch_page(&mut client, &url).await?;
+        client.save()?;
+
+        let comments = ScActivityComments::from_html(&html)?;
+        Ok(ResponsePayload::ScActivityComments(comments))
+    }
+}
+
+/// Fetch and parse the detail page of an activity, without downloading images.
+async fn fetch_activity_detail(client: &mut UserClient, id: i32) -> Result<ActivityDetail> {
+    let mut retry = BusyRetry::configured();
+    loop {
+        let result = fetch_activity_detail_once(client, id).await;
+        match retry.next_delay(&result) {
+            Some(delay) => tokio::time::sleep(delay).await,
+            None => return result,
+        }
+    }
+}
+
+async fn fetch_activity_detail_once(client: &mut UserClient, id: i32) -> Result<ActivityDetail> {
+    let url = build_url(url::HOME, url::ACTIVITY_DETAIL, &[("activityId", &id.to_string())])?;
+    let mut response = fetch_or_make_sure_active(client, &url).await?;
+    if response.is_none() {
+        let request = client.raw_client.get(&url).build()?;
+        response = Some(client.send(request).await?);
+    }
+
+    let response = response.unwrap();
+    let content_type = content_type_of(&response);
+    let now = server_time(response.headers());
+    let body = read_text(response).await?;
+
+    if !is_json_content(content_type.as_deref()) {
+        check_error_page(&body)?;
+    }
+    let mut activity: ActivityDetail = parse_body(&body, content_type.as_deref())?;
+    activity.registration_time_left = activity.time_left_to_apply(now);
+    if CONFIG.sc.validate {
+        activity.warnings = activity.validate(now);
+    }
+    Ok(activity)
+}
+
+/// Time on the server by the `Date` header, so that the clock of the agent does not matter. The
+/// local time is used if the header is missing.
+fn server_time(headers: &HeaderMap) -> DateTime<Local> {
+    headers
+        .get(reqwest::header::DATE)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| DateTime::parse_from_rfc2822(x).ok())
+        .map(|x| x.with_timezone(&Local))
+        .unwrap_or_else(Local::now)
+}
+
+/// Keyword of the error page of an activity deleted or never existing, "对不起，该活动不存在或已被删除！".
+const NOT_FOUND_KEYWORD: &str = "活动不存在";
+
+/// Fetch the title and apply status of an activity. It does not exist only if the upstream says so,
+/// and other pages without the activity are errors.
+async fn fetch_activity_brief(client: &mut UserClient, id: i32) -> Result<ActivityBrief> {
+    let url = build_url(url::HOME, url::ACTIVITY_DETAIL, &[("activityId", &id.to_string())])?;
+    let mut response = fetch_or_make_sure_active(client, &url).await?;
+    if response.is_none() {
+        let request = client.raw_client.get(&url).build()?;
+        response = Some(client.send(request).await?);
+    }
+    let response = response.unwrap();
+
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(ActivityBrief::not_found());
+    }
+    let html = read_text(response).await?;
+    if get_error_message(&html).is_some_and(|x| x.contains(NOT_FOUND_KEYWORD)) {
+        return Ok(ActivityBrief::not_found());
+    }
+    check_error_page(&html)?;
+    if is_login_page(&html) {
+        return Err(ActionError::SessionExpired.into());
+    }
+    ActivityBrief::from_html(&html)
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ScScoreItemRequest {
     pub account: String,
@@ -209,24 +927,166 @@ pub struct ScScoreItemRequest {
 
 #[async_trait::async_trait]
 impl DoRequest for ScScoreItemRequest {
-    async fn process(self, mut data: SharedData) -> ResponseResult {
-        let session = data.session_store.query_or(&self.account, &self.password)?;
-        let mut client = UserClient::new(session, &data.client);
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let mut client =
+            SessionGuard::new(&data.session_store, &data.client, &self.account, &self.password).await?;
         client.set_response_hook(Some(default_response_hook));
 
         make_sure_active(&mut client).await?;
 
-        let request = client.raw_client.get(url::MY_SCORE).build()?;
-        let response = client.send(request).await?;
-        let html = response.text().await?;
+        let html = fetch_score_page(&mut client).await?;
 
-        data.session_store.insert(&client.session)?;
+        client.save()?;
 
         let score = get_my_score_list(&html)?;
+        if CONFIG.sc.validate {
+            // The response has no room for warnings, so report them to operators.
+            for warning in score.iter().flat_map(ScScoreItem::validate) {
+                eprintln!("Implausible score item of {}: {}", self.account, warning);
+            }
+        }
         Ok(ResponsePayload::ScMyScore(score))
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ScScoreDiffRequest {
+    pub account: String,
+    pub password: String,
+    /// Score items the client fetched last time.
+    pub known: Vec<ScScoreItem>,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ScScoreDiffRequest {
+    /// Fetch score items, and return changes since the known state.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let html = with_active_session(&data, &self.account, &self.password, |client| {
+            Box::pin(fetch_score_page(client))
+        })
+        .await?;
+
+        let score = get_my_score_list(&html)?;
+        let (added, removed, changed) = diff_score_items(&self.known, &score);
+        let diff = ScScoreDiff {
+            added,
+            removed,
+            changed,
+            summary: ScScoreSummary::from_html(&html)?,
+        };
+        Ok(ResponsePayload::ScScoreDiff(diff))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScCategoryCreditRequest {
+    pub account: String,
+    pub password: String,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ScCategoryCreditRequest {
+    /// Fetch score items, and return categories the student has earned credits in.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let html = with_active_session(&data, &self.account, &self.password, |client| {
+            Box::pin(fetch_score_page(client))
+        })
+        .await?;
+
+        let score = get_my_score_list(&html)?;
+        Ok(ResponsePayload::ScCategoryCredit(count_category_credits(&score)))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScScoreSummaryRequest {
+    pub account: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScScoreSummaryResponse {
+    pub summary: ScScoreSummary,
+    /// Credits earned in each category, against `sc.category_required_credits`.
+    pub categories: Vec<ScCategoryTotal>,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ScScoreSummaryRequest {
+    /// Fetch score page, and return the summary with credits by category.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let html = with_active_session(&data, &self.account, &self.password, |client| {
+            Box::pin(fetch_score_page(client))
+        })
+        .await?;
+
+        let summary = ScScoreSummary::from_html(&html)?;
+        let categories = summary.by_category(&CONFIG.sc.category_required_credits);
+        Ok(ResponsePayload::ScScoreSummary(ScScoreSummaryResponse { summary, categories }))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScTermCreditRequest {
+    pub account: String,
+    pub password: String,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ScTermCreditRequest {
+    /// Fetch score items, and return credits earned in each term.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let html = with_active_session(&data, &self.account, &self.password, |client| {
+            Box::pin(fetch_score_page(client))
+        })
+        .await?;
+
+        let score = get_my_score_list(&html)?;
+        let terms = get_score_terms(&html);
+        Ok(ResponsePayload::ScTermCredit(group_by_term(score, &terms)))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScTranscriptRequest {
+    pub account: String,
+    pub password: String,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ScTranscriptRequest {
+    /// Fetch score page, and compose a transcript for printing.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let html = with_active_session(&data, &self.account, &self.password, |client| {
+            Box::pin(fetch_score_page(client))
+        })
+        .await?;
+
+        let transcript = ScTranscript::from_html(&html)?;
+        Ok(ResponsePayload::ScTranscript(transcript))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScRankingRequest {
+    pub account: String,
+    pub password: String,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ScRankingRequest {
+    /// Fetch the personal center, and return the rank on the participation leaderboard if shown.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let html = with_active_session(&data, &self.account, &self.password, |client| {
+            Box::pin(fetch_score_page(client))
+        })
+        .await?;
+
+        let ranking = ScRanking::from_html(&html)?;
+        Ok(ResponsePayload::ScRanking(ranking))
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ScActivityRequest {
     pub account: String,
@@ -235,18 +1095,16 @@ pub struct ScActivityRequest {
 
 #[async_trait::async_trait]
 impl DoRequest for ScActivityRequest {
-    async fn process(self, mut data: SharedData) -> ResponseResult {
-        let session = data.session_store.query_or(&self.account, &self.password)?;
-        let mut client = UserClient::new(session, &data.client);
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let mut client =
+            SessionGuard::new(&data.session_store, &data.client, &self.account, &self.password).await?;
         client.set_response_hook(Some(default_response_hook));
 
         make_sure_active(&mut client).await?;
 
-        let request = client.raw_client.get(url::MY_ACTIVITY).build()?;
-        let response = client.send(request).await?;
-        let html = response.text().await?;
+        let html = fetch_page(&mut client, url::MY_ACTIVITY).await?;
 
-        data.session_store.insert(&client.session)?;
+        client.save()?;
 
         let activity = get_my_activity_list(&html)?;
         Ok(ResponsePayload::ScMyActivity(activity))
@@ -254,31 +1112,533 @@ impl DoRequest for ScActivityRequest {
 }
 
 #[derive(Debug, Deserialize)]
-pub struct ScJoinRequest {
+pub struct ScVolunteerHoursRequest {
     pub account: String,
     pub password: String,
-    pub activity_id: i32,
-    pub force: bool,
 }
 
 #[async_trait::async_trait]
-impl DoRequest for ScJoinRequest {
-    async fn process(self, mut data: SharedData) -> ResponseResult {
-        let session = data.session_store.query_or(&self.account, &self.password)?;
-        let mut client = UserClient::new(session, &data.client);
-        client.set_response_hook(Some(default_response_hook));
+impl DoRequest for ScVolunteerHoursRequest {
+    /// Fetch the total volunteer hours and the service records.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let html = with_active_session(&data, &self.account, &self.password, |client| {
+            Box::pin(fetch_page(client, url::VOLUNTEER_HOURS))
+        })
+        .await?;
 
-        make_sure_active(&mut client).await?;
+        let hours = VolunteerHours::from_html(&html)?;
+        Ok(ResponsePayload::ScVolunteerHours(hours))
+    }
+}
 
-        // Expected page content
-        let _expected = "<script>alert('申请成功，下面将为您跳转至我的活动页面！');location.href='/public/pcenter/activityOrderList.action'</script>";
-        let request = client.raw_client.get(url::MY_ACTIVITY).build()?;
-        let response = client.send(request).await?;
-        let html = response.text().await?;
+#[derive(Debug, Deserialize)]
+pub struct ScCategoryListRequest;
 
-        data.session_store.insert(&client.session)?;
+#[async_trait::async_trait]
+impl DoRequest for ScCategoryListRequest {
+    /// Return all categories, so that clients need not keep the table.
+    async fn process(self, _data: SharedData) -> ResponseResult {
+        Ok(ResponsePayload::ScCategories(CATEGORY_MAPPING.to_vec()))
+    }
+}
 
-        let activity = get_my_activity_list(&html)?;
-        Ok(ResponsePayload::ScMyActivity(activity))
+#[derive(Debug, Deserialize)]
+pub struct ScPendingEvaluationRequest {
+    pub account: String,
+    pub password: String,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ScPendingEvaluationRequest {
+    /// List activities attended but not evaluated yet, as a to-do list.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let html = with_active_session(&data, &self.account, &self.password, |client| {
+            Box::pin(fetch_page(client, url::MY_ACTIVITY))
+        })
+        .await?;
+
+        let activity = get_pending_evaluation_list(&html)?;
+        Ok(ResponsePayload::ScPendingEvaluation(activity))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use reqwest::header::{HeaderMap, HeaderValue, DATE};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::{
+        activity_list_url, category_name, check_error_page, fetch_images, fetch_or_make_sure_active,
+        resume_url, server_time, take_newer, tran_category, url, ActivityExistsRequest, ActivityFilter,
+        AllPages, BusyRetry, CATEGORY_MAPPING,
+    };
+    use crate::agent::new_shared_data;
+    use crate::error::Result;
+    use crate::net::client::{build_url, default_response_hook};
+    use crate::net::fake::{FakeRequest, FakeResponse, FakeUpstream};
+    use crate::net::{Session, UserClient};
+    use crate::parser::{
+        Activity, ActivityDetail, ImageFetchStatus, ListTotal, Parse, ParsedRows, ScImages,
+    };
+    use crate::service::{ActionError, DoRequest, ErrorResponse, ResponsePayload};
+
+    fn sso_url(service: &str) -> String {
+        build_url(url::SSO_LOGIN, "", &[("service", service)]).unwrap()
+    }
+
+    #[test]
+    fn test_resume_url() {
+        let login_page = std::fs::read_to_string("html/统一认证登录页面.html").unwrap();
+        let score_page = std::fs::read_to_string("html/第二课堂得分页面.html").unwrap();
+
+        assert_eq!(
+            resume_url(&login_page, url::MY_SCORE).unwrap(),
+            Some(sso_url(url::MY_SCORE))
+        );
+        assert_eq!(resume_url(&score_page, url::MY_SCORE).unwrap(), None);
+    }
+
+    #[test]
+    fn test_resume_list_url() {
+        // A login page without the return url, so that the list is fetched again through authserver.
+        let login_page = std::fs::read_to_string("html/统一认证登录页面.html")
+            .unwrap()
+            .replace("?service=http%3A%2F%2Fsc.sit.edu.cn%2Fpublic%2Fpcenter%2FscoreDetail.action", "");
+        let list_url = build_url(url::HOME, url::ACTIVITY_LIST, &[("pageNo", "1")]).unwrap();
+
+        let expected = Some(sso_url(&list_url));
+        assert_eq!(resume_url(&login_page, &list_url).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_resume_at_return_url() {
+        // The session timed out during the flow, and the login page carries the page to go back.
+        let login_page = std::fs::read_to_string("html/统一认证登录页面_会话超时.html").unwrap();
+
+        assert_eq!(
+            resume_url(&login_page, url::MY_SCORE).unwrap(),
+            Some(sso_url(url::MY_ACTIVITY))
+        );
+    }
+
+    fn delta_page() -> Vec<Activity> {
+        let html_page = std::fs::read_to_string("html/第二课堂活动列表增量页面.html").unwrap();
+        Vec::<Activity>::from_html(&html_page).unwrap()
+    }
+
+    #[test]
+    fn test_activity_list_url() {
+        let filter = ActivityFilter::default();
+        let url = build_url(
+            url::HOME,
+            url::ACTIVITY_LIST,
+            &[("pageNo", "1"), ("pageSize", "20"), ("categoryId", "abc")],
+        );
+        assert_eq!(activity_list_url(1, 20, "abc", &filter).unwrap(), url.unwrap());
+
+        let filter = ActivityFilter {
+            keyword: Some(String::from("社会实践 宣讲")),
+            date_from: Some(NaiveDate::from_ymd_opt(2020, 6, 1).unwrap()),
+            date_to: None,
+        };
+        let url = activity_list_url(2, 20, "", &filter).unwrap();
+        let url = reqwest::Url::parse(&url).unwrap();
+        let query: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        let pairs: Vec<(&str, &str)> = query.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(
+            pairs,
+            vec![
+                ("pageNo", "2"),
+                ("pageSize", "20"),
+                ("categoryId", ""),
+                ("activityName", "社会实践 宣讲"),
+                ("startTime", "2020-06-01"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_activities_since() {
+        let (mut seen, mut result) = (HashSet::new(), Vec::new());
+        assert!(take_newer(delta_page(), 1062305, &mut seen, &mut result));
+
+        let ids: Vec<i32> = result.iter().map(|x| x.id).collect();
+        assert_eq!(ids, vec![1062309, 1062308, 1062307]);
+        assert!(take_newer(Vec::new(), 1062305, &mut seen, &mut result));
+    }
+
+    #[test]
+    fn test_activities_since_missing() {
+        // The activity of `since_id` has been deleted, so the next page is fetched, where activities
+        // shifted there are skipped.
+        let (mut seen, mut result) = (HashSet::new(), Vec::new());
+        assert!(!take_newer(delta_page(), 1062306, &mut seen, &mut result));
+        assert!(!take_newer(delta_page(), 1062306, &mut seen, &mut result));
+
+        let ids: Vec<i32> = result.iter().map(|x| x.id).collect();
+        assert_eq!(ids, vec![1062309, 1062308, 1062307]);
+    }
+
+    #[test]
+    fn test_all_pages() {
+        let page = |count| {
+            let rows = ParsedRows {
+                rows: delta_page().into_iter().take(count).collect(),
+                warnings: Vec::new(),
+            };
+            let total = ListTotal {
+                total_count: Some(7),
+                page_count: Some(3),
+            };
+            (rows, total)
+        };
+        let unknown_total = |count| (page(count).0, ListTotal::default());
+
+        // Stop at the last page told by the total.
+        let mut pages = AllPages::new(10);
+        let more: Vec<bool> = (1..=3)
+            .map(|index| {
+                let (rows, total) = page(3);
+                pages.push(index, rows, total)
+            })
+            .collect();
+        assert_eq!(more, vec![true, true, false]);
+        let (parsed, total) = pages.finish();
+        assert_eq!(parsed.rows.len(), 9);
+        assert_eq!(total.total_count, Some(7));
+
+        // Stop at an empty page, if the page count is not told.
+        let mut pages = AllPages::new(10);
+        let more: Vec<bool> = [2, 1, 0]
+            .iter()
+            .zip(1..)
+            .map(|(&count, index)| {
+                let (rows, total) = unknown_total(count);
+                pages.push(index, rows, total)
+            })
+            .collect();
+        assert_eq!(more, vec![true, true, false]);
+        assert_eq!(pages.finish().0.rows.len(), 3);
+
+        // Warn when pages are left out by the cap.
+        let mut pages = AllPages::new(2);
+        let more: Vec<bool> = (1..=2)
+            .map(|index| {
+                let (rows, total) = unknown_total(3);
+                pages.push(index, rows, total)
+            })
+            .collect();
+        assert_eq!(more, vec![true, false]);
+        let (parsed, _) = pages.finish();
+        assert_eq!(parsed.rows.len(), 6);
+        assert!(parsed.warnings[0].starts_with("stopped at the cap of 2 pages"));
+    }
+
+    #[test]
+    fn test_error_page() {
+        let html_page = std::fs::read_to_string("html/第二课堂错误页面.html").unwrap();
+        let response = ErrorResponse::from(check_error_page(&html_page).unwrap_err());
+
+        assert_eq!(response.code, 58);
+        assert_eq!(response.msg, "对不起，该活动不存在或已被删除！");
+
+        let html_page = std::fs::read_to_string("html/第二课堂得分页面.html").unwrap();
+        assert!(check_error_page(&html_page).is_ok());
+    }
+
+    /// Check whether the activity exists, with the detail page served from `file`.
+    async fn activity_exists(file: &'static str) -> std::result::Result<bool, ErrorResponse> {
+        let upstream = FakeUpstream::start(move |_| {
+            FakeResponse::ok(std::fs::read_to_string(file).unwrap())
+                .header("Content-Type", "text/html;charset=UTF-8")
+        })
+        .await;
+        let mut data = new_shared_data();
+        data.client = upstream.client();
+        let session = Session::new("1910000000", "password");
+        data.session_store.insert(&session).unwrap();
+
+        let (id, college) = (1061909, None);
+        let request = ActivityExistsRequest { id, college };
+        match request.process(data).await? {
+            ResponsePayload::ActivityExists(brief) => Ok(brief.exists),
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_activity_exists() {
+        assert!(activity_exists("html/第二课堂详情页面2.html").await.unwrap());
+        assert!(!activity_exists("html/第二课堂错误页面.html").await.unwrap());
+
+        // Pages without the activity are errors, rather than taken as the activity not existing.
+        let code = |result: std::result::Result<bool, ErrorResponse>| result.unwrap_err().code;
+        assert_eq!(code(activity_exists("html/统一认证登录页面.html").await), 67);
+        assert_eq!(code(activity_exists("html/第二课堂系统繁忙页面.html").await), 59);
+        let blank_page = activity_exists("html/第二课堂活动列表空白页面.html").await;
+        assert!(blank_page.is_err());
+    }
+
+    fn list_page(file: &str) -> Result<Vec<Activity>> {
+        let html_page = std::fs::read_to_string(file).unwrap();
+        check_error_page(&html_page)?;
+        Vec::<Activity>::from_html(&html_page)
+    }
+
+    #[test]
+    fn test_retry_on_busy() {
+        let busy = list_page("html/第二课堂系统繁忙页面.html");
+        let list = list_page("html/第二课堂活动列表增量页面.html");
+        let e = busy.as_ref().unwrap_err();
+        assert!(matches!(e.downcast_ref(), Some(ActionError::UpstreamBusy)));
+
+        let mut retry = BusyRetry::new(3, Duration::from_millis(10));
+        assert_eq!(retry.next_delay(&busy), Some(Duration::from_millis(10)));
+        assert_eq!(retry.next_delay(&busy), Some(Duration::from_millis(20)));
+        assert_eq!(retry.next_delay(&list), None);
+
+        // Give up after the retries.
+        let mut retry = BusyRetry::new(3, Duration::from_millis(10));
+        let delays: Vec<_> = (0..4).map(|_| retry.next_delay(&busy)).collect();
+        assert_eq!(delays[2], Some(Duration::from_millis(40)));
+        assert_eq!(delays[3], None);
+    }
+
+    #[tokio::test]
+    async fn test_category_mapping() {
+        assert_eq!(tran_category(1).await.unwrap(), "001");
+        assert_eq!(category_name("001"), Some("主题报告"));
+        let meeting = "ff8080814e241104014fedbbf7fd329d";
+        assert_eq!(category_name(meeting), Some("会议"));
+        assert_eq!(category_name("002"), None);
+
+        let indexes: Vec<i32> = CATEGORY_MAPPING.iter().map(|x| x.index).collect();
+        assert_eq!(indexes, (0..12).collect::<Vec<i32>>());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_category() {
+        assert_eq!(tran_category(1).await.unwrap(), "001");
+
+        let e = tran_category(99).await.unwrap_err();
+        assert!(matches!(e.downcast_ref(), Some(ActionError::UnknownCategory(99))));
+        let response = ErrorResponse::from(e);
+        assert_eq!(response.code, 63);
+        assert_eq!(response.msg, "未知的活动分类：99");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_images_concurrently() {
+        let mut images: Vec<ScImages> = (0..6)
+            .map(|i| ScImages {
+                new_name: format!("{}.jpg", i),
+                old_name: format!("/images/{}.jpg", i),
+                // The first one is cached by the client, and the second is downloaded already.
+                content: if i == 1 { b"old".to_vec() } else { Vec::new() },
+                client_cached: i == 0,
+            })
+            .collect();
+        let upstream = FakeUpstream::start(|request| {
+            // Earlier images take longer, so they finish out of order.
+            let path = request.path().to_string();
+            let index: u64 = path[8..9].parse().unwrap();
+            let response = match index {
+                4 => FakeResponse::status("404 Not Found"),
+                _ => FakeResponse::ok(path),
+            };
+            response.delay(Duration::from_millis(60 - index * 10))
+        })
+        .await;
+
+        let client = upstream.user_client();
+        let status = fetch_images(&client, images.iter_mut().collect(), 3, None).await;
+
+        assert_eq!(status, ImageFetchStatus::PartialFailure);
+        assert_eq!(upstream.requests().len(), 4);
+        assert!(upstream.most_in_flight() <= 3);
+        let contents: Vec<&[u8]> = images.iter().map(|x| x.content.as_slice()).collect();
+        let expected: Vec<&[u8]> = vec![
+            b"",
+            b"old",
+            b"/images/2.jpg",
+            b"/images/3.jpg",
+            b"",
+            b"/images/5.jpg",
+        ];
+        assert_eq!(contents, expected);
+    }
+
+    #[tokio::test]
+    async fn test_skip_client_cached() {
+        let html_page = std::fs::read_to_string("html/第二课堂详情页面2.html").unwrap();
+        let mut detail = ActivityDetail::from_html(&html_page).unwrap();
+        let cached = detail.images[0].old_name.clone();
+        detail.images.push(ScImages {
+            new_name: String::from("new.png"),
+            old_name: String::from("/js/kindeditor-4.1.7/new.png"),
+            content: Vec::new(),
+            client_cached: false,
+        });
+        detail.mark_client_cached(&std::iter::once(cached.clone()).collect());
+
+        let upstream = FakeUpstream::start(|_| FakeResponse::ok("PNG")).await;
+        let client = upstream.user_client();
+        let status = fetch_images(&client, detail.images.iter_mut().collect(), 2, None).await;
+
+        // Only the image the client does not have is requested.
+        assert_eq!(status, ImageFetchStatus::AllOk);
+        assert_eq!(upstream.paths(), vec![detail.images[1].old_name.clone()]);
+        assert!(detail.images[0].content.is_empty());
+        assert_eq!(detail.images[1].content, b"PNG");
+    }
+
+    #[tokio::test]
+    async fn test_image_not_image() {
+        // The login page is returned for images, since the session expired.
+        let upstream = FakeUpstream::start(|_| {
+            let html_page = std::fs::read_to_string("html/统一认证登录页面.html").unwrap();
+            FakeResponse::ok(html_page).header("Content-Type", "text/html;charset=UTF-8")
+        })
+        .await;
+        let mut images = [ScImages {
+            new_name: String::from("a.jpg"),
+            old_name: String::from("/images/a.jpg"),
+            content: Vec::new(),
+            client_cached: false,
+        }];
+
+        let client = upstream.user_client();
+        let status = fetch_images(&client, images.iter_mut().collect(), 1, None).await;
+
+        assert_eq!(status, ImageFetchStatus::AllFailed);
+        assert!(images[0].content.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cdn_unreachable() {
+        // Take a free port and close it, so that connections are refused.
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut images: Vec<ScImages> = ["/images/a.jpg", "/images/b.jpg"]
+            .iter()
+            .map(|name| ScImages {
+                new_name: name.to_string(),
+                old_name: name.to_string(),
+                content: Vec::new(),
+                client_cached: false,
+            })
+            .collect();
+
+        let raw_client = reqwest::Client::builder()
+            .proxy(reqwest::Proxy::http(format!("http://{}", addr)).unwrap())
+            .build()
+            .unwrap();
+        let client = UserClient::new(Session::new("1910000000", "password"), &raw_client);
+        let status = fetch_images(&client, images.iter_mut().collect(), 2, None).await;
+
+        assert_eq!(status, ImageFetchStatus::CdnUnreachable);
+        assert!(images.iter().all(|x| x.content.is_empty()));
+    }
+
+    #[test]
+    fn test_server_time() {
+        let mut headers = HeaderMap::new();
+        headers.insert(DATE, HeaderValue::from_static("Mon, 01 Jun 2020 04:00:00 GMT"));
+
+        let expected = Utc.with_ymd_and_hms(2020, 6, 1, 4, 0, 0).unwrap();
+        assert_eq!(server_time(&headers), expected);
+    }
+
+    /// Establish the session and return the detail page in a single response, counting requests.
+    async fn serve_detail_with_cookie(listener: TcpListener, hits: Arc<AtomicUsize>) {
+        let html_page = std::fs::read_to_string("html/第二课堂详情页面2.html").unwrap();
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 4096];
+            let _ = stream.read(&mut buf).await;
+            hits.fetch_add(1, Ordering::SeqCst);
+
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nSet-Cookie: JSESSIONID=0123456789ABCDEF; Path=/\r\n\
+                Content-Type: text/html;charset=UTF-8\r\nContent-Length: {}\r\n\
+                Connection: close\r\n\r\n{}",
+                html_page.len(),
+                html_page
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cookie_and_data_at_once() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let url = format!("http://{}/public/activity/activityDetail.action", addr);
+        let hits = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(serve_detail_with_cookie(listener, hits.clone()));
+
+        let session = Session::new("1910000000", "password");
+        let mut client = UserClient::new(session, &reqwest::Client::new());
+        let response = fetch_or_make_sure_active(&mut client, &url).await.unwrap();
+        let activity = ActivityDetail::from_html(&response.unwrap().text().await.unwrap()).unwrap();
+
+        assert_eq!(activity.id, 1061909);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            client.session.query_cookie("127.0.0.1", "JSESSIONID").map(String::as_str),
+            Some("0123456789ABCDEF")
+        );
+    }
+
+    /// Serve the detail page setting the session cookie, and an image redirected to another path.
+    /// Images are only served with the cookie.
+    fn serve_detail_and_images(request: &FakeRequest) -> FakeResponse {
+        let cookie = request.header("Cookie").unwrap_or_default();
+        let with_cookie = cookie.contains("JSESSIONID=0123456789ABCDEF");
+
+        match request.path() {
+            "/public/activity/activityDetail.action" => {
+                let html_page = std::fs::read_to_string("html/第二课堂详情页面2.html").unwrap();
+                FakeResponse::ok(html_page).header("Set-Cookie", "JSESSIONID=0123456789ABCDEF; Path=/")
+            }
+            _ if !with_cookie => FakeResponse::status("403 Forbidden"),
+            "/images/a.jpg" => FakeResponse::redirect("http://sc.sit.edu.cn/images/a_1.jpg"),
+            _ => FakeResponse::ok("JPEG"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_one_client_for_detail() {
+        let upstream = FakeUpstream::start(serve_detail_and_images).await;
+
+        // The hook is set once, before any step.
+        let mut client = upstream.user_client();
+        client.set_response_hook(Some(default_response_hook));
+
+        let url = "http://sc.sit.edu.cn/public/activity/activityDetail.action";
+        let response = fetch_or_make_sure_active(&mut client, url).await.unwrap();
+        let activity = ActivityDetail::from_html(&response.unwrap().text().await.unwrap()).unwrap();
+        assert_eq!(activity.id, 1061909);
+
+        let mut images = [ScImages {
+            new_name: String::from("a.jpg"),
+            old_name: String::from("/images/a.jpg"),
+            content: Vec::new(),
+            client_cached: false,
+        }];
+        let status = fetch_images(&client, images.iter_mut().collect(), 2, None).await;
+
+        // The image is served on the cookie set by the detail page, through the redirect.
+        assert_eq!(status, ImageFetchStatus::AllOk);
+        assert_eq!(images[0].content, b"JPEG");
     }
 }
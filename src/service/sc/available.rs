@@ -0,0 +1,175 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Duration, Local};
+use serde::Deserialize;
+
+use crate::agent::SharedData;
+use crate::net::client::default_response_hook;
+use crate::net::SessionGuard;
+use crate::parser::{ActivityDetail, ScActivityItem};
+use crate::service::{ActionError, DoRequest, ResponsePayload, ResponseResult};
+
+use super::join::fetch_joined;
+use super::{fetch_activity_detail, fetch_activity_list, make_sure_active};
+
+#[derive(Debug, Deserialize)]
+pub struct AvailableActivitiesRequest {
+    /// Category Id
+    pub category: i32,
+    /// Count of recent activities to check.
+    pub count: u16,
+}
+
+/// Whether the activity has slots left, or no limit.
+fn has_slots(activity: &ActivityDetail) -> bool {
+    match (activity.applied, activity.capacity) {
+        (Some(applied), Some(capacity)) => applied < capacity,
+        _ => true,
+    }
+}
+
+/// Keep activities not started yet and with slots left, sorted by start time, soonest first. Students
+/// can apply until the activity starts.
+fn available_activities(activities: Vec<ActivityDetail>, now: DateTime<Local>) -> Vec<ActivityDetail> {
+    let mut result: Vec<ActivityDetail> = activities
+        .into_iter()
+        .filter(|x| x.start_time > now && has_slots(x))
+        .collect();
+    result.sort_by_key(|x| x.start_time);
+    result
+}
+
+/// Keep available activities whose deadline is within `days` and not joined yet, the soonest first.
+fn upcoming_not_joined(
+    activities: Vec<ActivityDetail>,
+    joined: &[ScActivityItem],
+    now: DateTime<Local>,
+    days: u32,
+) -> Vec<ActivityDetail> {
+    let deadline = now + Duration::days(days as i64);
+    let joined: HashSet<i32> = joined.iter().map(|x| x.activity_id).collect();
+
+    available_activities(activities, now)
+        .into_iter()
+        .filter(|x| x.start_time <= deadline && !joined.contains(&x.id))
+        .collect()
+}
+
+#[async_trait::async_trait]
+impl DoRequest for AvailableActivitiesRequest {
+    /// Find recent activities a student can apply right now.
+    async fn process(self, mut data: SharedData) -> ResponseResult {
+        let mut client = SessionGuard::random(&mut data.session_store, &data.client)?
+            .ok_or(ActionError::NoSessionAvailable)?;
+        client.set_response_hook(Some(default_response_hook));
+
+        make_sure_active(&mut client).await?;
+
+        let candidates = fetch_activity_list(&mut client, 1, self.count, self.category).await?;
+        let mut activities = Vec::new();
+        for candidate in candidates {
+            let activity = fetch_activity_detail(&mut client, candidate.id).await?;
+            data.activity_history.record(&activity)?;
+            activities.push(activity);
+        }
+        client.save()?;
+
+        Ok(ResponsePayload::AvailableActivities(available_activities(
+            activities,
+            Local::now(),
+        )))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpcomingActivitiesRequest {
+    pub account: String,
+    pub password: String,
+    /// Category Id
+    pub category: i32,
+    /// Count of recent activities to check.
+    pub count: u16,
+    /// Days from now the deadline falls in.
+    pub days: u32,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for UpcomingActivitiesRequest {
+    /// Find activities closing within days, which the student has not joined, as a reminder.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let mut client =
+            SessionGuard::new(&data.session_store, &data.client, &self.account, &self.password).await?;
+        client.set_response_hook(Some(default_response_hook));
+
+        make_sure_active(&mut client).await?;
+
+        let joined = fetch_joined(&mut client).await?;
+        let candidates = fetch_activity_list(&mut client, 1, self.count, self.category).await?;
+        let mut activities = Vec::new();
+        for candidate in candidates {
+            let activity = fetch_activity_detail(&mut client, candidate.id).await?;
+            data.activity_history.record(&activity)?;
+            activities.push(activity);
+        }
+        client.save()?;
+
+        Ok(ResponsePayload::UpcomingActivities(upcoming_not_joined(
+            activities,
+            &joined,
+            Local::now(),
+            self.days,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Local, TimeZone};
+
+    use super::{available_activities, upcoming_not_joined};
+    use crate::parser::{get_my_activity_list, ActivityDetail, Parse};
+
+    fn parse(file: &str) -> ActivityDetail {
+        let html_page = std::fs::read_to_string(file).unwrap();
+        ActivityDetail::from_html(&html_page).unwrap()
+    }
+
+    #[test]
+    fn test_available_activities() {
+        let activities = vec![
+            parse("html/第二课堂详情页面_名额未满.html"),
+            parse("html/第二课堂详情页面_名额已满.html"),
+            // No apply limit.
+            parse("html/第二课堂详情页面2.html"),
+            // Started long ago.
+            parse("html/第二课堂详情页面.html"),
+        ];
+        let now = Local.with_ymd_and_hms(2020, 5, 20, 12, 0, 0).unwrap();
+
+        let ids: Vec<i32> = available_activities(activities, now)
+            .iter()
+            .map(|x| x.id)
+            .collect();
+        assert_eq!(ids, vec![1061909, 1062402]);
+    }
+
+    #[test]
+    fn test_upcoming_not_joined() {
+        let activities = vec![
+            // Joined already.
+            parse("html/第二课堂详情页面_名额未满.html"),
+            parse("html/第二课堂详情页面_即将截止.html"),
+            parse("html/第二课堂详情页面_名额已满.html"),
+            parse("html/第二课堂详情页面2.html"),
+        ];
+        let html_page = std::fs::read_to_string("html/第二课堂我的活动页面_已报名.html").unwrap();
+        let joined = get_my_activity_list(&html_page).unwrap();
+        let now = Local.with_ymd_and_hms(2020, 6, 1, 12, 0, 0).unwrap();
+
+        let ids: Vec<i32> = upcoming_not_joined(activities, &joined, now, 7)
+            .iter()
+            .map(|x| x.id)
+            .collect();
+        assert_eq!(ids, vec![1062403]);
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::agent::SharedData;
+use crate::config::CONFIG;
+use crate::error::Result;
+use crate::net::client::default_response_hook;
+use crate::net::{SessionGuard, UserClient};
+use crate::parser::ActivityDetail;
+use crate::service::{ActionError, DoRequest, ResponsePayload, ResponseResult};
+
+use super::image::ImageCache;
+use super::{configured_cache, fetch_activity_detail, fetch_images, make_sure_active};
+
+/// Detail pages fetched at once.
+const BATCH_CONCURRENCY: usize = 4;
+
+#[derive(Debug, Deserialize)]
+pub struct ActivityDetailBatchRequest {
+    /// Activity ids in sc.sit.edu.cn
+    pub ids: Vec<i32>,
+    /// Download images in the description, which takes most of the time.
+    pub fetch_images: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ActivityDetailBatchResponse {
+    /// Details fetched, in the order of ids requested.
+    pub details: Vec<ActivityDetail>,
+    /// Why the detail of an activity is not fetched, by activity id.
+    pub errors: HashMap<i32, String>,
+}
+
+impl ActivityDetailBatchResponse {
+    /// Details fetched, or why not, by activity id.
+    fn new(results: Vec<(i32, Result<ActivityDetail>)>) -> Self {
+        let mut response = Self {
+            details: Vec::new(),
+            errors: HashMap::new(),
+        };
+        for (id, result) in results {
+            match result {
+                Ok(detail) => response.details.push(detail),
+                Err(e) => {
+                    response.errors.insert(id, e.to_string());
+                }
+            }
+        }
+        response
+    }
+}
+
+/// Fetch the detail of each activity, at most `concurrency` at once. Each detail is fetched by a fork
+/// of the client, and cookies set on forks are merged back. A failure is kept in `errors`, and does
+/// not stop the rest.
+async fn fetch_each(
+    client: &mut UserClient,
+    ids: Vec<i32>,
+    concurrency: usize,
+    with_images: bool,
+    cache: Option<&ImageCache>,
+) -> ActivityDetailBatchResponse {
+    let base = &*client;
+    let fetched: Vec<(i32, Result<ActivityDetail>, UserClient)> = stream::iter(ids)
+        .map(|id| {
+            let mut fork = base.fork();
+            async move {
+                let detail = fetch_detail(&mut fork, id, with_images, cache).await;
+                (id, detail, fork)
+            }
+        })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await;
+
+    let mut results = Vec::new();
+    for (id, detail, fork) in fetched {
+        client.merge(fork);
+        results.push((id, detail));
+    }
+    ActivityDetailBatchResponse::new(results)
+}
+
+/// Fetch the detail of an activity, and the images in it if `with_images`.
+async fn fetch_detail(
+    client: &mut UserClient,
+    id: i32,
+    with_images: bool,
+    cache: Option<&ImageCache>,
+) -> Result<ActivityDetail> {
+    let mut activity = fetch_activity_detail(client, id).await?;
+    if with_images {
+        let images = activity.images.iter_mut().collect();
+        let concurrency = CONFIG.sc.image_concurrency;
+        activity.image_fetch_status = fetch_images(client, images, concurrency, cache).await;
+    }
+    Ok(activity)
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ActivityDetailBatchRequest {
+    /// Fetch details of several activities on one session.
+    async fn process(self, mut data: SharedData) -> ResponseResult {
+        let mut client = SessionGuard::random(&mut data.session_store, &data.client)?
+            .ok_or(ActionError::NoSessionAvailable)?;
+        client.set_response_hook(Some(default_response_hook));
+
+        // Activate once, so that clients forked from it need not.
+        make_sure_active(&mut client).await?;
+
+        let cache = configured_cache(&data.image_cache);
+        let response =
+            fetch_each(&mut client, self.ids, BATCH_CONCURRENCY, self.fetch_images, cache).await;
+        client.save()?;
+
+        for activity in response.details.iter() {
+            data.activity_history.record(activity)?;
+        }
+        Ok(ResponsePayload::ActivityDetailBatch(response))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ActivityDetailBatchResponse;
+    use crate::parser::{ActivityDetail, Parse};
+
+    #[test]
+    fn test_batch_response() {
+        let results = [1061909, 1000000, 1062210, 1001182]
+            .iter()
+            .map(|&id| {
+                let file = match id {
+                    1061909 => "html/第二课堂详情页面2.html",
+                    1062210 => "html/第二课堂详情页面_同主办方.html",
+                    1001182 => "html/第二课堂详情页面.html",
+                    _ => return (id, Err(anyhow::anyhow!("活动不存在"))),
+                };
+                let html_page = std::fs::read_to_string(file).unwrap();
+                (id, ActivityDetail::from_html(&html_page))
+            })
+            .collect();
+        let response = ActivityDetailBatchResponse::new(results);
+
+        let ids: Vec<i32> = response.details.iter().map(|x| x.id).collect();
+        assert_eq!(ids, vec![1061909, 1062210, 1001182]);
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(response.errors[&1000000], "活动不存在");
+    }
+}
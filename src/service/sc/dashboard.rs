@@ -0,0 +1,231 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::agent::SharedData;
+use crate::config::CONFIG;
+use crate::error::Result;
+use crate::net::client::default_response_hook;
+use crate::net::{SessionGuard, UserClient};
+use crate::parser::{get_my_activity_list, Parse, ScActivityItem, ScScoreSummary};
+use crate::service::{DoRequest, ResponsePayload, ResponseResult};
+
+use super::{fetch_page, fetch_score_page, make_sure_active, url};
+
+/// Count of recent activities on the dashboard.
+const RECENT_COUNT: usize = 5;
+
+/// Credits overview of a student, ready to render.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScDashboard {
+    pub summary: ScScoreSummary,
+    /// Credits still required, zero if already satisfied.
+    pub remaining: f32,
+    /// Latest activities joined.
+    pub recent: Vec<ScActivityItem>,
+    /// When the dashboard is scraped.
+    pub updated_at: DateTime<Local>,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    /// Password the dashboard is composed with, which a request must match to be served from cache.
+    password: String,
+    created: Instant,
+    dashboard: ScDashboard,
+}
+
+/// A dashboard in the cache, as exported for state handoff.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedDashboard {
+    account: String,
+    password: String,
+    /// Time since the dashboard is composed.
+    age: Duration,
+    dashboard: ScDashboard,
+}
+
+/// Dashboards composed recently, indexed by account.
+#[derive(Debug, Default)]
+pub struct DashboardCache {
+    inner: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl DashboardCache {
+    /// Return the cached dashboard if not older than `ttl`, or `compose` a new one and cache it.
+    pub(crate) async fn get_or_compose<F, Fut>(
+        &self,
+        account: &str,
+        password: &str,
+        ttl: Duration,
+        compose: F,
+    ) -> Result<ScDashboard>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<ScDashboard>>,
+    {
+        if let Some(entry) = self.inner.lock().unwrap().get(account) {
+            if entry.password == password && entry.created.elapsed() < ttl {
+                return Ok(entry.dashboard.clone());
+            }
+        }
+        let dashboard = compose().await?;
+        let entry = CacheEntry {
+            password: password.to_string(),
+            created: Instant::now(),
+            dashboard: dashboard.clone(),
+        };
+        self.inner.lock().unwrap().insert(account.to_string(), entry);
+
+        Ok(dashboard)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock().unwrap().is_empty()
+    }
+
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().clear();
+    }
+
+    /// Export dashboards cached, for state handoff.
+    pub fn dump(&self) -> Vec<ExportedDashboard> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .iter()
+            .map(|(account, entry)| ExportedDashboard {
+                account: account.clone(),
+                password: entry.password.clone(),
+                age: entry.created.elapsed(),
+                dashboard: entry.dashboard.clone(),
+            })
+            .collect()
+    }
+
+    /// Restore dashboards exported by `dump`, keeping their age.
+    pub fn restore(&self, dashboards: Vec<ExportedDashboard>) {
+        let mut inner = self.inner.lock().unwrap();
+        for x in dashboards {
+            // Older than the monotonic clock of this process, and surely expired.
+            let created = match Instant::now().checked_sub(x.age) {
+                Some(created) => created,
+                None => continue,
+            };
+            let entry = CacheEntry {
+                password: x.password,
+                created,
+                dashboard: x.dashboard,
+            };
+            inner.insert(x.account, entry);
+        }
+    }
+}
+
+async fn compose_dashboard(client: &mut UserClient) -> Result<ScDashboard> {
+    make_sure_active(client).await?;
+
+    let summary = ScScoreSummary::from_html(&fetch_score_page(client).await?)?;
+    let mut recent = get_my_activity_list(&fetch_page(client, url::MY_ACTIVITY).await?)?;
+    recent.sort_by_key(|x| Reverse(x.time));
+    recent.truncate(RECENT_COUNT);
+
+    Ok(ScDashboard {
+        remaining: (CONFIG.sc.required_credits - summary.effect).max(0.0),
+        summary,
+        recent,
+        updated_at: Local::now(),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScDashboardRequest {
+    pub account: String,
+    pub password: String,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ScDashboardRequest {
+    /// Return the credit dashboard, served from cache within `sc.dashboard_ttl`.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let ttl = Duration::from_secs(CONFIG.sc.dashboard_ttl);
+        let dashboard = data
+            .dashboard_cache
+            .get_or_compose(&self.account, &self.password, ttl, || async {
+                // Loaded only on a cache miss, where the session is used.
+                let store = &data.session_store;
+                let mut client =
+                    SessionGuard::new(store, &data.client, &self.account, &self.password).await?;
+                client.set_response_hook(Some(default_response_hook));
+
+                let dashboard = compose_dashboard(&mut client).await?;
+                client.save()?;
+                Ok(dashboard)
+            })
+            .await?;
+
+        Ok(ResponsePayload::ScDashboard(dashboard))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use chrono::Local;
+
+    use super::{DashboardCache, ScDashboard};
+    use crate::parser::{Parse, ScScoreSummary};
+
+    fn dashboard() -> ScDashboard {
+        let html_page = std::fs::read_to_string("html/第二课堂得分页面.html").unwrap();
+        ScDashboard {
+            summary: ScScoreSummary::from_html(&html_page).unwrap(),
+            remaining: 0.0,
+            recent: Vec::new(),
+            updated_at: Local::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dashboard_cache() {
+        let cache = DashboardCache::default();
+        let ttl = Duration::from_secs(600);
+        let mut scraped = 0;
+
+        for _ in 0..2 {
+            cache
+                .get_or_compose("1910000000", "password", ttl, || async {
+                    scraped += 1;
+                    Ok(dashboard())
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(scraped, 1);
+
+        // A wrong password is not served from cache.
+        cache
+            .get_or_compose("1910000000", "wrong", ttl, || async {
+                scraped += 1;
+                Ok(dashboard())
+            })
+            .await
+            .unwrap();
+        assert_eq!(scraped, 2);
+
+        // Expired.
+        cache
+            .get_or_compose("1910000000", "wrong", Duration::from_secs(0), || async {
+                scraped += 1;
+                Ok(dashboard())
+            })
+            .await
+            .unwrap();
+        assert_eq!(scraped, 3);
+    }
+}
@@ -0,0 +1,223 @@
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use serde::Deserialize;
+
+use crate::agent::SharedData;
+use crate::error::Result;
+use crate::net::client::default_response_hook;
+use crate::net::SessionGuard;
+use crate::parser::ActivityDetail;
+use crate::service::{ActionError, DoRequest, ResponsePayload, ResponseResult};
+
+use super::{fetch_activity_detail, fetch_activity_list, make_sure_active};
+
+/// Columns of the CSV, in order.
+const HEADER: &[&str] = &[
+    "id", "title", "category", "credit", "start", "end", "capacity", "applied",
+];
+/// Byte order mark, with which Excel reads the file as UTF-8 rather than the locale encoding.
+const BOM: char = '\u{feff}';
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// A row of the activity CSV.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActivityRow {
+    pub id: i32,
+    pub title: String,
+    pub category: i32,
+    /// Credit is not shown on the detail page, but on the list from the JSON endpoint.
+    pub credit: Option<f32>,
+    pub start: DateTime<Local>,
+    pub end: Option<DateTime<Local>>,
+    pub capacity: Option<u32>,
+    pub applied: Option<u32>,
+}
+
+impl From<&ActivityDetail> for ActivityRow {
+    fn from(activity: &ActivityDetail) -> Self {
+        Self {
+            id: activity.id,
+            title: activity.title.clone(),
+            category: activity.category,
+            credit: None,
+            start: activity.start_time,
+            end: activity.end_time,
+            capacity: activity.capacity,
+            applied: activity.applied,
+        }
+    }
+}
+
+/// Quote the field if it contains a delimiter, a quote or a line break.
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|x| x.to_string()).unwrap_or_default()
+}
+
+/// Write rows to CSV, with a header line. Lines end with CRLF as RFC 4180 says.
+pub fn activities_to_csv(rows: &[ActivityRow]) -> String {
+    let mut text = String::new();
+    text.push(BOM);
+    text.push_str(&HEADER.join(","));
+    text.push_str("\r\n");
+
+    for row in rows {
+        let fields = [
+            row.id.to_string(),
+            escape(&row.title),
+            row.category.to_string(),
+            optional(row.credit),
+            row.start.format(TIME_FORMAT).to_string(),
+            optional(row.end.map(|x| x.format(TIME_FORMAT))),
+            optional(row.capacity),
+            optional(row.applied),
+        ];
+        text.push_str(&fields.join(","));
+        text.push_str("\r\n");
+    }
+    text
+}
+
+/// Split CSV text into records of fields.
+fn split_records(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+fn parse_time(text: &str) -> Option<DateTime<Local>> {
+    let time = NaiveDateTime::parse_from_str(text, TIME_FORMAT).ok()?;
+    Local.from_local_datetime(&time).single()
+}
+
+fn parse_optional<T: std::str::FromStr>(text: &str) -> Result<Option<T>> {
+    if text.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(text.parse().map_err(|_| ActionError::ParsingError)?))
+}
+
+/// Read rows written by `activities_to_csv`.
+pub fn activities_from_csv(text: &str) -> Result<Vec<ActivityRow>> {
+    let text = text.trim_start_matches(BOM);
+    let mut rows = Vec::new();
+
+    for record in split_records(text).into_iter().skip(1) {
+        if record.len() != HEADER.len() {
+            return Err(ActionError::ParsingError.into());
+        }
+        rows.push(ActivityRow {
+            id: record[0].parse()?,
+            title: record[1].clone(),
+            category: record[2].parse()?,
+            credit: parse_optional(&record[3])?,
+            start: parse_time(&record[4]).ok_or(ActionError::ParsingError)?,
+            end: match record[5].as_str() {
+                "" => None,
+                s => Some(parse_time(s).ok_or(ActionError::ParsingError)?),
+            },
+            capacity: parse_optional(&record[6])?,
+            applied: parse_optional(&record[7])?,
+        });
+    }
+    Ok(rows)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ActivityCsvRequest {
+    /// Count of activities per page.
+    pub count: u16,
+    /// Page index.
+    pub index: u16,
+    /// Category Id
+    pub category: i32,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ActivityCsvRequest {
+    /// Fetch activities on the page with their details, and return them in CSV for spreadsheets.
+    async fn process(self, mut data: SharedData) -> ResponseResult {
+        let mut client = SessionGuard::random(&mut data.session_store, &data.client)?
+            .ok_or(ActionError::NoSessionAvailable)?;
+        client.set_response_hook(Some(default_response_hook));
+
+        make_sure_active(&mut client).await?;
+
+        let activities = fetch_activity_list(&mut client, self.index, self.count, self.category).await?;
+        let mut rows = Vec::new();
+        for activity in activities {
+            let mut detail = fetch_activity_detail(&mut client, activity.id).await?;
+            data.activity_history.record(&detail)?;
+            detail.category = activity.category;
+            rows.push(ActivityRow {
+                credit: activity.credit,
+                ..ActivityRow::from(&detail)
+            });
+        }
+        client.save()?;
+
+        Ok(ResponsePayload::ActivityCsv(activities_to_csv(&rows)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{activities_from_csv, activities_to_csv, ActivityRow};
+    use crate::parser::{ActivityDetail, Parse};
+
+    #[test]
+    fn test_csv_round_trip() {
+        let mut rows: Vec<ActivityRow> = [
+            "html/第二课堂详情页面2.html",
+            "html/第二课堂详情页面_名额未满.html",
+        ]
+        .iter()
+        .map(|file| {
+            let html_page = std::fs::read_to_string(file).unwrap();
+            ActivityRow::from(&ActivityDetail::from_html(&html_page).unwrap())
+        })
+        .collect();
+        rows[0].title = String::from("书法大赛, \"初赛\"\n线上");
+        rows[1].credit = Some(0.5);
+
+        let text = activities_to_csv(&rows);
+        assert!(text.contains("\"书法大赛, \"\"初赛\"\"\n线上\""));
+        assert!(text
+            .lines()
+            .next()
+            .unwrap()
+            .ends_with("id,title,category,credit,start,end,capacity,applied"));
+
+        assert_eq!(activities_from_csv(&text).unwrap(), rows);
+    }
+}
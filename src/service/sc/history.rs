@@ -0,0 +1,130 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::agent::SharedData;
+use crate::error::Result;
+use crate::parser::ActivityDetail;
+use crate::service::{DoRequest, ResponsePayload, ResponseResult};
+
+/// Sled tree name of activity apply history.
+pub const HISTORY_TREE: &str = "activity_history";
+
+/// Apply count of an activity at some time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApplySnapshot {
+    /// Fetch time
+    pub time: DateTime<Local>,
+    /// Count of students applied
+    pub applied: Option<u32>,
+    /// Max count of students
+    pub capacity: Option<u32>,
+}
+
+/// Append-only store of apply snapshots, indexed by (activity id, fetch time).
+#[derive(Debug, Clone)]
+pub struct ActivityHistory {
+    tree: sled::Tree,
+}
+
+impl ActivityHistory {
+    pub fn new(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+
+    /// Append a snapshot of the activity, if it has any apply count.
+    pub fn record(&self, activity: &ActivityDetail) -> Result<()> {
+        if activity.applied.is_none() && activity.capacity.is_none() {
+            return Ok(());
+        }
+        let snapshot = ApplySnapshot {
+            time: Local::now(),
+            applied: activity.applied,
+            capacity: activity.capacity,
+        };
+        self.append(activity.id, &snapshot)
+    }
+
+    fn append(&self, id: i32, snapshot: &ApplySnapshot) -> Result<()> {
+        // Big endian keys keep snapshots of an activity in time order.
+        let mut key = id.to_be_bytes().to_vec();
+        key.extend_from_slice(&snapshot.time.timestamp_millis().to_be_bytes());
+
+        self.tree.insert(key, bincode::serialize(snapshot)?)?;
+        Ok(())
+    }
+
+    /// Dump raw entries of all activities, for state handoff.
+    pub fn dump(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.tree
+            .iter()
+            .map(|item| {
+                let (key, value) = item?;
+                Ok((key.to_vec(), value.to_vec()))
+            })
+            .collect()
+    }
+
+    /// Restore raw entries dumped by `dump`.
+    pub fn restore(&self, entries: Vec<(Vec<u8>, Vec<u8>)>) -> Result<()> {
+        for (key, value) in entries {
+            self.tree.insert(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Query all snapshots of the activity, in time order.
+    pub fn query(&self, id: i32) -> Result<Vec<ApplySnapshot>> {
+        self.tree
+            .scan_prefix(id.to_be_bytes())
+            .map(|item| {
+                let (_, value) = item?;
+                Ok(bincode::deserialize::<ApplySnapshot>(&value)?)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ActivityHistoryRequest {
+    /// Activity id in sc.sit.edu.cn
+    pub id: i32,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ActivityHistoryRequest {
+    /// Query apply counts recorded by each activity detail fetch.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let history = data.activity_history.query(self.id)?;
+        Ok(ResponsePayload::ActivityHistory(history))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Local};
+
+    use super::{ActivityHistory, ApplySnapshot};
+
+    #[test]
+    fn test_activity_history() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let history = ActivityHistory::new(db.open_tree(super::HISTORY_TREE).unwrap());
+
+        let first = ApplySnapshot {
+            time: Local::now(),
+            applied: Some(10),
+            capacity: Some(60),
+        };
+        let second = ApplySnapshot {
+            time: first.time + Duration::minutes(5),
+            applied: Some(35),
+            capacity: Some(60),
+        };
+        // Another activity, should not be returned.
+        history.append(1061910, &first).unwrap();
+        history.append(1061909, &second).unwrap();
+        history.append(1061909, &first).unwrap();
+
+        assert_eq!(history.query(1061909).unwrap(), vec![first, second]);
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{self, HeaderMap};
+use reqwest::{Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::net::UserClient;
+use crate::parser::ParserError;
+
+/// Longest time an image is taken as fresh, so that a huge `max-age` does not overflow the instant.
+const MAX_FRESHNESS: Duration = Duration::from_secs(365 * 24 * 3600);
+
+#[derive(Debug, Clone)]
+struct CachedImage {
+    content: Vec<u8>,
+    /// Until when the image can be served without asking the server.
+    fresh_until: Instant,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// When the image is served last, by the tick of the cache.
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Images {
+    map: HashMap<String, CachedImage>,
+    tick: u64,
+}
+
+impl Images {
+    fn get(&mut self, url: &str) -> Option<CachedImage> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.map.get_mut(url).map(|x| {
+            x.last_used = tick;
+            x.clone()
+        })
+    }
+
+    /// Store the image, and evict the least recently used ones beyond `capacity`.
+    fn insert(&mut self, url: &str, mut image: CachedImage, capacity: usize) {
+        self.tick += 1;
+        image.last_used = self.tick;
+        self.map.insert(url.to_string(), image);
+
+        while self.map.len() > capacity {
+            let oldest = self.map.iter().min_by_key(|(_, x)| x.last_used).map(|(k, _)| k.clone());
+            match oldest {
+                Some(url) => self.map.remove(&url),
+                None => break,
+            };
+        }
+    }
+}
+
+/// An image in the cache, as exported for state handoff.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedImage {
+    url: String,
+    content: Vec<u8>,
+    /// Time left before the image turns stale.
+    fresh_for: Duration,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// Images downloaded, indexed by url, following the cache headers of the server. At most `capacity`
+/// images are kept, and the least recently used one is evicted first.
+#[derive(Debug)]
+pub struct ImageCache {
+    capacity: usize,
+    inner: Mutex<Images>,
+}
+
+fn header_of(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|x| x.to_str().ok())
+        .map(ToString::to_string)
+}
+
+/// Return the response if it carries an image, or an error if the server answers with an error status
+/// or a page, like the login page when the session expires in the middle.
+pub(super) fn check_image(response: Response) -> Result<Response> {
+    let response = response.error_for_status()?;
+    let content_type = header_of(response.headers(), header::CONTENT_TYPE).unwrap_or_default();
+    let is_image = content_type.is_empty()
+        || content_type.starts_with("image/")
+        || content_type.starts_with("application/octet-stream");
+
+    if !is_image {
+        return Err(ParserError::UnsupportedFormat(content_type).into());
+    }
+    Ok(response)
+}
+
+/// How long a response stays fresh by `Cache-Control` and `Expires`, up to `MAX_FRESHNESS`, or `None`
+/// if it must not be stored, for `no-store` and `no-cache`.
+fn freshness(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let cache_control = header_of(headers, header::CACHE_CONTROL).unwrap_or_default();
+    let directives: Vec<String> = cache_control
+        .split(',')
+        .map(|x| x.trim().to_lowercase())
+        .collect();
+
+    if directives.iter().any(|x| x == "no-store" || x == "no-cache") {
+        return None;
+    }
+    let max_age = directives
+        .iter()
+        .find_map(|x| x.strip_prefix("max-age="))
+        .and_then(|x| x.parse().ok());
+    if let Some(max_age) = max_age {
+        return Some(Duration::from_secs(max_age).min(MAX_FRESHNESS));
+    }
+    // "Wed, 21 Oct 2020 07:28:00 GMT"
+    let expires = header_of(headers, header::EXPIRES)
+        .and_then(|x| DateTime::parse_from_rfc2822(&x).ok())
+        .map(|x| (x.with_timezone(&Utc) - now).to_std().unwrap_or_default());
+
+    Some(expires.unwrap_or_default().min(MAX_FRESHNESS))
+}
+
+impl ImageCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Images::default()),
+        }
+    }
+
+    /// Download the image, or serve it from cache if still fresh. Stale ones are revalidated with
+    /// `ETag` and `Last-Modified`.
+    pub async fn fetch(&self, client: &mut UserClient, url: &str) -> Result<Vec<u8>> {
+        let cached = self.inner.lock().unwrap().get(url);
+        if let Some(cached) = &cached {
+            if cached.fresh_until > Instant::now() {
+                return Ok(cached.content.clone());
+            }
+        }
+
+        let mut request = client.raw_client.get(url);
+        if let Some(cached) = &cached {
+            if let Some(etag) = &cached.etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let response = client.send(request.build()?).await?;
+        let headers = response.headers().clone();
+        let content = match cached {
+            Some(cached) if response.status() == StatusCode::NOT_MODIFIED => cached.content,
+            _ => check_image(response)?.bytes().await?.to_vec(),
+        };
+
+        match freshness(&headers, Utc::now()) {
+            Some(ttl) => {
+                let image = CachedImage {
+                    content: content.clone(),
+                    fresh_until: Instant::now() + ttl,
+                    etag: header_of(&headers, header::ETAG),
+                    last_modified: header_of(&headers, header::LAST_MODIFIED),
+                    last_used: 0,
+                };
+                self.inner.lock().unwrap().insert(url, image, self.capacity);
+            }
+            None => {
+                self.inner.lock().unwrap().map.remove(url);
+            }
+        }
+        Ok(content)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock().unwrap().map.is_empty()
+    }
+
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().map.clear();
+    }
+
+    /// Export images from the least recently used, for state handoff.
+    pub fn dump(&self) -> Vec<ExportedImage> {
+        let now = Instant::now();
+        let mut images: Vec<(String, CachedImage)> =
+            self.inner.lock().unwrap().map.clone().into_iter().collect();
+        images.sort_by_key(|(_, x)| x.last_used);
+
+        images
+            .into_iter()
+            .map(|(url, x)| ExportedImage {
+                url,
+                content: x.content,
+                fresh_for: x.fresh_until.saturating_duration_since(now),
+                etag: x.etag,
+                last_modified: x.last_modified,
+            })
+            .collect()
+    }
+
+    /// Restore images exported by `dump`, which keeps the least recently used order.
+    pub fn restore(&self, images: Vec<ExportedImage>) {
+        let now = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+        for x in images {
+            let image = CachedImage {
+                content: x.content,
+                fresh_until: now + x.fresh_for,
+                etag: x.etag,
+                last_modified: x.last_modified,
+                last_used: 0,
+            };
+            inner.insert(&x.url, image, self.capacity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+    use reqwest::header::{HeaderMap, HeaderValue};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::{freshness, ImageCache, MAX_FRESHNESS};
+    use crate::net::{Session, UserClient};
+
+    /// Serve a tiny image at any path with the given cache headers, and count requests received.
+    async fn serve_image(listener: TcpListener, headers: &'static str, hits: Arc<AtomicUsize>) {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let hits = hits.clone();
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 4096];
+                let _ = stream.read(&mut buf).await;
+                hits.fetch_add(1, Ordering::SeqCst);
+
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: image/gif\r\nContent-Length: 4\r\n{}Connection: close\r\n\r\nGIF8",
+                    headers
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    }
+
+    async fn fetch_twice(headers: &'static str) -> usize {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/image.gif", listener.local_addr().unwrap());
+        let hits = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(serve_image(listener, headers, hits.clone()));
+
+        let cache = ImageCache::new(16);
+        let mut client =
+            UserClient::new(Session::new("1910000000", "password"), &reqwest::Client::new());
+        for _ in 0..2 {
+            let content = cache.fetch(&mut client, &url).await.unwrap();
+            assert_eq!(content, b"GIF8");
+        }
+        hits.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn test_image_cache() {
+        assert_eq!(fetch_twice("Cache-Control: max-age=86400\r\n").await, 1);
+        assert_eq!(fetch_twice("Cache-Control: no-store\r\n").await, 2);
+        assert_eq!(fetch_twice("Cache-Control: no-cache\r\n").await, 2);
+        // Not overflowing.
+        let max_age = "Cache-Control: max-age=18446744073709551615\r\n";
+        assert_eq!(fetch_twice(max_age).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_image_cache_lru() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let hits = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(serve_image(listener, "Cache-Control: max-age=86400\r\n", hits.clone()));
+
+        let cache = ImageCache::new(2);
+        let mut client =
+            UserClient::new(Session::new("1910000000", "password"), &reqwest::Client::new());
+        // a is used again before c comes, so b is the least recently used and evicted.
+        let expected_hits = [("a", 1), ("b", 2), ("a", 2), ("c", 3), ("a", 3), ("b", 4)];
+        for (name, expected) in expected_hits.iter() {
+            let url = format!("{}/{}.gif", base, name);
+            cache.fetch(&mut client, &url).await.unwrap();
+            assert_eq!(hits.load(Ordering::SeqCst), *expected);
+        }
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_freshness() {
+        let now = Utc.with_ymd_and_hms(2020, 10, 21, 7, 28, 0).unwrap();
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut map = HeaderMap::new();
+            for (k, v) in pairs {
+                map.insert(*k, HeaderValue::from_static(v));
+            }
+            map
+        };
+
+        assert_eq!(
+            freshness(&headers(&[("cache-control", "public, max-age=600")]), now),
+            Some(Duration::from_secs(600))
+        );
+        let max_age = ("cache-control", "max-age=18446744073709551615");
+        assert_eq!(freshness(&headers(&[max_age]), now), Some(MAX_FRESHNESS));
+        assert_eq!(freshness(&headers(&[("cache-control", "no-cache")]), now), None);
+        assert_eq!(freshness(&headers(&[("cache-control", "no-store")]), now), None);
+        assert_eq!(
+            freshness(&headers(&[("expires", "Wed, 21 Oct 2020 08:28:00 GMT")]), now),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(freshness(&headers(&[]), now), Some(Duration::from_secs(0)));
+    }
+}
@@ -0,0 +1,258 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::agent::SharedData;
+use crate::error::Result;
+use crate::net::client::{build_url, default_response_hook, read_text};
+use crate::net::{SessionGuard, UserClient};
+use crate::parser::{
+    get_my_activity_list, ActivityStatus, Parse, ScActivityItem, ScCancelResult, ScJoinResult,
+};
+use crate::service::{ActionError, DoRequest, ResponsePayload, ResponseResult};
+
+use super::{fetch_page, make_sure_active, url, with_active_session};
+
+/// Apply for an activity with an activated client.
+async fn join_activity(client: &mut UserClient, activity_id: i32, force: bool) -> Result<ScJoinResult> {
+    let activity_id = activity_id.to_string();
+
+    // The page checks whether the student can apply, before redirecting to the apply page.
+    if !force {
+        let url = build_url(url::HOME, url::CHECK_USER, &[("activityId", &activity_id)])?;
+        let request = client.raw_client.post(&url).build()?;
+        let text = read_text(client.send(request).await?).await?;
+
+        let result = ScJoinResult::from_html(&text)?;
+        if result != ScJoinResult::Ok {
+            return Ok(result);
+        }
+    }
+    let url = build_url(url::HOME, url::APPLY_ACTIVITY, &[("activityId", &activity_id)])?;
+    let request = client.raw_client.post(&url).build()?;
+    // Expected page content:
+    // <script>alert('申请成功，下面将为您跳转至我的活动页面！');location.href='...'</script>
+    let html = read_text(client.send(request).await?).await?;
+
+    ScJoinResult::from_html(&html)
+}
+
+/// Refuse to join an activity shown full or ended in the list, without asking the upstream.
+fn check_status(status: Option<ActivityStatus>) -> Result<()> {
+    match status {
+        Some(ActivityStatus::Full) => Err(ActionError::ActivityFull.into()),
+        Some(ActivityStatus::Ended) => Err(ActionError::ActivityEnded.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Withdraw from a joined activity with an activated client.
+async fn cancel_activity(client: &mut UserClient, activity_id: i32) -> Result<ScCancelResult> {
+    let activity_id = activity_id.to_string();
+
+    let url = build_url(url::HOME, url::CANCEL_ACTIVITY, &[("activityId", &activity_id)])?;
+    let request = client.raw_client.post(&url).build()?;
+    // Expected page content:
+    // <script>alert('取消成功！');location.href='...'</script>
+    let html = read_text(client.send(request).await?).await?;
+
+    ScCancelResult::from_html(&html)
+}
+
+/// Fetch activities the student joined.
+pub(super) async fn fetch_joined(client: &mut UserClient) -> Result<Vec<ScActivityItem>> {
+    let html = fetch_page(client, url::MY_ACTIVITY).await?;
+
+    get_my_activity_list(&html)
+}
+
+/// Times to check the joined list, which may lag behind the apply.
+const VERIFY_TIMES: u32 = 3;
+/// Delay before checking the joined list again.
+const VERIFY_DELAY: Duration = Duration::from_secs(1);
+
+/// Check whether the activity appears in the joined list. Try again after `VERIFY_DELAY` if not found,
+/// at most `VERIFY_TIMES` times, since the list may lag behind.
+async fn verify_joined(client: &mut UserClient, activity_id: i32) -> Result<bool> {
+    for i in 0..VERIFY_TIMES {
+        if i > 0 {
+            tokio::time::sleep(VERIFY_DELAY).await;
+        }
+        let joined = fetch_joined(client).await?;
+        if joined.iter().any(|x| x.activity_id == activity_id) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Join activities one by one. A failure is returned as the result of that activity, and does not
+/// stop the rest.
+async fn join_each(
+    client: &mut UserClient,
+    activity_ids: &[i32],
+    force: bool,
+) -> Vec<(i32, ScJoinResult)> {
+    let mut results = Vec::with_capacity(activity_ids.len());
+
+    for &id in activity_ids {
+        let result = join_activity(client, id, force)
+            .await
+            .unwrap_or_else(|e| ScJoinResult::Err(e.to_string()));
+        results.push((id, result));
+    }
+    results
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScJoinRequest {
+    pub account: String,
+    pub password: String,
+    pub activity_id: i32,
+    /// Apply without checking the user first.
+    pub force: bool,
+    /// Check the joined list after applying, to confirm the apply landed.
+    pub verify: bool,
+    /// Status of the activity in the list, if the client has it. Full or ended ones are refused
+    /// before applying, unless forced.
+    pub status: Option<ActivityStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScJoinResponse {
+    pub result: ScJoinResult,
+    /// Whether the activity is found in the joined list, or `None` if not verified.
+    pub confirmed: Option<bool>,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ScJoinRequest {
+    async fn process(self, data: SharedData) -> ResponseResult {
+        if !self.force {
+            check_status(self.status)?;
+        }
+        let mut client =
+            SessionGuard::new(&data.session_store, &data.client, &self.account, &self.password).await?;
+        client.set_response_hook(Some(default_response_hook));
+
+        make_sure_active(&mut client).await?;
+        let result = join_activity(&mut client, self.activity_id, self.force).await?;
+
+        let mut confirmed = None;
+        if self.verify && !matches!(result, ScJoinResult::Err(_) | ScJoinResult::Full) {
+            confirmed = Some(verify_joined(&mut client, self.activity_id).await?);
+        }
+
+        client.save()?;
+        Ok(ResponsePayload::ScJoin(ScJoinResponse { result, confirmed }))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScJoinBatchRequest {
+    pub account: String,
+    pub password: String,
+    pub activity_ids: Vec<i32>,
+    /// Apply without checking the user first.
+    pub force: bool,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ScJoinBatchRequest {
+    /// Join activities with one session in order, and return the result of each activity.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let mut client =
+            SessionGuard::new(&data.session_store, &data.client, &self.account, &self.password).await?;
+        client.set_response_hook(Some(default_response_hook));
+
+        make_sure_active(&mut client).await?;
+        let results = join_each(&mut client, &self.activity_ids, self.force).await;
+
+        client.save()?;
+        Ok(ResponsePayload::ScJoinBatch(results))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScCancelRequest {
+    pub account: String,
+    pub password: String,
+    pub activity_id: i32,
+}
+
+/// Withdraw from the activity, and fetch the joined list after that.
+async fn cancel_and_list(client: &mut UserClient, activity_id: i32) -> Result<Vec<ScActivityItem>> {
+    match cancel_activity(client, activity_id).await? {
+        ScCancelResult::Ok => {}
+        ScCancelResult::Started => return Err(ActionError::CancelRefused.into()),
+        ScCancelResult::Err(message) => return Err(ActionError::UpstreamError { message }.into()),
+    }
+    fetch_joined(client).await
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ScCancelRequest {
+    /// Withdraw from the activity, and return the joined list after that.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let id = self.activity_id;
+        let joined = with_active_session(&data, &self.account, &self.password, |client| {
+            Box::pin(cancel_and_list(client, id))
+        })
+        .await?;
+        Ok(ResponsePayload::ScCancel(joined))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_status, join_each};
+    use crate::net::fake::{FakeResponse, FakeUpstream};
+    use crate::parser::{ActivityStatus, ScJoinResult};
+    use crate::service::ActionError;
+
+    #[tokio::test]
+    async fn test_join_each() {
+        // Check results returned by the server, and 1061911 is full.
+        let upstream = FakeUpstream::start(|request| match request.path() {
+            "/public/pcenter/checkUser.action?activityId=1061911" => FakeResponse::ok("4"),
+            path if path.starts_with("/public/pcenter/checkUser.action") => FakeResponse::ok("0"),
+            _ => FakeResponse::ok("<script>alert('申请成功，下面将为您跳转至我的活动页面！');</script>"),
+        })
+        .await;
+        let mut client = upstream.user_client();
+
+        let results = join_each(&mut client, &[1061909, 1061911, 1061910], false).await;
+
+        assert_eq!(
+            results,
+            vec![
+                (1061909, ScJoinResult::Ok),
+                (1061911, ScJoinResult::Full),
+                (1061910, ScJoinResult::Ok),
+            ]
+        );
+        // Not applied for the full one.
+        let applied = upstream
+            .requests()
+            .iter()
+            .filter(|x| x.path().starts_with("/public/pcenter/applyActivity.action"))
+            .count();
+        assert_eq!(applied, 2);
+    }
+
+    #[test]
+    fn test_check_status() {
+        let code = |status| {
+            check_status(status)
+                .err()
+                .and_then(|e| e.downcast::<ActionError>().ok())
+                .map(|e| e.code())
+        };
+
+        assert_eq!(code(None), None);
+        assert_eq!(code(Some(ActivityStatus::Open)), None);
+        assert_eq!(code(Some(ActivityStatus::Unknown)), None);
+        assert_eq!(code(Some(ActivityStatus::Full)), Some(65));
+        assert_eq!(code(Some(ActivityStatus::Ended)), Some(66));
+    }
+}
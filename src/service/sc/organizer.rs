@@ -0,0 +1,96 @@
+use serde::Deserialize;
+
+use crate::agent::SharedData;
+use crate::error::Result;
+use crate::net::client::default_response_hook;
+use crate::net::{SessionGuard, UserClient};
+use crate::parser::ActivityDetail;
+use crate::service::{ActionError, DoRequest, ResponsePayload, ResponseResult};
+
+use super::{fetch_activity_detail, fetch_activity_list, make_sure_active};
+
+#[derive(Debug, Deserialize)]
+pub struct OrganizerActivitiesRequest {
+    /// Current activity, which is excluded from results. Its organizer is used if `organizer` is
+    /// not given.
+    pub id: Option<i32>,
+    /// Organizer name, like "鲁班书院".
+    pub organizer: Option<String>,
+    /// Category to look for activities in.
+    pub category: i32,
+    /// Count of recent activities to check.
+    pub count: u16,
+}
+
+/// Fetch the detail of each candidate except `exclude`, and keep those held by `organizer`.
+/// There is no organizer filter upstream, so each detail page is fetched.
+async fn filter_by_organizer(
+    client: &mut UserClient,
+    candidates: &[i32],
+    exclude: Option<i32>,
+    organizer: &str,
+) -> Result<Vec<ActivityDetail>> {
+    let mut details = Vec::new();
+    for &id in candidates.iter().filter(|&&id| Some(id) != exclude) {
+        details.push(fetch_activity_detail(client, id).await?);
+    }
+    Ok(held_by(details, organizer))
+}
+
+fn held_by(mut details: Vec<ActivityDetail>, organizer: &str) -> Vec<ActivityDetail> {
+    details.retain(|x| x.organizer.as_deref() == Some(organizer));
+    details
+}
+
+#[async_trait::async_trait]
+impl DoRequest for OrganizerActivitiesRequest {
+    /// Find other recent activities held by the same organizer.
+    async fn process(self, mut data: SharedData) -> ResponseResult {
+        let mut client = SessionGuard::random(&mut data.session_store, &data.client)?
+            .ok_or(ActionError::NoSessionAvailable)?;
+        client.set_response_hook(Some(default_response_hook));
+
+        make_sure_active(&mut client).await?;
+
+        let organizer = match (self.organizer, self.id) {
+            (Some(organizer), _) => organizer,
+            (None, Some(id)) => fetch_activity_detail(&mut client, id)
+                .await?
+                .organizer
+                .ok_or(ActionError::BadParameter)?,
+            (None, None) => return Err(ActionError::BadParameter.into()),
+        };
+        let candidates: Vec<i32> = fetch_activity_list(&mut client, 1, self.count, self.category)
+            .await?
+            .into_iter()
+            .map(|x| x.id)
+            .collect();
+        let activities = filter_by_organizer(&mut client, &candidates, self.id, &organizer).await?;
+        client.save()?;
+
+        Ok(ResponsePayload::OrganizerActivities(activities))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::held_by;
+    use crate::parser::{ActivityDetail, Parse};
+
+    #[test]
+    fn test_filter_by_organizer() {
+        let pages = [
+            "html/第二课堂详情页面_同主办方.html",
+            "html/第二课堂详情页面.html",
+        ];
+        let details: Vec<ActivityDetail> = pages
+            .iter()
+            .map(|path| ActivityDetail::from_html(&std::fs::read_to_string(path).unwrap()).unwrap())
+            .collect();
+
+        let activities = held_by(details, "鲁班书院");
+        assert_eq!(activities.len(), 1);
+        assert_eq!(activities[0].id, 1062210);
+        assert_eq!(activities[0].title, "鲁班书院第二届书法大赛");
+    }
+}
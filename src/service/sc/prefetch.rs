@@ -0,0 +1,121 @@
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::agent::SharedData;
+use crate::error::Result;
+use crate::net::client::default_response_hook;
+use crate::net::{SessionGuard, UserClient};
+use crate::parser::{ActivityDetail, ScActivityItem};
+use crate::service::{DoRequest, ResponsePayload, ResponseResult};
+
+use super::join::fetch_joined;
+use super::{fetch_activity_detail, make_sure_active};
+
+/// Detail pages fetched at once.
+const PREFETCH_CONCURRENCY: usize = 4;
+
+#[derive(Debug, Deserialize)]
+pub struct JoinedDetailsRequest {
+    pub account: String,
+    pub password: String,
+}
+
+/// A joined activity, with its detail or why the detail is not fetched.
+#[derive(Debug, Serialize)]
+pub struct JoinedActivityDetail {
+    pub item: ScActivityItem,
+    pub detail: Option<ActivityDetail>,
+    pub error: Option<String>,
+}
+
+impl JoinedActivityDetail {
+    fn new(item: ScActivityItem, detail: Result<ActivityDetail>) -> Self {
+        match detail {
+            Ok(detail) => Self {
+                item,
+                detail: Some(detail),
+                error: None,
+            },
+            Err(e) => Self {
+                item,
+                detail: None,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+/// Fetch the detail of each joined activity, at most `concurrency` at once, in the order of `joined`.
+/// Each detail page is fetched by a fork of the client, and cookies set on forks are merged back.
+/// A failure is kept in the entry of that activity, and does not stop the rest.
+async fn fetch_details(
+    client: &mut UserClient,
+    joined: Vec<ScActivityItem>,
+    concurrency: usize,
+) -> Vec<JoinedActivityDetail> {
+    let base = &*client;
+    let fetched: Vec<(JoinedActivityDetail, UserClient)> = stream::iter(joined)
+        .map(|item| {
+            let mut fork = base.fork();
+            async move {
+                let detail = fetch_activity_detail(&mut fork, item.activity_id).await;
+                (JoinedActivityDetail::new(item, detail), fork)
+            }
+        })
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    let mut activities = Vec::new();
+    for (activity, fork) in fetched {
+        client.merge(fork);
+        activities.push(activity);
+    }
+    activities
+}
+
+#[async_trait::async_trait]
+impl DoRequest for JoinedDetailsRequest {
+    /// Return activities the student joined with their details, for the timeline.
+    async fn process(self, data: SharedData) -> ResponseResult {
+        let mut client =
+            SessionGuard::new(&data.session_store, &data.client, &self.account, &self.password).await?;
+        client.set_response_hook(Some(default_response_hook));
+
+        make_sure_active(&mut client).await?;
+        let joined = fetch_joined(&mut client).await?;
+        let activities = fetch_details(&mut client, joined, PREFETCH_CONCURRENCY).await;
+        client.save()?;
+
+        for activity in activities.iter().filter_map(|x| x.detail.as_ref()) {
+            data.activity_history.record(activity)?;
+        }
+        Ok(ResponsePayload::JoinedDetails(activities))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::JoinedActivityDetail;
+    use crate::parser::{get_my_activity_list, ActivityDetail, Parse};
+
+    #[test]
+    fn test_joined_detail() {
+        let html_page = std::fs::read_to_string("html/第二课堂我的活动页面_三个活动.html").unwrap();
+        let mut joined = get_my_activity_list(&html_page).unwrap().into_iter();
+
+        let html_page = std::fs::read_to_string("html/第二课堂详情页面2.html").unwrap();
+        let detail = ActivityDetail::from_html(&html_page);
+        let activity = JoinedActivityDetail::new(joined.next().unwrap(), detail);
+        assert_eq!(activity.item.activity_id, 1061909);
+        assert_eq!(activity.detail.as_ref().map(|x| x.id), Some(1061909));
+        assert!(activity.error.is_none());
+
+        // A failure is kept in the entry.
+        let detail = Err(anyhow::anyhow!("活动不存在"));
+        let activity = JoinedActivityDetail::new(joined.next().unwrap(), detail);
+        assert_eq!(activity.item.activity_id, 1062210);
+        assert!(activity.detail.is_none());
+        assert_eq!(activity.error.as_deref(), Some("活动不存在"));
+    }
+}
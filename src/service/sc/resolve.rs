@@ -0,0 +1,152 @@
+use reqwest::header::LOCATION;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::agent::SharedData;
+use crate::error::Result;
+use crate::net::client::default_response_hook;
+use crate::net::SessionGuard;
+use crate::parser::ActivityDetail;
+use crate::service::{ActionError, DoRequest, ResponsePayload, ResponseResult};
+
+use super::{fetch_activity_detail, url};
+
+/// Where short codes on posters lead, like "http://t.sit.edu.cn/aB3x".
+const SHORT_LINK_BASE: &str = "http://t.sit.edu.cn/";
+/// Max redirects to follow, in case of a loop.
+const MAX_REDIRECTS: usize = 5;
+
+#[derive(Debug, Deserialize)]
+pub struct ResolveActivityRequest {
+    /// Short link, short code or activity detail link shared on posters.
+    pub link: String,
+    /// Fetch the activity detail too.
+    pub with_detail: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResolvedActivity {
+    pub id: i32,
+    pub detail: Option<Box<ActivityDetail>>,
+}
+
+/// Activity id of a detail link, like ".../activityDetail.action?activityId=1062079".
+fn activity_id_of(link: &Url) -> Option<i32> {
+    if !link.path().ends_with(url::ACTIVITY_DETAIL) {
+        return None;
+    }
+    link.query_pairs()
+        .find(|(k, _)| k == "activityId")
+        .and_then(|(_, v)| v.parse().ok())
+}
+
+/// Follow redirects of the link until an activity detail link, and return the activity id. A bare
+/// number is taken as the id itself, and other codes are looked up as short links.
+async fn resolve_link(client: &reqwest::Client, link: &str) -> Result<i32> {
+    let link = link.trim();
+    if let Ok(id) = link.parse::<i32>() {
+        return Ok(id);
+    }
+    let mut current = if link.contains("://") {
+        Url::parse(link)
+    } else {
+        Url::parse(SHORT_LINK_BASE)?.join(link)
+    }
+    .map_err(|_| ActionError::InvalidLink)?;
+
+    for _ in 0..=MAX_REDIRECTS {
+        if let Some(id) = activity_id_of(&current) {
+            return Ok(id);
+        }
+        let response = client.get(current.clone()).send().await?;
+        // Expired links show a page rather than redirect.
+        if !response.status().is_redirection() {
+            break;
+        }
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|x| x.to_str().ok())
+            .ok_or(ActionError::InvalidLink)?;
+        current = current.join(location).map_err(|_| ActionError::InvalidLink)?;
+    }
+    Err(ActionError::InvalidLink.into())
+}
+
+#[async_trait::async_trait]
+impl DoRequest for ResolveActivityRequest {
+    /// Resolve a shared link to the activity id, and fetch the detail if requested.
+    async fn process(self, mut data: SharedData) -> ResponseResult {
+        let id = resolve_link(&data.client, &self.link).await?;
+
+        let detail = if self.with_detail {
+            let mut client = SessionGuard::random(&mut data.session_store, &data.client)?
+                .ok_or(ActionError::NoSessionAvailable)?;
+            client.set_response_hook(Some(default_response_hook));
+            let activity = fetch_activity_detail(&mut client, id).await?;
+            client.save()?;
+            data.activity_history.record(&activity)?;
+            Some(Box::new(activity))
+        } else {
+            None
+        };
+        Ok(ResponsePayload::ResolveActivity(ResolvedActivity { id, detail }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::resolve_link;
+    use crate::service::ActionError;
+
+    /// Redirect short links, and show a page for expired ones.
+    async fn serve_short_links(listener: TcpListener) {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 4096];
+                let n = stream.read(&mut buf).await.unwrap_or_default();
+                let request = String::from_utf8_lossy(&buf[..n]);
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+
+                let response = match path {
+                    "/aB3x" => {
+                        "HTTP/1.1 302 Found\r\nLocation: http://sc.sit.edu.cn/public/activity/\
+                        activityDetail.action?activityId=1062079\r\nContent-Length: 0\r\n\r\n"
+                    }
+                    // A relative redirect, to another short link.
+                    "/old" => {
+                        "HTTP/1.1 301 Moved Permanently\r\nLocation: /aB3x\r\nContent-Length: 0\r\n\r\n"
+                    }
+                    _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n",
+                };
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_link() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve_short_links(listener));
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+
+        let id = resolve_link(&client, &format!("{}/aB3x", base)).await.unwrap();
+        assert_eq!(id, 1062079);
+        let id = resolve_link(&client, &format!("{}/old", base)).await.unwrap();
+        assert_eq!(id, 1062079);
+        assert_eq!(resolve_link(&client, " 1062079 ").await.unwrap(), 1062079);
+
+        let e = resolve_link(&client, &format!("{}/expired", base))
+            .await
+            .unwrap_err();
+        assert!(matches!(e.downcast_ref(), Some(ActionError::InvalidLink)));
+    }
+}
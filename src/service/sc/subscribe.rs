@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::agent::SharedData;
+use crate::error::Result;
+use crate::net::client::default_response_hook;
+use crate::net::{SessionGuard, UserClient};
+use crate::parser::ActivityBrief;
+use crate::service::{ActionError, DoRequest, ResponsePayload, ResponseResult};
+
+use super::{fetch_activity_brief, make_sure_active};
+
+/// Apply state of an activity, judged by the apply button.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum ActivityState {
+    Open,
+    Full,
+    Closed,
+}
+
+impl From<&ActivityBrief> for ActivityState {
+    fn from(brief: &ActivityBrief) -> Self {
+        match brief.status.as_deref() {
+            Some("我要申请") => ActivityState::Open,
+            Some(status) if status.contains("已满") => ActivityState::Full,
+            _ => ActivityState::Closed,
+        }
+    }
+}
+
+/// An activity changed its state.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ActivityEvent {
+    pub id: i32,
+    pub state: ActivityState,
+}
+
+#[derive(Debug, Default)]
+struct Subscription {
+    /// Last known state of each activity.
+    states: HashMap<i32, ActivityState>,
+    cancel: Arc<Notify>,
+}
+
+/// Subscriptions of the host, indexed by the id the host gives.
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    inner: Mutex<HashMap<u64, Subscription>>,
+}
+
+impl Subscriptions {
+    /// Take the last known states out to poll, and create the subscription if not exists.
+    fn take(&self, key: u64) -> (HashMap<i32, ActivityState>, Arc<Notify>) {
+        let mut inner = self.inner.lock().unwrap();
+        let subscription = inner.entry(key).or_default();
+
+        (
+            std::mem::take(&mut subscription.states),
+            subscription.cancel.clone(),
+        )
+    }
+
+    /// Put the states back after polling, unless the subscription is canceled.
+    fn put_back(&self, key: u64, states: HashMap<i32, ActivityState>) {
+        if let Some(subscription) = self.inner.lock().unwrap().get_mut(&key) {
+            subscription.states = states;
+        }
+    }
+
+    /// Remove the subscription, and wake up its polling request. Return false if not exists.
+    pub fn cancel(&self, key: u64) -> bool {
+        match self.inner.lock().unwrap().remove(&key) {
+            Some(subscription) => {
+                subscription.cancel.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Poll activities until any of them changes its state, or the subscription is canceled. States
+/// seen at the first time are recorded without an event.
+async fn watch_activities(
+    client: &mut UserClient,
+    ids: &[i32],
+    states: &mut HashMap<i32, ActivityState>,
+    interval: Duration,
+    cancel: &Notify,
+) -> Result<Vec<ActivityEvent>> {
+    loop {
+        let mut events = Vec::new();
+        for &id in ids {
+            let state = ActivityState::from(&fetch_activity_brief(client, id).await?);
+            match states.insert(id, state) {
+                Some(last) if last != state => events.push(ActivityEvent { id, state }),
+                _ => {}
+            }
+        }
+        if !events.is_empty() {
+            return Ok(events);
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = cancel.notified() => return Ok(Vec::new()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubscribeActivityRequest {
+    /// Subscription id, chosen by the host.
+    pub subscription: u64,
+    /// Activity ids to watch.
+    pub ids: Vec<i32>,
+    /// Poll interval in seconds.
+    pub poll_interval: u64,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for SubscribeActivityRequest {
+    /// Long poll: return once any activity opens, closes or fills up, and the host sends the request
+    /// again for the next events. An empty list is returned if the subscription is canceled.
+    async fn process(self, mut data: SharedData) -> ResponseResult {
+        let mut client = SessionGuard::random(&mut data.session_store, &data.client)?
+            .ok_or(ActionError::NoSessionAvailable)?;
+        client.set_response_hook(Some(default_response_hook));
+
+        make_sure_active(&mut client).await?;
+
+        let (mut states, cancel) = data.subscriptions.take(self.subscription);
+        let interval = Duration::from_secs(self.poll_interval.max(1));
+        let events = watch_activities(&mut client, &self.ids, &mut states, interval, &cancel).await;
+        data.subscriptions.put_back(self.subscription, states);
+        client.save()?;
+
+        Ok(ResponsePayload::ActivityEvents(events?))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnsubscribeActivityRequest {
+    pub subscription: u64,
+}
+
+#[async_trait::async_trait]
+impl DoRequest for UnsubscribeActivityRequest {
+    async fn process(self, data: SharedData) -> ResponseResult {
+        data.subscriptions.cancel(self.subscription);
+        Ok(ResponsePayload::None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use tokio::sync::Notify;
+
+    use super::{watch_activities, ActivityEvent, ActivityState, Subscriptions};
+    use crate::net::fake::{FakeResponse, FakeUpstream};
+
+    fn not_found_page() -> String {
+        std::fs::read_to_string("html/第二课堂错误页面.html").unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_watch_activities() {
+        // The activity is not found on the first two polls, and open since the third.
+        let detail_page = std::fs::read_to_string("html/第二课堂详情页面2.html").unwrap();
+        let not_found_page = not_found_page();
+        let mut polls = 0;
+        let upstream = FakeUpstream::start(move |_| {
+            polls += 1;
+            let page = if polls < 3 { &not_found_page } else { &detail_page };
+            FakeResponse::ok(page.as_str())
+        })
+        .await;
+        let mut client = upstream.user_client();
+        let mut states = HashMap::new();
+
+        let interval = Duration::from_millis(10);
+        let events = watch_activities(&mut client, &[1061909], &mut states, interval, &Notify::new())
+            .await
+            .unwrap();
+
+        assert_eq!(upstream.requests().len(), 3);
+        assert_eq!(
+            events,
+            vec![ActivityEvent {
+                id: 1061909,
+                state: ActivityState::Open
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_subscription() {
+        let subscriptions = Subscriptions::default();
+        let (mut states, cancel) = subscriptions.take(7);
+        assert!(subscriptions.cancel(7));
+
+        let upstream = FakeUpstream::start(|_| FakeResponse::ok(not_found_page())).await;
+        let mut client = upstream.user_client();
+
+        let interval = Duration::from_secs(60);
+        let events = watch_activities(&mut client, &[1061909], &mut states, interval, &cancel)
+            .await
+            .unwrap();
+
+        assert!(events.is_empty());
+        assert!(!subscriptions.cancel(7));
+    }
+}